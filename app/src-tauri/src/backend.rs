@@ -0,0 +1,80 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::downloader::{DownloadInfo, DownloadManager, HttpBackend};
+use crate::local_source::LocalBackend;
+
+/// The download a backend is running, and how it reports progress
+pub struct DownloadCtx<'a> {
+    pub manager: &'a DownloadManager,
+    pub id: &'a str,
+}
+
+impl DownloadCtx<'_> {
+    /// Latest stored state of the download
+    pub async fn info(&self) -> Result<DownloadInfo> {
+        self.manager
+            .get_download_info(self.id)
+            .await
+            .context("Download not found")
+    }
+
+    /// Persist the download's progress and send it to the frontend
+    pub async fn report(&self, info: &DownloadInfo) -> Result<()> {
+        self.manager.report_progress(info).await
+    }
+}
+
+/// Tells a running backend that a pause or cancel was requested
+#[derive(Clone)]
+pub struct ControlHandle {
+    stop: Arc<AtomicBool>,
+}
+
+impl ControlHandle {
+    pub fn new(stop: Arc<AtomicBool>) -> Self {
+        Self { stop }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    /// The underlying flag, for transfer code that polls it directly
+    pub fn flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+}
+
+/// A transfer protocol, picked by URL scheme. Pause/resume/cancel,
+/// persistence and completion stay in the shared download task.
+#[async_trait]
+pub trait DownloadBackend: Send + Sync {
+    /// Transfer the download to its target path. Returning while `control`
+    /// is stopped means the transfer was interrupted; otherwise the
+    /// content is complete.
+    async fn download(&self, ctx: &DownloadCtx<'_>, control: ControlHandle) -> Result<()>;
+
+    /// Name to save `url` under when neither the user nor the URL's path
+    /// provides one
+    fn file_name(&self, _url: &str) -> Option<String> {
+        None
+    }
+}
+
+/// The backend that handles `url`'s scheme
+pub fn for_url(url: &str) -> Result<Box<dyn DownloadBackend>> {
+    #[cfg(feature = "torrent")]
+    if crate::torrent::handles(url) {
+        return Ok(Box::new(crate::torrent::TorrentBackend));
+    }
+
+    let scheme = url.split_once(':').map(|(scheme, _)| scheme.to_ascii_lowercase());
+    match scheme.as_deref() {
+        Some("http" | "https") => Ok(Box::new(HttpBackend)),
+        Some("data" | "file") => Ok(Box::new(LocalBackend)),
+        _ => anyhow::bail!("Unsupported URL: {}", url),
+    }
+}
@@ -1,89 +1,102 @@
 // Separate binary for Native Messaging Host
-// This runs as a standalone process when invoked by Firefox
+// This runs as a standalone process when invoked by the browser (Firefox,
+// Chrome, Chromium or Edge) and forwards each request to the running GripDL
+// app over its localhost control server.
+//
+// `gripdl-native-messaging --install-manifest <browser> [--extension-id <id>]...`
+// registers this binary with a browser instead.
 
-use anyhow::Result;
-use serde::{Deserialize, Serialize};
-use std::io::{self, BufRead, BufReader, Write};
-
-#[derive(Debug, Deserialize)]
-struct NativeMessage {
-    url: String,
-    cookies: Option<String>,
-    referrer: Option<String>,
-    user_agent: Option<String>,
-}
-
-#[derive(Debug, Serialize)]
-struct NativeResponse {
-    success: bool,
-    message: Option<String>,
-}
-
-fn send_response(
-    stdout: &mut io::Stdout,
-    success: bool,
-    message: Option<String>,
-) -> Result<()> {
-    let response = NativeResponse { success, message };
-    let json = serde_json::to_string(&response)?;
-    let length = json.len() as u32;
-
-    stdout.write_all(&length.to_le_bytes())?;
-    stdout.write_all(json.as_bytes())?;
-    stdout.flush()?;
-
-    Ok(())
-}
+use anyhow::{bail, Context, Result};
+use gripdl::browser_manifest::{self, Browser};
+use gripdl::native_messaging::{
+    forward_to_app, read_message, send_response, NativeMessage, NativeResponse,
+};
+use std::io;
 
 fn main() -> Result<()> {
+    // stdout carries the native messaging protocol, so logs go to stderr
     tracing_subscriber::fmt()
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(io::stderr)
         .init();
 
-    let stdin = io::stdin();
-    let mut reader = BufReader::new(stdin.lock());
-    let mut stdout = io::stdout();
-
-    loop {
-        // Read message length (4 bytes, little-endian)
-        let mut length_bytes = [0u8; 4];
-        if reader.read_exact(&mut length_bytes).is_err() {
-            break; // EOF or error
-        }
-        let length = u32::from_le_bytes(length_bytes) as usize;
-
-        if length == 0 {
-            continue;
-        }
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--install-manifest") {
+        return install_manifest(&args[1..]);
+    }
 
-        // Read message content
-        let mut buffer = vec![0u8; length];
-        if reader.read_exact(&mut buffer).is_err() {
-            break;
-        }
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout();
 
+    while let Some(buffer) = read_message(&mut stdin)? {
         // Parse message
         let message: NativeMessage = match serde_json::from_slice(&buffer) {
             Ok(msg) => msg,
             Err(e) => {
-                eprintln!("Failed to parse message: {}", e);
-                send_response(&mut stdout, false, Some("Invalid message format".to_string()))?;
+                tracing::error!("Failed to parse message: {}", e);
+                let response = NativeResponse {
+                    message: Some("Invalid message format".to_string()),
+                    ..Default::default()
+                };
+                send_response(&mut stdout, &response)?;
                 continue;
             }
         };
 
-        // In production, this should communicate with the main GripDL app via:
-        // - Unix domain socket
-        // - HTTP localhost server
-        // - Named pipe
-        // For now, we'll just acknowledge receipt
-        // The main app should be listening for these requests
-        
-        tracing::info!("Received download request: {}", message.url);
-        
-        send_response(&mut stdout, true, None)?;
+        let urls = message.targets.urls();
+        tracing::info!("Received {} download request(s)", urls.len());
+
+        let response = match forward_to_app(&message) {
+            Ok(results) => {
+                let mut response = NativeResponse::default();
+                let mut errors = Vec::new();
+                for (url, result) in urls.iter().zip(results) {
+                    match result {
+                        Ok(id) => response.ids.push(id),
+                        Err(e) => {
+                            tracing::error!("Failed to forward {}: {:#}", url, e);
+                            errors.push(format!("{}: {:#}", url, e));
+                        }
+                    }
+                }
+                response.success = errors.is_empty();
+                response.message = (!errors.is_empty()).then(|| errors.join("; "));
+                response
+            }
+            Err(e) => {
+                tracing::error!("Failed to forward downloads: {:#}", e);
+                NativeResponse {
+                    message: Some(format!("{:#}", e)),
+                    ..Default::default()
+                }
+            }
+        };
+        send_response(&mut stdout, &response)?;
     }
 
     Ok(())
 }
 
+fn install_manifest(args: &[String]) -> Result<()> {
+    let browser: Browser = args
+        .first()
+        .context("Usage: --install-manifest <firefox|chrome|chromium|edge> [--extension-id <id>]...")?
+        .parse()?;
+
+    let mut extension_ids = Vec::new();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--extension-id" => {
+                extension_ids.push(rest.next().context("--extension-id needs a value")?.clone())
+            }
+            other => bail!("Unexpected argument '{}'", other),
+        }
+    }
+
+    let host_path = std::env::current_exe().context("Failed to locate this executable")?;
+    let manifest_path = browser_manifest::install(browser, &host_path, &extension_ids)?;
+    println!("Native messaging host registered at: {}", manifest_path.display());
+    println!("Restart the browser for changes to take effect.");
+    Ok(())
+}
@@ -0,0 +1,167 @@
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+/// Native messaging host name - must match `NATIVE_HOST` in the extension
+pub const HOST_NAME: &str = "com.gripdl.app";
+
+/// Gecko id from the extension's manifest.json
+pub const FIREFOX_EXTENSION_ID: &str = "gripdl@example.com";
+
+const DESCRIPTION: &str = "GripDL Native Messaging Host";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Browser {
+    Firefox,
+    Chrome,
+    Chromium,
+    Edge,
+}
+
+impl std::str::FromStr for Browser {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "firefox" => Ok(Browser::Firefox),
+            "chrome" => Ok(Browser::Chrome),
+            "chromium" => Ok(Browser::Chromium),
+            "edge" => Ok(Browser::Edge),
+            other => bail!(
+                "Unknown browser '{}' (expected firefox, chrome, chromium or edge)",
+                other
+            ),
+        }
+    }
+}
+
+impl Browser {
+    /// Manifest contents. Firefox allow-lists extension ids, Chromium-based
+    /// browsers allow-list `chrome-extension://<id>/` origins.
+    fn manifest(self, host_path: &Path, extension_ids: &[String]) -> Result<serde_json::Value> {
+        let path = host_path.to_string_lossy();
+        Ok(match self {
+            Browser::Firefox => {
+                let ids: Vec<&str> = if extension_ids.is_empty() {
+                    vec![FIREFOX_EXTENSION_ID]
+                } else {
+                    extension_ids.iter().map(String::as_str).collect()
+                };
+                json!({
+                    "name": HOST_NAME,
+                    "description": DESCRIPTION,
+                    "path": path,
+                    "type": "stdio",
+                    "allowed_extensions": ids,
+                })
+            }
+            _ => {
+                if extension_ids.is_empty() {
+                    bail!("--extension-id is required for Chromium-based browsers");
+                }
+                let origins: Vec<String> = extension_ids
+                    .iter()
+                    .map(|id| format!("chrome-extension://{}/", id))
+                    .collect();
+                json!({
+                    "name": HOST_NAME,
+                    "description": DESCRIPTION,
+                    "path": path,
+                    "type": "stdio",
+                    "allowed_origins": origins,
+                })
+            }
+        })
+    }
+
+    /// Directory the browser scans for host manifests
+    #[cfg(target_os = "macos")]
+    fn manifest_dir(self) -> Option<PathBuf> {
+        let support = dirs::home_dir()?.join("Library/Application Support");
+        Some(match self {
+            Browser::Firefox => support.join("Mozilla/NativeMessagingHosts"),
+            Browser::Chrome => support.join("Google/Chrome/NativeMessagingHosts"),
+            Browser::Chromium => support.join("Chromium/NativeMessagingHosts"),
+            Browser::Edge => support.join("Microsoft Edge/NativeMessagingHosts"),
+        })
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn manifest_dir(self) -> Option<PathBuf> {
+        let home = dirs::home_dir()?;
+        let config = dirs::config_dir()?;
+        Some(match self {
+            Browser::Firefox => home.join(".mozilla/native-messaging-hosts"),
+            Browser::Chrome => config.join("google-chrome/NativeMessagingHosts"),
+            Browser::Chromium => config.join("chromium/NativeMessagingHosts"),
+            Browser::Edge => config.join("microsoft-edge/NativeMessagingHosts"),
+        })
+    }
+
+    /// Windows finds manifests through the registry, so they can live
+    /// anywhere; keep them next to the app's data
+    #[cfg(windows)]
+    fn manifest_dir(self) -> Option<PathBuf> {
+        Some(
+            dirs::data_local_dir()?
+                .join(HOST_NAME)
+                .join("NativeMessagingHosts")
+                .join(self.key()),
+        )
+    }
+
+    #[cfg(windows)]
+    fn key(self) -> &'static str {
+        match self {
+            Browser::Firefox => "firefox",
+            Browser::Chrome => "chrome",
+            Browser::Chromium => "chromium",
+            Browser::Edge => "edge",
+        }
+    }
+
+    #[cfg(windows)]
+    fn registry_key(self) -> String {
+        let vendor = match self {
+            Browser::Firefox => r"Mozilla",
+            Browser::Chrome => r"Google\Chrome",
+            Browser::Chromium => r"Chromium",
+            Browser::Edge => r"Microsoft\Edge",
+        };
+        format!(r"HKCU\Software\{}\NativeMessagingHosts\{}", vendor, HOST_NAME)
+    }
+}
+
+/// Register `host_path` as GripDL's native messaging host with `browser`.
+/// Returns where the manifest was written.
+pub fn install(browser: Browser, host_path: &Path, extension_ids: &[String]) -> Result<PathBuf> {
+    let manifest = browser.manifest(host_path, extension_ids)?;
+    let dir = browser
+        .manifest_dir()
+        .context("Could not determine the manifest directory")?;
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let manifest_path = dir.join(format!("{}.json", HOST_NAME));
+    std::fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)
+        .with_context(|| format!("Failed to write {}", manifest_path.display()))?;
+
+    #[cfg(windows)]
+    register(browser, &manifest_path)?;
+
+    Ok(manifest_path)
+}
+
+#[cfg(windows)]
+fn register(browser: Browser, manifest_path: &Path) -> Result<()> {
+    let status = std::process::Command::new("reg")
+        .args(["add", &browser.registry_key(), "/ve", "/t", "REG_SZ", "/d"])
+        .arg(manifest_path)
+        .arg("/f")
+        .status()
+        .context("Failed to run reg.exe")?;
+    if !status.success() {
+        bail!("reg.exe failed to register the manifest ({})", status);
+    }
+    Ok(())
+}
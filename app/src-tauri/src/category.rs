@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Longest category name or tag accepted
+const MAX_LABEL_LEN: usize = 64;
+
+/// Files downloads served with a matching Content-Type under `category`
+/// and saves them in `dir`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CategoryRule {
+    /// MIME type like `application/zip`, or a wildcard like `video/*`
+    pub content_type: String,
+    pub category: String,
+    /// May start with `~/` for the home directory
+    pub dir: PathBuf,
+}
+
+impl CategoryRule {
+    /// Check the rule and expand `~` in its directory
+    pub fn normalize(mut self) -> Result<Self> {
+        self.content_type = self.content_type.trim().to_ascii_lowercase();
+        if !self.content_type.contains('/') {
+            anyhow::bail!("{:?} is not a MIME type", self.content_type);
+        }
+        self.category = normalize_label(&self.category)?.context("Empty category name")?;
+
+        if let Ok(rest) = self.dir.strip_prefix("~") {
+            self.dir = dirs::home_dir()
+                .context("Cannot find the home directory")?
+                .join(rest);
+        }
+        if !self.dir.is_absolute() {
+            anyhow::bail!("{} is not an absolute path", self.dir.display());
+        }
+        Ok(self)
+    }
+
+    fn matches(&self, content_type: &str) -> bool {
+        match self.content_type.strip_suffix("/*") {
+            Some(top) => content_type.split_once('/').is_some_and(|(t, _)| t == top),
+            None => self.content_type == content_type,
+        }
+    }
+}
+
+/// The first rule matching a Content-Type header value
+pub fn rule_for<'a>(rules: &'a [CategoryRule], content_type: &str) -> Option<&'a CategoryRule> {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    rules.iter().find(|rule| rule.matches(&essence))
+}
+
+/// Category for a file the user didn't categorize, by its extension
+pub fn for_file_name(file_name: &str) -> Option<&'static str> {
+    let (_, ext) = file_name.rsplit_once('.')?;
+    Some(match ext.to_ascii_lowercase().as_str() {
+        "mp4" | "mkv" | "webm" | "mov" | "avi" | "wmv" | "flv" | "m4v" | "ts" => "video",
+        "mp3" | "flac" | "wav" | "ogg" | "opus" | "m4a" | "aac" | "wma" => "audio",
+        "zip" | "rar" | "7z" | "tar" | "gz" | "tgz" | "bz2" | "xz" | "zst" | "iso" | "dmg" => {
+            "archive"
+        }
+        "pdf" | "doc" | "docx" | "odt" | "rtf" | "txt" | "md" | "epub" | "xls" | "xlsx" | "ods"
+        | "csv" | "ppt" | "pptx" | "odp" => "document",
+        _ => return None,
+    })
+}
+
+/// Trim a category name or tag; empty ones are dropped. Tags are stored
+/// newline-separated when read back, so control characters are rejected.
+pub fn normalize_label(label: &str) -> Result<Option<String>> {
+    let label = label.trim();
+    if label.is_empty() {
+        return Ok(None);
+    }
+    if label.chars().any(char::is_control) {
+        anyhow::bail!("{:?} contains control characters", label);
+    }
+    if label.chars().count() > MAX_LABEL_LEN {
+        anyhow::bail!("{:?} is longer than {} characters", label, MAX_LABEL_LEN);
+    }
+    Ok(Some(label.to_string()))
+}
+
+/// Normalized tags, without duplicates, in the order given
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        if let Some(tag) = normalize_label(tag)? {
+            if !normalized.contains(&tag) {
+                normalized.push(tag);
+            }
+        }
+    }
+    Ok(normalized)
+}
@@ -0,0 +1,124 @@
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+pub const DEFAULT_MAX_CONNECTIONS: usize = 64;
+pub const DEFAULT_MAX_CONNECTIONS_PER_HOST: usize = 8;
+
+/// Caps the connections open at once across all downloads, overall and
+/// to any single host, so a heavily segmented download doesn't get us
+/// throttled or banned. Segments beyond a cap wait for a connection to
+/// close.
+pub struct ConnectionLimiter {
+    state: Mutex<State>,
+    released: Notify,
+}
+
+struct State {
+    max_total: usize,
+    max_per_host: usize,
+    total: usize,
+    per_host: HashMap<String, usize>,
+    /// Lowered limits for hosts that asked us to back off
+    host_caps: HashMap<String, usize>,
+}
+
+/// A connection slot, given back when dropped
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    host: String,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_total: usize, max_per_host: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                max_total,
+                max_per_host,
+                total: 0,
+                per_host: HashMap::new(),
+                host_caps: HashMap::new(),
+            }),
+            released: Notify::new(),
+        }
+    }
+
+    /// Wait until a connection to `host` fits under both limits
+    pub async fn acquire(self: &Arc<Self>, host: &str) -> ConnectionPermit {
+        loop {
+            let released = self.released.notified();
+            if let Some(permit) = self.try_acquire(host) {
+                return permit;
+            }
+            released.await;
+        }
+    }
+
+    fn try_acquire(self: &Arc<Self>, host: &str) -> Option<ConnectionPermit> {
+        let mut state = self.state.lock();
+        let open = state.per_host.get(host).copied().unwrap_or(0);
+        let max_open = state
+            .host_caps
+            .get(host)
+            .map_or(state.max_per_host, |&cap| cap.min(state.max_per_host));
+        if state.total >= state.max_total || open >= max_open {
+            return None;
+        }
+        state.total += 1;
+        state.per_host.insert(host.to_string(), open + 1);
+        Some(ConnectionPermit {
+            limiter: Arc::clone(self),
+            host: host.to_string(),
+        })
+    }
+
+    /// Allow one connection fewer to a host than are open to it now, for
+    /// the rest of the session. Never goes below one.
+    pub fn throttle(&self, host: &str) {
+        let mut state = self.state.lock();
+        let open = state.per_host.get(host).copied().unwrap_or(0);
+        let cap = open.saturating_sub(1).max(1);
+        let current = state.host_caps.entry(host.to_string()).or_insert(cap);
+        if cap < *current {
+            *current = cap;
+        }
+        tracing::info!("Limiting connections to {} to {}", host, *current);
+    }
+
+    /// The overall and per-host limits
+    pub fn limits(&self) -> (usize, usize) {
+        let state = self.state.lock();
+        (state.max_total, state.max_per_host)
+    }
+
+    /// Change the limits at runtime. Lowering them doesn't close open
+    /// connections; new ones wait until the count has dropped.
+    pub fn set_limits(&self, max_total: usize, max_per_host: usize) -> Result<()> {
+        if max_total == 0 || max_per_host == 0 {
+            bail!("Connection limits must be at least 1");
+        }
+        let mut state = self.state.lock();
+        state.max_total = max_total;
+        state.max_per_host = max_per_host;
+        drop(state);
+        self.released.notify_waiters();
+        Ok(())
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock();
+        state.total -= 1;
+        if let Some(open) = state.per_host.get_mut(&self.host) {
+            *open -= 1;
+            if *open == 0 {
+                state.per_host.remove(&self.host);
+            }
+        }
+        drop(state);
+        self.limiter.released.notify_waiters();
+    }
+}
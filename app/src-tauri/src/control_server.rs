@@ -0,0 +1,167 @@
+use anyhow::{Context, Result};
+use axum::extract::{Request, State};
+use axum::http::{header::AUTHORIZATION, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::cookies::CookieSpec;
+use crate::downloader::DownloadOptions;
+use crate::post_action::PostAction;
+use crate::state::AppState;
+
+/// File in the app data dir that tells local clients where to find the
+/// control server
+pub const DISCOVERY_FILE: &str = "control.json";
+
+/// Contents of the discovery file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Discovery {
+    pub port: u16,
+    /// Per-session secret clients send as `Authorization: Bearer <token>`
+    pub token: String,
+}
+
+/// Body of `POST /downloads`; the same fields as the `start_download` command
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct NewDownload {
+    pub url: String,
+    pub cookies: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookies_structured: Option<Vec<CookieSpec>>,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub headers: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<DownloadOptions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_at: Option<i64>,
+}
+
+#[derive(Clone)]
+struct ServerState {
+    app: AppState,
+    token: Arc<str>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedDownload {
+    pub id: String,
+}
+
+/// Serve the control API on a random localhost port and publish the port
+/// in the discovery file inside `app_data_dir`
+pub fn spawn(state: AppState, app_data_dir: PathBuf) {
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = serve(state, &app_data_dir).await {
+            tracing::error!("Control server stopped: {:#}", e);
+        }
+    });
+}
+
+async fn serve(state: AppState, app_data_dir: &Path) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0))
+        .await
+        .context("Failed to bind control server")?;
+    let port = listener.local_addr()?.port();
+
+    // Anything that can read the discovery file can drive the server, so
+    // a fresh token each session and owner-only permissions on the file
+    let token = Uuid::new_v4().simple().to_string();
+    write_discovery(
+        &app_data_dir.join(DISCOVERY_FILE),
+        &Discovery {
+            port,
+            token: token.clone(),
+        },
+    )?;
+    tracing::info!("Control server listening on 127.0.0.1:{}", port);
+
+    let state = ServerState {
+        app: state,
+        token: token.into(),
+    };
+    let app = Router::new()
+        .route("/downloads", post(create_download))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+fn write_discovery(path: &Path, discovery: &Discovery) -> Result<()> {
+    let json = serde_json::to_string(discovery)?;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .context("Failed to write discovery file")?;
+    std::io::Write::write_all(&mut file, json.as_bytes())?;
+    Ok(())
+}
+
+async fn require_token(
+    State(state): State<ServerState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let presented = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    if !constant_time_eq(presented.as_bytes(), state.token.as_bytes()) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(next.run(request).await)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn create_download(
+    State(state): State<ServerState>,
+    Json(request): Json<NewDownload>,
+) -> Result<Json<CreatedDownload>, (StatusCode, String)> {
+    // Whoever holds the token could otherwise run programs as the user
+    let on_complete = request.options.as_ref().and_then(|o| o.on_complete.as_ref());
+    if matches!(on_complete, Some(PostAction::Run { .. })) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Commands to run after a download can only be set in the app".to_string(),
+        ));
+    }
+
+    let manager = state.app.download_manager.read().await;
+    let id = manager
+        .start_download(
+            request.url,
+            request.cookies,
+            request.cookies_structured,
+            request.referrer,
+            request.user_agent,
+            request.headers,
+            request.options.unwrap_or_default(),
+            request.start_at,
+        )
+        .await
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()))?;
+
+    Ok(Json(CreatedDownload { id }))
+}
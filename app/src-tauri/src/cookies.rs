@@ -0,0 +1,79 @@
+use reqwest::cookie::Jar;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// One browser cookie with its scope, so it is also sent after a redirect
+/// to another subdomain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CookieSpec {
+    pub name: String,
+    pub value: String,
+    /// Sent to this domain and its subdomains; without one, only to the
+    /// download's host
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// Defaults to `/`
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Only sent over HTTPS
+    #[serde(default)]
+    pub secure: bool,
+    #[serde(default)]
+    pub http_only: bool,
+}
+
+impl CookieSpec {
+    /// As a `Set-Cookie` value, or `None` if a part would break the header
+    fn set_cookie(&self) -> Option<String> {
+        let clean = |s: &str| !s.chars().any(|c| c == ';' || c.is_control());
+        if self.name.is_empty() || self.name.contains('=') || !clean(&self.name) {
+            return None;
+        }
+        let mut cookie = format!("{}={}", self.name, self.value);
+        if let Some(domain) = &self.domain {
+            cookie.push_str(&format!("; Domain={}", domain));
+        }
+        cookie.push_str(&format!("; Path={}", self.path.as_deref().unwrap_or("/")));
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+        if self.http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        clean(&cookie).then_some(cookie)
+    }
+
+    /// Where the cookie is set from: its own domain, so the jar accepts
+    /// domains the download URL isn't under
+    fn origin(&self, url: &Url) -> Option<Url> {
+        let Some(domain) = &self.domain else {
+            return Some(url.clone());
+        };
+        Url::parse(&format!("https://{}/", domain.trim_start_matches('.'))).ok()
+    }
+}
+
+/// The cookies a download sends. Structured cookies win; the extension's
+/// older `name=value; name2=value2` string is scoped to `url`'s host.
+pub fn jar(url: &Url, flat: Option<&str>, structured: Option<&[CookieSpec]>) -> Jar {
+    let jar = Jar::default();
+    match structured.filter(|cookies| !cookies.is_empty()) {
+        Some(cookies) => {
+            for cookie in cookies {
+                match (cookie.set_cookie(), cookie.origin(url)) {
+                    (Some(set_cookie), Some(origin)) => jar.add_cookie_str(&set_cookie, &origin),
+                    _ => tracing::warn!("Skipping invalid cookie {:?}", cookie.name),
+                }
+            }
+        }
+        None => {
+            for pair in flat.into_iter().flat_map(|s| s.split(';')) {
+                let pair = pair.trim();
+                if pair.contains('=') {
+                    jar.add_cookie_str(pair, url);
+                }
+            }
+        }
+    }
+    jar
+}
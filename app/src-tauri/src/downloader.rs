@@ -1,30 +1,121 @@
 use anyhow::{Context, Result};
+use async_trait::async_trait;
 use bytes::Bytes;
+use futures::future::{BoxFuture, Shared};
+use futures::{FutureExt, StreamExt};
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tauri::{AppHandle, Emitter};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT_ENCODING, ACCEPT_RANGES, AUTHORIZATION, CONNECTION,
+    CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, COOKIE, ETAG, HOST, IF_RANGE,
+    LAST_MODIFIED, RANGE, REFERER, RETRY_AFTER, TRANSFER_ENCODING, USER_AGENT,
+};
+use tauri::{AppHandle, Manager};
 use tokio::fs::{File, OpenOptions};
-use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use std::io::SeekFrom;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt, BufWriter};
 use tokio::sync::mpsc;
 use uuid::Uuid;
 
-use crate::persistence::DownloadPersistence;
-use std::sync::Arc;
+use crate::backend::{self, ControlHandle, DownloadBackend, DownloadCtx};
+use crate::category::{self, CategoryRule};
+use crate::connections::{
+    ConnectionLimiter, DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_CONNECTIONS_PER_HOST,
+};
+use crate::cookies::{self, CookieSpec};
+use crate::error::DownloadError;
+use crate::events::EventSink;
+use crate::history::{self, ExportedDownload};
+use crate::hls::{self, Playlist};
+use crate::host_stats::{self, HostStats, MIN_SAMPLE_SIZE};
+use crate::integrity::{self, Verification, VerifyReport, VERIFY_CONCURRENCY};
+use crate::local_source::LocalSource;
+use crate::network::{IpPreference, PreferenceResolver};
+use crate::notifications::NotificationSettings;
+use crate::opener;
+use crate::persistence::{
+    DownloadPage, DownloadPersistence, DownloadQuery, UNFINISHED_STATUSES,
+};
+use crate::post_action::{self, PostAction};
+use crate::queue::{DownloadQueue, DEFAULT_MAX_CONCURRENT_DOWNLOADS};
+use crate::rate_limit::RateLimiter;
+use crate::redirects::{RedirectChain, RedirectPolicy};
+use crate::segment_strategy::SegmentStrategy;
+use crate::settings::{self, Settings};
+use crate::sidecar;
+use crate::speed_schedule::{self, SpeedSchedule};
+use crate::speed::SpeedTracker;
+use crate::statistics::{Statistics, TOP_HOSTS};
+use crate::transport::{HttpTransport, ReqwestTransport};
+use crate::user_agent::UserAgentSettings;
+
+pub(crate) const DEFAULT_MAX_SEGMENTS: usize = 32;
+pub(crate) const DEFAULT_MIN_SEGMENT_SIZE: u64 = 1024 * 1024; // 1MB minimum per segment
+/// Upper bound for any configured segment count
+const SEGMENT_LIMIT: usize = 64;
+/// Appended to a download's file name while it is being written
+const TEMP_SUFFIX: &str = ".gripdl-tmp";
+/// How often a segment's progress is recorded, bounding what a crash loses
+pub(crate) const DEFAULT_SEGMENT_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+/// Bounds for a configured segment save interval
+const SEGMENT_SAVE_INTERVALS: std::ops::RangeInclusive<Duration> =
+    Duration::from_millis(250)..=Duration::from_secs(60);
+/// Segments a download may run per CPU core, unless configured otherwise
+pub(crate) const DEFAULT_SEGMENTS_PER_CORE: usize = 4;
+/// Write buffers all segments of one download may hold between them
+pub(crate) const DEFAULT_SEGMENT_MEMORY_BUDGET: u64 = 64 * 1024 * 1024;
+pub(crate) const DEFAULT_WRITE_BUFFER_SIZE: usize = 1024 * 1024;
+/// Bounds for any configured write buffer size
+const WRITE_BUFFER_SIZES: std::ops::RangeInclusive<usize> = 64 * 1024..=8 * 1024 * 1024;
+/// Minimum time between progress writes and `download-update` events
+pub(crate) const PROGRESS_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+const DEFAULT_MAX_RETRIES: u32 = 5;
+const DEFAULT_RETRY_BACKOFF_BASE: Duration = Duration::from_secs(1);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+/// Longest `Retry-After` we wait out; asking for more fails the download
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60 * 60);
+const MAX_SEGMENT_REDISPATCHES: u32 = 3;
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_READ_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_STALL_TIMEOUT_SECS: u64 = 60;
+const TASK_EXIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long a task told to stop gets to finish before callers move on
+const TASK_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long quitting waits for transfers to record their progress
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
-const MAX_SEGMENTS: usize = 32;
-const MIN_SEGMENT_SIZE: u64 = 1024 * 1024; // 1MB minimum per segment
+/// Headers GripDL controls itself, which custom headers may not override
+const MANAGED_HEADERS: [HeaderName; 9] = [
+    ACCEPT_ENCODING,
+    RANGE,
+    IF_RANGE,
+    HOST,
+    CONTENT_LENGTH,
+    TRANSFER_ENCODING,
+    CONNECTION,
+    COOKIE,
+    USER_AGENT,
+];
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DownloadStatus {
     Pending,
+    /// Waiting for its `start_at` time
+    Scheduled,
+    /// Waiting for a free slot under the concurrent-download limit
+    Queued,
     Downloading,
+    /// Transferred; being hashed and checked before it's completed
+    Verifying,
     Paused,
     Completed,
-    Failed(String),
+    Failed(DownloadError),
     Cancelled,
 }
 
@@ -32,72 +123,621 @@ pub enum DownloadStatus {
 pub struct DownloadInfo {
     pub id: String,
     pub url: String,
+    /// Where `url` last redirected to (e.g. a signed CDN link)
+    #[serde(default)]
+    pub resolved_url: Option<String>,
+    /// Every URL `url` went through to get there, starting with itself;
+    /// empty if it wasn't redirected
+    #[serde(default)]
+    pub redirect_chain: Vec<String>,
     pub file_path: PathBuf,
     pub file_name: String,
     pub total_size: Option<u64>,
     pub downloaded_size: u64,
     pub status: DownloadStatus,
     pub cookies: Option<String>,
+    /// Cookies with their domain and path, preferred over `cookies`
+    #[serde(default)]
+    pub cookies_structured: Option<Vec<CookieSpec>>,
     pub referrer: Option<String>,
     pub user_agent: Option<String>,
+    /// Extra request headers forwarded by the extension (Origin, API keys, ...)
+    #[serde(default)]
+    pub headers: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub options: DownloadOptions,
+    /// Queued downloads with a higher priority start first
+    #[serde(default)]
+    pub priority: i32,
+    /// Unix timestamp the download is scheduled to start at
+    #[serde(default)]
+    pub start_at: Option<i64>,
+    /// `<algo>:<hex digest>` of the finished file
+    #[serde(default)]
+    pub checksum: Option<String>,
+    /// `ETag` the server sent for the file, to tell whether a partial
+    /// download still matches it
+    #[serde(default)]
+    pub etag: Option<String>,
+    /// `Last-Modified` the server sent, used when there's no strong ETag
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    /// Group the download is filed under, e.g. `video`
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Free-form labels. Kept in their own table; `save_download` leaves
+    /// them alone and `set_tags` writes them.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Set on the update sent when opening the file found it gone
+    #[serde(default)]
+    pub file_missing: bool,
+    /// Smoothed transfer rate; only filled in on `download-update` events
+    #[serde(default)]
+    pub speed_bps: Option<u64>,
+    /// Estimated seconds remaining; only filled in on `download-update` events
+    #[serde(default)]
+    pub eta_secs: Option<u64>,
+    /// Seconds until a rate-limited download tries again; only filled in on
+    /// `download-update` events
+    #[serde(default)]
+    pub throttled_secs: Option<u64>,
+    /// User agent the download's requests send; only filled in by the
+    /// `get_download_info` command
+    #[serde(default)]
+    pub effective_user_agent: Option<String>,
+    /// Fraction done, 0.0 to 1.0; `None` while the size is unknown, in
+    /// which case `downloaded_size` still shows activity
+    #[serde(default)]
+    pub progress: Option<f32>,
+    /// Place in the queue of a `Queued` download, 1 for the next to start.
+    /// Not stored; filled in when the download is read or updated.
+    #[serde(default)]
+    pub queue_position: Option<usize>,
+    /// Bytes per second over the time a completed download spent
+    /// transferring
+    #[serde(default)]
+    pub avg_speed_bps: Option<u64>,
     pub created_at: i64,
     pub updated_at: i64,
 }
 
-#[derive(Debug, Clone)]
-struct Segment {
-    index: usize,
-    start: u64,
-    end: u64,
+impl DownloadInfo {
+    /// Never above 1.0, even when the server under-reported the size
+    pub fn fraction_done(&self) -> Option<f32> {
+        if let DownloadStatus::Completed = self.status {
+            return Some(1.0);
+        }
+        self.total_size
+            .filter(|&total| total > 0)
+            .map(|total| (self.downloaded_size as f64 / total as f64).min(1.0) as f32)
+    }
+}
+
+/// Per-download tuning. Unset fields fall back to the built-in defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownloadOptions {
+    /// Maximum time to establish a connection
+    pub connect_timeout_secs: Option<u64>,
+    /// Maximum time to wait for the server to start responding
+    pub read_timeout_secs: Option<u64>,
+    /// Abort and retry a transfer that receives no data for this long
+    pub stall_timeout_secs: Option<u64>,
+    /// What to do when the target file already exists
+    pub on_conflict: ConflictPolicy,
+    /// `http://`, `https://` or `socks5://` proxy URL, optionally with
+    /// `user:pass@` credentials. Overrides the manager's default proxy.
+    pub proxy: Option<String>,
+    /// Credentials for the `Authorization` header. Never serialized back
+    /// out; persistence stores them separately from the other options.
+    #[serde(skip_serializing)]
+    pub auth: Option<AuthMethod>,
+    /// Cap on the aggregate transfer rate in bytes per second
+    pub rate_limit: Option<u64>,
+    /// Hex digest the finished file must match
+    pub expected_checksum: Option<(HashAlgo, String)>,
+    /// Directory to save into instead of the system download directory.
+    /// Created, along with missing parents, if it doesn't exist yet.
+    pub dest_dir: Option<PathBuf>,
+    /// Name to save as; takes precedence over the URL and the server's
+    /// Content-Disposition
+    pub file_name: Option<String>,
+    /// Alternate URLs for the same file, tried in order when the primary
+    /// URL fails with an HTTP error or stalls
+    pub mirrors: Vec<String>,
+    /// Overrides the manager's segment count limit for this download
+    pub max_segments: Option<usize>,
+    /// Overrides the manager's minimum segment size for this download
+    pub min_segment_size: Option<u64>,
+    /// Start even if the same URL is already downloading or downloaded
+    pub force: bool,
+    /// Refuse files larger than this many bytes, overriding the manager's
+    /// limit; 0 means no limit
+    pub max_size: Option<u64>,
+    /// Move, copy or run a command on the file once it has completed
+    pub on_complete: Option<PostAction>,
+    /// Category to file the download under, e.g. picked by the extension
+    /// from the file type. Guessed from the file name if unset.
+    pub category: Option<String>,
+    /// Overrides the manager's write buffer size for this download
+    pub write_buffer_size: Option<usize>,
+    /// Overrides whether redirects may leave the URL's site, e.g. for
+    /// links from untrusted sources
+    pub same_site_redirects: Option<bool>,
+    /// Overrides the manager's local address; connections leave from it
+    pub local_address: Option<IpAddr>,
+    /// Overrides the manager's IP version preference
+    pub ip_preference: Option<IpPreference>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashAlgo {
+    #[default]
+    Sha256,
+    Sha1,
+    Md5,
+}
+
+impl HashAlgo {
+    fn prefix(self) -> &'static str {
+        match self {
+            HashAlgo::Sha256 => "sha256",
+            HashAlgo::Sha1 => "sha1",
+            HashAlgo::Md5 => "md5",
+        }
+    }
+
+    pub(crate) fn from_prefix(prefix: &str) -> Option<Self> {
+        [HashAlgo::Sha256, HashAlgo::Sha1, HashAlgo::Md5]
+            .into_iter()
+            .find(|algo| algo.prefix().eq_ignore_ascii_case(prefix))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum AuthMethod {
+    Basic { user: String, pass: String },
+    Bearer { token: String },
+}
+
+impl std::fmt::Debug for AuthMethod {
+    // Keep credentials out of logs
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthMethod::Basic { user, .. } => write!(f, "Basic({}:***)", user),
+            AuthMethod::Bearer { .. } => write!(f, "Bearer(***)"),
+        }
+    }
+}
+
+impl AuthMethod {
+    fn header_value(&self) -> Result<HeaderValue> {
+        let raw = match self {
+            AuthMethod::Basic { user, pass } => {
+                use base64::Engine;
+                let encoded = base64::engine::general_purpose::STANDARD
+                    .encode(format!("{}:{}", user, pass));
+                format!("Basic {}", encoded)
+            }
+            AuthMethod::Bearer { token } => format!("Bearer {}", token),
+        };
+        let mut value = HeaderValue::from_str(&raw).context("Invalid credentials")?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+}
+
+/// How to handle a download whose target path is already taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+    /// Replace the existing file
+    Overwrite,
+    /// Pick a free name like `video (1).mp4`
+    #[default]
+    Rename,
+    /// Refuse to start the download
+    Fail,
+}
+
+impl DownloadOptions {
+    fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs.unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS))
+    }
+
+    fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout_secs.unwrap_or(DEFAULT_READ_TIMEOUT_SECS))
+    }
+
+    fn stall_timeout(&self) -> Duration {
+        Duration::from_secs(self.stall_timeout_secs.unwrap_or(DEFAULT_STALL_TIMEOUT_SECS))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Segment {
+    pub index: usize,
+    pub start: u64,
+    pub end: u64,
+    pub downloaded: u64,
+}
+
+/// Payload of the `downloads-summary` event
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadsSummary {
+    /// Downloads currently transferring
+    pub active: usize,
+    /// Downloads waiting for a free slot
+    pub queued: usize,
+    /// Bytes received so far by the active downloads
+    pub downloaded: u64,
+    /// Combined size of the active downloads whose size is known
+    pub total: u64,
+    pub speed_bps: u64,
+}
+
+/// Payload of the lifecycle events: `download-started`, `download-queued`,
+/// `download-dequeued`, `download-verifying`, `download-paused`,
+/// `download-completed`, `download-failed` and `download-cancelled`
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadLifecycleEvent {
+    pub id: String,
+    pub file_name: String,
+    pub file_path: PathBuf,
+    /// Why a download failed
+    pub error: Option<DownloadError>,
+    /// Digest of a completed file
+    pub checksum: Option<String>,
+}
+
+/// One entry of `queue-positions`
+#[derive(Debug, Clone, Serialize)]
+pub struct QueuePosition {
+    pub id: String,
+    /// 1 for the next download to start
+    pub queue_position: usize,
+}
+
+/// Payload of `post-action-failed`: a completed download's `on_complete`
+/// action didn't succeed. The download itself stays completed.
+#[derive(Debug, Clone, Serialize)]
+pub struct PostActionFailed {
+    pub id: String,
+    pub file_path: PathBuf,
+    pub error: String,
+}
+
+/// What a server reports about a URL, from `probe_url`
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeResult {
+    /// Name the file would be saved under, before resolving conflicts
+    pub file_name: String,
+    pub total_size: Option<u64>,
+    /// Whether the download could be split and resumed
+    pub supports_range: bool,
+    pub content_type: Option<String>,
+    /// Where the URL redirects to, if it does
+    pub resolved_url: Option<String>,
+    /// Every URL of the redirects, starting with the given one
+    pub redirect_chain: Vec<String>,
+    /// An HLS playlist, downloaded as a stream of its segments
+    pub is_playlist: bool,
+}
+
+#[derive(Default)]
+struct LiveProgress {
+    speed: SpeedTracker,
     downloaded: u64,
+    total_size: Option<u64>,
+}
+
+/// The task running a download
+struct ActiveTask {
+    commands: mpsc::Sender<DownloadCommand>,
+    /// The task's `JoinHandle`, shared so several callers can await it
+    finished: Shared<BoxFuture<'static, ()>>,
 }
 
 pub struct DownloadManager {
-    app_handle: AppHandle,
+    events: Arc<dyn EventSink>,
     persistence: DownloadPersistence,
-    active_downloads: Arc<Mutex<HashMap<String, mpsc::Sender<DownloadCommand>>>>,
+    active_downloads: Arc<Mutex<HashMap<String, ActiveTask>>>,
+    queue: Arc<DownloadQueue>,
+    connections: Arc<ConnectionLimiter>,
+    transport: Arc<dyn HttpTransport>,
+    /// Progress of the downloads currently transferring, for speed, ETA
+    /// and the aggregate summary
+    live: Arc<Mutex<HashMap<String, LiveProgress>>>,
+    last_summary_at: Arc<Mutex<Option<Instant>>>,
+    /// Shared so a change reaches downloads that are already running
+    notifications: Arc<Mutex<NotificationSettings>>,
+    /// Whether downloads may run a command when they complete. Off by
+    /// default; only the user can turn it on.
+    allow_post_commands: Arc<AtomicBool>,
+    /// Whether unfinished downloads keep a `.gripdl` sidecar file
+    sidecar_files: Arc<AtomicBool>,
+    category_rules: Arc<Mutex<Vec<CategoryRule>>>,
+    user_agents: Arc<Mutex<UserAgentSettings>>,
+    /// Caps all downloads together, following `speed_schedule`
+    speed_limit: Arc<RateLimiter>,
+    speed_schedule: Arc<Mutex<SpeedSchedule>>,
+    /// How many times a transient network error is retried before failing
+    pub max_retries: u32,
+    /// Delay before the first retry; doubles on every further attempt
+    pub retry_backoff_base: Duration,
+    /// Proxy used by downloads that don't specify their own
+    pub default_proxy: Option<String>,
+    /// Most connections a download is split into
+    pub max_segments: usize,
+    /// Segments are never made smaller than this
+    pub min_segment_size: u64,
+    /// Caps `max_segments` at this many per CPU core; 0 for no cap
+    pub segments_per_core: usize,
+    /// Caps `max_segments` so a download's write buffers fit in this many
+    /// bytes; `None` for no cap
+    pub segment_memory_budget: Option<u64>,
+    /// Picks the segment count by file size, within the two limits above
+    pub segment_strategy: SegmentStrategy,
+    /// Reserve the full size of a segmented download on disk before any
+    /// segment starts writing
+    pub preallocate: bool,
+    /// Refuse files larger than this many bytes, unless a download sets
+    /// its own limit
+    pub max_size: Option<u64>,
+    /// Bytes collected per file or segment before they are written out
+    pub write_buffer_size: usize,
+    /// Time between records of a segment's progress
+    pub segment_save_interval: Duration,
+    /// How many redirects are followed, and where to
+    pub redirect_policy: RedirectPolicy,
+    /// Folder for downloads that don't pick their own, instead of the
+    /// system's Downloads folder
+    pub download_dir: Option<PathBuf>,
+    /// The system's Downloads folder, if it has one
+    system_download_dir: Option<PathBuf>,
+    /// Address connections are bound to, picking the interface they leave
+    /// through; `None` to let the system choose
+    pub local_address: Option<IpAddr>,
+    /// Which IP versions connections may use
+    pub ip_preference: IpPreference,
 }
 
-enum DownloadCommand {
+/// A state change requested with `set_download_state`
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum DownloadAction {
     Pause,
     Resume,
+    /// Stop but keep the partial file, like `stop_and_keep`
+    Stop,
+    /// Stop and delete the partial file, like `cancel_and_discard`
     Cancel,
+    Retry,
+}
+
+enum DownloadCommand {
+    Pause,
+    Resume,
+    Cancel { keep_partial: bool },
+}
+
+/// How a transfer attempt ended when it didn't error out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TransferOutcome {
+    Completed,
+    /// Stopped early because a pause or cancel was requested
+    Interrupted,
 }
 
 impl DownloadManager {
     pub fn new(app_handle: AppHandle) -> Self {
+        Self::with_transport(app_handle, Arc::new(ReqwestTransport))
+    }
+
+    /// A manager whose HTTP requests go through `transport`
+    pub fn with_transport(app_handle: AppHandle, transport: Arc<dyn HttpTransport>) -> Self {
         let persistence = DownloadPersistence::new(&app_handle)
             .expect("Failed to initialize persistence");
+        let system_download_dir = app_handle.path().download_dir().ok();
+        Self::with_parts(Arc::new(app_handle), persistence, transport, system_download_dir)
+    }
+
+    /// A manager that reports to `events` rather than a running app
+    pub(crate) fn with_parts(
+        events: Arc<dyn EventSink>,
+        persistence: DownloadPersistence,
+        transport: Arc<dyn HttpTransport>,
+        system_download_dir: Option<PathBuf>,
+    ) -> Self {
+        let speed_schedule = persistence
+            .load_setting(speed_schedule::SETTINGS_KEY)
+            .unwrap_or_else(|e| {
+                tracing::warn!("Ignoring the saved speed schedule: {:#}", e);
+                None
+            })
+            .unwrap_or_default();
         
-        Self {
-            app_handle,
+        let mut manager = Self {
+            events,
             persistence,
             active_downloads: Arc::new(Mutex::new(HashMap::new())),
+            queue: Arc::new(DownloadQueue::new(DEFAULT_MAX_CONCURRENT_DOWNLOADS)),
+            connections: Arc::new(ConnectionLimiter::new(
+                DEFAULT_MAX_CONNECTIONS,
+                DEFAULT_MAX_CONNECTIONS_PER_HOST,
+            )),
+            transport,
+            live: Arc::new(Mutex::new(HashMap::new())),
+            last_summary_at: Arc::new(Mutex::new(None)),
+            notifications: Arc::new(Mutex::new(NotificationSettings::default())),
+            allow_post_commands: Arc::new(AtomicBool::new(false)),
+            sidecar_files: Arc::new(AtomicBool::new(false)),
+            category_rules: Arc::new(Mutex::new(Vec::new())),
+            user_agents: Arc::new(Mutex::new(UserAgentSettings::default())),
+            speed_limit: Arc::new(RateLimiter::unlimited()),
+            speed_schedule: Arc::new(Mutex::new(speed_schedule)),
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_backoff_base: DEFAULT_RETRY_BACKOFF_BASE,
+            default_proxy: None,
+            max_segments: DEFAULT_MAX_SEGMENTS,
+            min_segment_size: DEFAULT_MIN_SEGMENT_SIZE,
+            segments_per_core: DEFAULT_SEGMENTS_PER_CORE,
+            segment_memory_budget: Some(DEFAULT_SEGMENT_MEMORY_BUDGET),
+            segment_strategy: SegmentStrategy::default(),
+            preallocate: false,
+            max_size: None,
+            write_buffer_size: DEFAULT_WRITE_BUFFER_SIZE,
+            segment_save_interval: DEFAULT_SEGMENT_SAVE_INTERVAL,
+            redirect_policy: RedirectPolicy::default(),
+            download_dir: None,
+            system_download_dir,
+            local_address: None,
+            ip_preference: IpPreference::default(),
+        };
+
+        match manager.persistence.load_setting::<Settings>(settings::SETTINGS_KEY) {
+            Ok(Some(saved)) => {
+                // Opting out of stored credentials holds even if the rest
+                // of the saved settings can't be used
+                let fallback = Settings {
+                    persist_credentials: saved.persist_credentials,
+                    purge_finished_credentials: saved.purge_finished_credentials,
+                    ..Settings::default()
+                };
+                if let Err(e) = manager.apply_settings(saved) {
+                    tracing::warn!("Ignoring the saved settings: {:#}", e);
+                    manager.restore_settings(fallback);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Ignoring the saved settings: {:#}", e),
+        }
+
+        if let Err(e) = manager.restore_interrupted_downloads() {
+            tracing::error!("Failed to restore interrupted downloads: {}", e);
         }
+        manager.apply_speed_schedule();
+
+        manager
+    }
+
+    /// Downloads that were running when the app last exited have no task
+    /// behind them anymore, so park them as Paused until the user resumes
+    fn restore_interrupted_downloads(&self) -> Result<()> {
+        for mut info in self.persistence.load_downloads()? {
+            if matches!(
+                info.status,
+                DownloadStatus::Pending
+                    | DownloadStatus::Queued
+                    | DownloadStatus::Downloading
+                    | DownloadStatus::Verifying
+            ) {
+                info.status = DownloadStatus::Paused;
+                self.persistence.save_download(&info)?;
+            }
+        }
+        Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn start_download(
         &self,
         url: String,
         cookies: Option<String>,
+        cookies_structured: Option<Vec<CookieSpec>>,
         referrer: Option<String>,
         user_agent: Option<String>,
+        headers: Option<HashMap<String, String>>,
+        options: DownloadOptions,
+        start_at: Option<i64>,
     ) -> Result<String> {
+        validate_url(&url)?;
+        if !options.force {
+            if let Some(existing) = self.find_duplicate(&url)? {
+                return Ok(existing);
+            }
+        }
+
+        if let Some(action) = &options.on_complete {
+            action.validate()?;
+            self.check_post_action_allowed(action)?;
+        }
+
         let id = Uuid::new_v4().to_string();
+        let backend = backend::for_url(&url)?;
+        for mirror in &options.mirrors {
+            let parsed = reqwest::Url::parse(mirror)
+                .with_context(|| format!("Invalid mirror URL {}", mirror))?;
+            if !matches!(parsed.scheme(), "http" | "https") {
+                anyhow::bail!("Mirrors must be HTTP(S) URLs: {}", mirror);
+            }
+        }
+
+        // Check the folder now, so a missing drive fails the request
+        // instead of every download it starts
+        let downloads_dir = match &options.dest_dir {
+            Some(dir) => dir.clone(),
+            None => self.default_download_dir()?,
+        };
+        ensure_writable_dir(&downloads_dir)?;
+
+        let file_name = options
+            .file_name
+            .as_deref()
+            .map(sanitize_filename)
+            .filter(|name| !name.is_empty())
+            .or_else(|| {
+                backend
+                    .file_name(&url)
+                    .map(|name| sanitize_filename(&name))
+                    .or_else(|| self.extract_filename(&url))
+            })
+            .unwrap_or_else(|| format!("download_{}", id.chars().take(8).collect::<String>()));
         
-        // Create download directory
-        let downloads_dir = self
-            .app_handle
-            .path()
-            .download_dir()
-            .context("Failed to get download directory")?;
-        
-        let file_name = self.extract_filename(&url).unwrap_or_else(|| {
-            format!("download_{}", id.chars().take(8).collect::<String>())
-        });
-        
-        let file_path = downloads_dir.join(&file_name);
+        let mut file_path =
+            resolve_target_path(&downloads_dir.join(&file_name), options.on_conflict)?;
+        // The file may not exist yet while another download is about to
+        // write it; two transfers into one file corrupt each other
+        let busy: Vec<PathBuf> = self
+            .persistence
+            .query_downloads(&DownloadQuery {
+                statuses: UNFINISHED_STATUSES.iter().map(|s| s.to_string()).collect(),
+                ..Default::default()
+            })?
+            .downloads
+            .into_iter()
+            .map(|d| d.file_path)
+            .collect();
+        if busy.contains(&file_path) {
+            match options.on_conflict {
+                ConflictPolicy::Rename => {
+                    file_path =
+                        free_path(&file_path, |p| p.exists() || busy.iter().any(|b| b == p));
+                }
+                _ => anyhow::bail!(
+                    "Another download is already saving to {}",
+                    file_path.display()
+                ),
+            }
+        }
+        let file_name = file_path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or(file_name);
+
+        // An earlier download of this URL may have left its partial here
+        // before its entry was removed. The recorded validators go out as
+        // If-Range, so the bytes are only kept if the file is unchanged.
+        let partial = leftover_partial(&file_path, &url).await;
+        if partial.is_some() {
+            tracing::info!("Continuing from the partial left at {}", file_path.display());
+        }
         
+        let category = match options.category.as_deref() {
+            Some(category) => category::normalize_label(category)?,
+            None => category::for_file_name(&file_name).map(str::to_string),
+        };
+
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -105,190 +745,262 @@ impl DownloadManager {
 
         let info = DownloadInfo {
             id: id.clone(),
-            url: url.clone(),
-            file_path: file_path.clone(),
-            file_name: file_name.clone(),
-            total_size: None,
-            downloaded_size: 0,
-            status: DownloadStatus::Pending,
-            cookies: cookies.clone(),
-            referrer: referrer.clone(),
-            user_agent: user_agent.clone(),
+            url,
+            resolved_url: None,
+            redirect_chain: Vec::new(),
+            file_path,
+            file_name,
+            total_size: partial.as_ref().and_then(|p| p.total_size),
+            downloaded_size: partial.as_ref().map_or(0, |p| p.downloaded_size),
+            status: if start_at.is_some_and(|t| t > now) {
+                DownloadStatus::Scheduled
+            } else {
+                DownloadStatus::Pending
+            },
+            cookies,
+            cookies_structured,
+            referrer,
+            user_agent,
+            headers,
+            options,
+            priority: 0,
+            start_at,
+            checksum: None,
+            etag: partial.as_ref().and_then(|p| p.etag.clone()),
+            last_modified: partial.as_ref().and_then(|p| p.last_modified.clone()),
+            category,
+            tags: Vec::new(),
+            file_missing: false,
+            speed_bps: None,
+            eta_secs: None,
+            throttled_secs: None,
+            effective_user_agent: None,
+            progress: None,
+            queue_position: None,
+            avg_speed_bps: None,
             created_at: now,
             updated_at: now,
         };
 
         self.persistence.save_download(&info)?;
-
-        // Start download task
-        let (tx, mut rx) = mpsc::channel(10);
-        self.active_downloads.lock().insert(id.clone(), tx);
-
-        let manager_clone = self.clone_for_task();
-        let app_handle_clone = self.app_handle.clone();
-        let id_clone = id.clone();
-
-        tokio::spawn(async move {
-            let mut paused = false;
-            let mut cancelled = false;
-
-            loop {
-                tokio::select! {
-                    cmd = rx.recv() => {
-                        match cmd {
-                            Some(DownloadCommand::Pause) => paused = true,
-                            Some(DownloadCommand::Resume) => paused = false,
-                            Some(DownloadCommand::Cancel) => {
-                                cancelled = true;
-                                break;
-                            }
-                            None => break,
-                        }
-                    }
-                    _ = tokio::time::sleep(tokio::time::Duration::from_millis(100)) => {
-                        if !paused && !cancelled {
-                            if let Err(e) = manager_clone.download_file(
-                                &id_clone,
-                                &url,
-                                &file_path,
-                                cookies.as_deref(),
-                                referrer.as_deref(),
-                                user_agent.as_deref(),
-                            ).await {
-                                tracing::error!("Download error: {}", e);
-                                let mut info = manager_clone.get_download_info(&id_clone).await.unwrap();
-                                info.status = DownloadStatus::Failed(e.to_string());
-                                info.updated_at = SystemTime::now()
-                                    .duration_since(UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_secs() as i64;
-                                let _ = manager_clone.persistence.save_download(&info);
-                                manager_clone.emit_download_update(&info).await;
-                                break;
-                            } else {
-                                // Download completed
-                                break;
-                            }
-                        }
-                    }
-                }
-            }
-
-            manager_clone.active_downloads.lock().remove(&id_clone);
-        });
-
+        if let Some(partial) = partial.filter(|p| !p.segments.is_empty()) {
+            self.persistence.save_segments(&id, &partial.segments)?;
+        }
+        if let DownloadStatus::Pending = info.status {
+            self.spawn_download_task(&info);
+        }
         self.emit_download_update(&info).await;
 
         Ok(id)
     }
 
-    async fn download_file(
+    /// Ask the server about a URL without downloading it: the same request
+    /// a download starts with
+    pub async fn probe_url(
         &self,
-        id: &str,
         url: &str,
-        file_path: &Path,
         cookies: Option<&str>,
+        cookies_structured: Option<&[CookieSpec]>,
         referrer: Option<&str>,
         user_agent: Option<&str>,
-    ) -> Result<()> {
-        let client = self.build_client(cookies, referrer, user_agent)?;
+    ) -> Result<ProbeResult> {
+        validate_url(url)?;
+        if !matches!(reqwest::Url::parse(url)?.scheme(), "http" | "https") {
+            anyhow::bail!("Only HTTP(S) URLs can be probed");
+        }
 
-        // Head request to get file size and check Range support
-        let head_response = client.head(url).send().await?;
-        let total_size = head_response
-            .headers()
-            .get("content-length")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|s| s.parse::<u64>().ok());
+        let options = DownloadOptions::default();
+        let (client, redirects) = self.build_client(
+            url,
+            cookies,
+            cookies_structured,
+            referrer,
+            user_agent,
+            None,
+            &options,
+        )?;
+        let (response, total_size, supports_range) =
+            self.head_or_probe(&client, url, &options).await?;
+        let response = check_status(response)?;
 
-        let supports_range = head_response
-            .headers()
-            .get("accept-ranges")
+        let headers = response.headers();
+        let content_type = headers
+            .get(CONTENT_TYPE)
             .and_then(|v| v.to_str().ok())
-            .map(|s| s == "bytes")
-            .unwrap_or(false);
+            .map(str::to_string);
+        let resolved_url = response.url().to_string();
+        let file_name = headers
+            .get(CONTENT_DISPOSITION)
+            .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+            .and_then(|v| filename_from_content_disposition(&v))
+            .or_else(|| self.extract_filename(&resolved_url))
+            .map(|name| sanitize_filename(&name))
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "download".to_string());
 
-        // Update download info
-        let mut info = self.get_download_info(id).await.unwrap();
-        info.total_size = total_size;
-        info.status = DownloadStatus::Downloading;
-        self.persistence.save_download(&info)?;
-        self.emit_download_update(&info).await;
+        Ok(ProbeResult {
+            file_name,
+            total_size,
+            supports_range,
+            is_playlist: hls::is_playlist(response.url(), content_type.as_deref()),
+            content_type,
+            resolved_url: (resolved_url != url).then_some(resolved_url),
+            redirect_chain: redirects.urls(),
+        })
+    }
 
-        if !supports_range || total_size.is_none() {
-            // Single-threaded download
-            return self.download_single_threaded(&client, url, file_path, id).await;
-        }
+    /// The first request of a download, for the file's size, Range support,
+    /// type and name: a HEAD, or a GET of the first byte for servers that
+    /// refuse HEAD or fail it. Its body, if any, isn't read.
+    async fn head_or_probe(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        options: &DownloadOptions,
+    ) -> Result<(reqwest::Response, Option<u64>, bool)> {
+        let failure = match self.transport.head(client, url, options.read_timeout()).await {
+            Ok(response) if !head_refused(response.status()) => {
+                if let Some(throttle) = Throttled::from_response(&response) {
+                    return Err(throttle.into());
+                }
+                let (total_size, supports_range) =
+                    self.range_support(client, url, response.headers(), options).await;
+                return Ok((response, total_size, supports_range));
+            }
+            Ok(response) => response.status().to_string(),
+            Err(e) => format!("{:#}", e),
+        };
 
-        let total_size = total_size.unwrap();
-        let num_segments = self.calculate_segments(total_size);
-        
-        if num_segments <= 1 {
-            return self.download_single_threaded(&client, url, file_path, id).await;
+        tracing::debug!("HEAD of {} failed ({}); asking for the first byte instead", url, failure);
+        let response = self
+            .transport
+            .get_range(client, url, 0, Some(0), None, options.read_timeout())
+            .await?;
+        if let Some(throttle) = Throttled::from_response(&response) {
+            return Err(throttle.into());
         }
-
-        // Multi-threaded segmented download
-        let self_arc = Arc::new(self.clone_for_task());
-        self_arc.download_segmented(&client, url, file_path, total_size, num_segments, id).await
-    }
-
-    fn calculate_segments(&self, total_size: u64) -> usize {
-        let max_segments = MAX_SEGMENTS.min((total_size / MIN_SEGMENT_SIZE) as usize);
-        max_segments.max(1)
+        let response = check_status(response)?;
+        // A server ignoring the Range header sends the whole file instead
+        let (total_size, supports_range) =
+            if response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+                (content_range_total(response.headers()), true)
+            } else {
+                (content_length(response.headers()), false)
+            };
+        Ok((response, total_size, supports_range))
     }
 
-    async fn download_segmented(
-        self: Arc<Self>,
+    /// File size and Range support from a HEAD response. Some servers
+    /// leave `Accept-Ranges` out but honour Range requests anyway, so
+    /// without the header the first byte is asked for to find out.
+    async fn range_support(
+        &self,
         client: &reqwest::Client,
         url: &str,
-        file_path: &Path,
-        total_size: u64,
-        num_segments: usize,
-        id: &str,
-    ) -> Result<()> {
-        let segment_size = total_size / num_segments as u64;
-        let mut handles = Vec::new();
+        headers: &HeaderMap,
+        options: &DownloadOptions,
+    ) -> (Option<u64>, bool) {
+        let total_size = content_length(headers);
+        if headers.contains_key(ACCEPT_RANGES) {
+            return (total_size, accepts_ranges(headers));
+        }
+
+        let response = match self
+            .transport
+            .get_range(client, url, 0, Some(0), None, options.read_timeout())
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::debug!("Range probe of {} failed: {}", url, e);
+                return (total_size, false);
+            }
+        };
+        if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            return (total_size, false);
+        }
+        match content_range_total(response.headers()) {
+            Some(size) if total_size.is_none_or(|total| total == size) => (Some(size), true),
+            _ => (total_size, false),
+        }
+    }
 
-        // Create temporary files for each segment
-        let temp_dir = file_path.parent().unwrap();
-        let temp_base = format!("{}.part", file_path.file_name().unwrap().to_string_lossy());
+    /// An earlier download of the same URL that makes this one redundant:
+    /// an unfinished one is returned so the caller can track it instead,
+    /// a completed one whose file is still there is reported as an error
+    fn find_duplicate(&self, url: &str) -> Result<Option<String>> {
+        let mut matches = self.persistence.downloads_with_url(url)?;
+        // Most recent first
+        matches.sort_by_key(|d| std::cmp::Reverse(d.created_at));
 
-        for i in 0..num_segments {
-            let start = i as u64 * segment_size;
-            let end = if i == num_segments - 1 {
-                total_size - 1
-            } else {
-                (i + 1) as u64 * segment_size - 1
-            };
+        if let Some(active) = matches.iter().find(|d| {
+            matches!(
+                d.status,
+                DownloadStatus::Pending
+                    | DownloadStatus::Scheduled
+                    | DownloadStatus::Queued
+                    | DownloadStatus::Downloading
+                    | DownloadStatus::Verifying
+                    | DownloadStatus::Paused
+            )
+        }) {
+            tracing::info!("{} is already being downloaded as {}", url, active.id);
+            return Ok(Some(active.id.clone()));
+        }
 
-            let segment_file = temp_dir.join(format!("{}.{}", temp_base, i));
-            let url = url.to_string();
-            let client = client.clone();
-            let id = id.to_string();
-            let manager = Arc::clone(&self);
+        if let Some(done) = matches
+            .iter()
+            .find(|d| matches!(d.status, DownloadStatus::Completed) && d.file_path.exists())
+        {
+            anyhow::bail!(
+                "Already downloaded to {}; set force to download it again",
+                done.file_path.display()
+            );
+        }
+        Ok(None)
+    }
 
-            let handle = tokio::spawn(async move {
-                manager
-                    .download_segment(&client, &url, &segment_file, start, end, &id, i)
-                    .await
-            });
+    /// Promote scheduled downloads whose start time has passed
+    pub async fn start_due_downloads(&self) -> Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
 
-            handles.push(handle);
+        for mut info in self.persistence.load_downloads()? {
+            if !matches!(info.status, DownloadStatus::Scheduled)
+                || info.start_at.is_some_and(|t| t > now)
+            {
+                continue;
+            }
+
+            tracing::info!("Starting scheduled download {}", info.id);
+            info.status = DownloadStatus::Pending;
+            info.updated_at = now;
+            self.persistence.save_download(&info)?;
+            self.spawn_download_task(&info);
+            self.emit_download_update(&info).await;
         }
+        Ok(())
+    }
 
-        // Wait for all segments to complete
-        let mut results = Vec::new();
-        for handle in handles {
-            results.push(handle.await??);
+    /// Move a scheduled or paused download to a new start time. A time in
+    /// the past starts it right away.
+    pub async fn reschedule(&self, id: &str, start_at: i64) -> Result<()> {
+        if self.active_downloads.lock().contains_key(id) {
+            anyhow::bail!("Cannot reschedule a download that is already running");
         }
 
-        // Merge segments
-        self.merge_segments(file_path, &temp_dir, &temp_base, num_segments).await?;
+        let mut info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        if !matches!(info.status, DownloadStatus::Scheduled | DownloadStatus::Paused) {
+            anyhow::bail!("Only scheduled or paused downloads can be rescheduled");
+        }
 
-        // Update final status
-        let mut info = self.get_download_info(id).await.unwrap();
-        info.status = DownloadStatus::Completed;
-        info.downloaded_size = total_size;
+        info.start_at = Some(start_at);
+        info.status = DownloadStatus::Scheduled;
         info.updated_at = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -296,217 +1008,3928 @@ impl DownloadManager {
         self.persistence.save_download(&info)?;
         self.emit_download_update(&info).await;
 
-        Ok(())
+        self.start_due_downloads().await
     }
 
-    async fn download_segment(
-        self: Arc<Self>,
-        client: &reqwest::Client,
-        url: &str,
-        segment_file: &Path,
-        start: u64,
-        end: u64,
-        id: &str,
-        segment_index: usize,
-    ) -> Result<u64> {
-        let mut file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(segment_file)
-            .await?;
+    /// Run the transfer for an existing download row on a background task.
+    /// Progress already recorded for the download is picked up, so this is
+    /// also how interrupted downloads are resumed after a restart.
+    fn spawn_download_task(&self, info: &DownloadInfo) {
+        let (tx, mut rx) = mpsc::channel(10);
+        // Held until the task is registered, so it can't deregister first
+        let mut active = self.active_downloads.lock();
 
-        let range_header = format!("bytes={}-{}", start, end);
-        let mut response = client
-            .get(url)
-            .header("Range", range_header)
-            .send()
-            .await?;
+        let manager_clone = self.clone_for_task();
+        let id_clone = info.id.clone();
 
-        let mut downloaded = 0u64;
-        while let Some(chunk) = response.chunk().await? {
-            file.write_all(&chunk).await?;
-            downloaded += chunk.len() as u64;
+        let handle = tokio::spawn(async move {
+            'task: loop {
+                // Hold a slot under the concurrency limit for as long as the
+                // transfer runs; commands still apply while waiting for one
+                let slot = match manager_clone.queue.try_acquire() {
+                    Some(slot) => slot,
+                    None => {
+                        let priority = manager_clone
+                            .get_download_info(&id_clone)
+                            .await
+                            .map_or(0, |info| info.priority);
+                        manager_clone.queue.join(&id_clone, priority);
+                        let _ = manager_clone
+                            .set_status(&id_clone, DownloadStatus::Queued)
+                            .await;
+                        manager_clone.emit_queue_positions();
+                        let slot = loop {
+                            let cmd = tokio::select! {
+                                slot = manager_clone.queue.acquire(&id_clone, priority) => break slot,
+                                cmd = rx.recv() => cmd,
+                            };
+                            if let Some(DownloadCommand::Resume) = cmd {
+                                continue;
+                            }
 
-            // Update progress periodically
-            if downloaded % (1024 * 1024) == 0 {
-                let mut info = self.get_download_info(id).await.unwrap();
-                info.downloaded_size += chunk.len() as u64;
-                info.updated_at = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs() as i64;
-                self.persistence.save_download(&info)?;
-                self.emit_download_update(&info).await;
+                            manager_clone.queue.remove(&id_clone);
+                            manager_clone.emit_queue_positions();
+                            match cmd {
+                                Some(DownloadCommand::Pause)
+                                    if manager_clone.park(&id_clone, &mut rx).await =>
+                                {
+                                    continue 'task;
+                                }
+                                Some(DownloadCommand::Cancel { keep_partial }) => {
+                                    let _ = manager_clone
+                                        .mark_cancelled(&id_clone, keep_partial)
+                                        .await;
+                                }
+                                _ => {}
+                            }
+                            break 'task;
+                        };
+                        // Everything behind it moves up
+                        manager_clone.emit_queue_positions();
+                        if let Some(info) = manager_clone.get_download_info(&id_clone).await {
+                            manager_clone.emit_lifecycle("download-dequeued", &info);
+                        }
+                        slot
+                    }
+                };
+
+                let stop = Arc::new(AtomicBool::new(false));
+                let started = Instant::now();
+                let transfer = manager_clone.download_file(&id_clone, Arc::clone(&stop));
+                tokio::pin!(transfer);
+
+                // Keep listening for commands while the transfer runs so a
+                // pause/cancel can interrupt it mid-stream
+                let mut requested = None;
+                let result = loop {
+                    tokio::select! {
+                        result = &mut transfer => break result,
+                        cmd = rx.recv(), if requested.is_none() => match cmd {
+                            Some(DownloadCommand::Resume) => {}
+                            Some(cmd) => {
+                                stop.store(true, Ordering::SeqCst);
+                                requested = Some(cmd);
+                            }
+                            None => {
+                                stop.store(true, Ordering::SeqCst);
+                                requested = Some(DownloadCommand::Cancel { keep_partial: true });
+                            }
+                        },
+                    }
+                };
+
+                // A paused download gives its slot to the next queued one
+                drop(slot);
+                let completed = matches!(result, Ok(TransferOutcome::Completed));
+                manager_clone
+                    .record_transfer(&id_clone, started.elapsed(), completed)
+                    .await;
+
+                match result {
+                    Ok(TransferOutcome::Completed) => break,
+                    Ok(TransferOutcome::Interrupted) => {
+                        if let Some(DownloadCommand::Pause) = requested {
+                            if !manager_clone.park(&id_clone, &mut rx).await {
+                                break 'task;
+                            }
+                        } else {
+                            let keep_partial = match requested {
+                                Some(DownloadCommand::Cancel { keep_partial }) => keep_partial,
+                                _ => true,
+                            };
+                            let _ = manager_clone
+                                .mark_cancelled(&id_clone, keep_partial)
+                                .await;
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("Download error: {}", e);
+                        if let Err(e) = manager_clone.discard_partials(&id_clone).await {
+                            tracing::warn!("Failed to clean up partial files: {}", e);
+                        }
+                        let _ = manager_clone
+                            .set_status(&id_clone, DownloadStatus::Failed(classify_error(&e)))
+                            .await;
+                        break;
+                    }
+                }
             }
-        }
 
-        Ok(downloaded)
+            manager_clone.active_downloads.lock().remove(&id_clone);
+        });
+
+        active.insert(
+            info.id.clone(),
+            ActiveTask {
+                commands: tx,
+                finished: handle.map(|_| ()).boxed().shared(),
+            },
+        );
     }
 
-    async fn merge_segments(
-        &self,
-        final_path: &Path,
-        temp_dir: &Path,
-        temp_base: &str,
-        num_segments: usize,
-    ) -> Result<()> {
-        let mut final_file = File::create(final_path).await?;
+    /// Mark a download paused and wait until the user resumes it (`true`)
+    /// or cancels it (`false`)
+    async fn park(&self, id: &str, rx: &mut mpsc::Receiver<DownloadCommand>) -> bool {
+        let _ = self.set_status(id, DownloadStatus::Paused).await;
 
-        for i in 0..num_segments {
-            let segment_path = temp_dir.join(format!("{}.{}", temp_base, i));
-            let mut segment_file = File::open(&segment_path).await?;
-            tokio::io::copy(&mut segment_file, &mut final_file).await?;
-            tokio::fs::remove_file(&segment_path).await?;
+        loop {
+            match rx.recv().await {
+                Some(DownloadCommand::Resume) => return true,
+                Some(DownloadCommand::Pause) => {}
+                Some(DownloadCommand::Cancel { keep_partial }) => {
+                    let _ = self.mark_cancelled(id, keep_partial).await;
+                    return false;
+                }
+                None => return false,
+            }
         }
-
-        Ok(())
     }
 
-    async fn download_single_threaded(
-        &self,
-        client: &reqwest::Client,
-        url: &str,
-        file_path: &Path,
-        id: &str,
+    async fn download_file(&self, id: &str, stop: Arc<AtomicBool>) -> Result<TransferOutcome> {
+        let proxy = self
+            .get_download_info(id)
+            .await
+            .and_then(|info| self.effective_proxy(&info.options).map(str::to_string));
+
+        let mut attempt = 0;
+        loop {
+            let result = self.try_download_file(id, Arc::clone(&stop)).await;
+
+            let e = match result {
+                // A changed file restarts from scratch on the next attempt
+                Err(e)
+                    if (is_transient(&e) || e.downcast_ref::<RemoteChanged>().is_some())
+                        && attempt < self.max_retries =>
+                {
+                    e
+                }
+                Err(mut e) => {
+                    // Make a dead proxy distinguishable from a dead server
+                    if let Some(proxy) = proxy.as_deref().filter(|_| is_connect_error(&e)) {
+                        e = anyhow::anyhow!(
+                            "proxy connection failed ({}): {}",
+                            redact_proxy(proxy),
+                            e
+                        );
+                    }
+                    if attempt > 0 {
+                        e = anyhow::anyhow!("{} (gave up after {} attempts)", e, attempt + 1);
+                    }
+                    return Err(e);
+                }
+                other => return other,
+            };
+
+            attempt += 1;
+            let delay = self.backoff(&e, attempt);
+            tracing::warn!(
+                "Download {} hit a transient error ({}), retry {}/{} in {:?}",
+                id,
+                e,
+                attempt,
+                self.max_retries,
+                delay
+            );
+            self.note_throttle(id, &e, delay).await;
+
+            // Progress made so far is persisted, so the next attempt resumes
+            // from it via Range requests
+            if !sleep_unless_stopped(delay, &stop).await {
+                return Ok(TransferOutcome::Interrupted);
+            }
+        }
+    }
+
+    /// One attempt at a download with the backend for its URL scheme
+    async fn try_download_file(&self, id: &str, stop: Arc<AtomicBool>) -> Result<TransferOutcome> {
+        let ctx = DownloadCtx { manager: self, id };
+        let backend = backend::for_url(&ctx.info().await?.url)?;
+        let result = backend
+            .download(&ctx, ControlHandle::new(Arc::clone(&stop)))
+            .await;
+
+        // Whatever a stopped transfer ended with, it was paused or cancelled
+        if stop.load(Ordering::SeqCst) {
+            return Ok(TransferOutcome::Interrupted);
+        }
+        result?;
+        self.complete_download(id).await
+    }
+
+    /// Fetch a download over HTTP(S), failing over to its mirrors in turn.
+    /// Bytes already on disk are kept, so a mirror continues where the
+    /// previous source stopped.
+    async fn download_http(&self, id: &str, stop: Arc<AtomicBool>) -> Result<()> {
+        let info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        let sources: Vec<&str> = std::iter::once(info.url.as_str())
+            .chain(info.options.mirrors.iter().map(String::as_str))
+            .collect();
+
+        for (i, source) in sources.iter().enumerate() {
+            let result = self
+                .download_http_from(id, source, i > 0, Arc::clone(&stop))
+                .await;
+            match (result, sources.get(i + 1)) {
+                (Err(e), Some(next)) if is_mirror_failure(&e) && !stop.load(Ordering::SeqCst) => {
+                    tracing::warn!("{} failed for {} ({}), trying {}", source, id, e, next);
+                }
+                (result, _) => return result,
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetch a download from one source: one connection or several segments
+    /// depending on what the server supports, or an HLS stream
+    async fn download_http_from(
+        &self,
+        id: &str,
+        source: &str,
+        mirror: bool,
+        stop: Arc<AtomicBool>,
     ) -> Result<()> {
-        let mut response = client.get(url).send().await?;
-        let mut file = File::create(file_path).await?;
-        let mut downloaded = 0u64;
+        // Always start from the stored row: an earlier attempt may already
+        // have resolved the real filename
+        let info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        let url = source;
+        let options = &info.options;
 
-        while let Some(chunk) = response.chunk().await? {
-            file.write_all(&chunk).await?;
-            downloaded += chunk.len() as u64;
+        // Cookies, custom headers and credentials belong to the primary host
+        // and aren't sent to mirrors
+        let (client, redirects) = if mirror {
+            let options = DownloadOptions {
+                auth: None,
+                ..options.clone()
+            };
+            self.build_client(
+                url,
+                None,
+                None,
+                info.referrer.as_deref(),
+                info.user_agent.as_deref(),
+                None,
+                &options,
+            )?
+        } else {
+            self.build_client(
+                url,
+                info.cookies.as_deref(),
+                info.cookies_structured.as_deref(),
+                info.referrer.as_deref(),
+                info.user_agent.as_deref(),
+                info.headers.as_ref(),
+                options,
+            )?
+        };
 
-            // Update progress
-            let mut info = self.get_download_info(id).await.unwrap();
-            info.downloaded_size = downloaded;
-            info.updated_at = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64;
-            self.persistence.save_download(&info)?;
-            self.emit_download_update(&info).await;
+        // Head request to get file size and check Range support
+        let (head_response, total_size, supports_range) =
+            self.head_or_probe(&client, url, options).await?;
+
+        // Bytes from one source only fit another if it serves the same file
+        if let (true, Some(expected), Some(actual)) = (mirror, info.total_size, total_size) {
+            if expected != actual {
+                return Err(MirrorMismatch(format!(
+                    "{} serves {} bytes, expected {}",
+                    url, actual, expected
+                ))
+                .into());
+            }
+        }
+
+        if let (Some(limit), Some(size)) = (self.max_size_for(options), total_size) {
+            if size > limit {
+                return Err(too_large(size, limit));
+            }
+        }
+
+        // Redirects are followed on every attempt starting from the original
+        // URL, but within this attempt all transfers go straight to the
+        // final location so they don't depend on the redirect again
+        let resolved_url = head_response.url().to_string();
+
+        let content_type = head_response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok());
+        if hls::is_playlist(head_response.url(), content_type) {
+            let playlist_url = head_response.url().clone();
+            return self.download_hls(&client, playlist_url, id, &stop).await;
         }
 
+        // Update download info
+        let headers = head_response.headers().clone();
         let mut info = self.get_download_info(id).await.unwrap();
-        info.status = DownloadStatus::Completed;
-        info.downloaded_size = downloaded;
-        info.updated_at = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+
+        // Validators belong to the primary URL; a mirror's differ anyway.
+        // If the file changed since the partial bytes were fetched, they
+        // can't be continued.
+        let remote_changed = !mirror
+            && info.downloaded_size > 0
+            && validators_changed(&info, &headers);
+        if !mirror {
+            info.etag = header_string(&headers, ETAG);
+            info.last_modified = header_string(&headers, LAST_MODIFIED);
+        }
+        info.total_size = total_size;
+        info.status = DownloadStatus::Downloading;
+        info.resolved_url = (resolved_url != info.url).then(|| resolved_url.clone());
+        info.redirect_chain = redirects.urls();
+        let renamed = self.apply_server_filename(&mut info, &headers)?;
+        let moved = self.apply_category_rule(&mut info, &headers)?;
+
+        adopt_in_place_partial(&info).await?;
+
+        // Segments write into their slots of the temp file, so while a
+        // layout is on record the bytes on disk aren't a contiguous prefix.
+        // Keep using it if it still describes the file, otherwise start over.
+        let layout = self.persistence.load_segments(id)?;
+        let layout_fits = supports_range
+            && total_size.is_some()
+            && layout.last().map(|s| s.end + 1) == total_size;
+        if remote_changed {
+            tracing::warn!("{} changed on the server; restarting {} from scratch", url, id);
+        }
+        if remote_changed || (!layout.is_empty() && !layout_fits) {
+            discard_legacy_partials(&info.file_path, layout.len()).await?;
+            remove_file_if_exists(&temp_path(&info.file_path)).await?;
+            self.persistence.delete_segments(id)?;
+            info.downloaded_size = 0;
+        }
+
+        // Without a layout the bytes on disk are a contiguous prefix, left
+        // by a transfer that may not have known the size or had Range. They
+        // can be continued only if the server now reports both.
+        let mut prefix = 0;
+        if layout.is_empty() && info.downloaded_size > 0 {
+            let on_disk = tokio::fs::metadata(temp_path(&info.file_path))
+                .await
+                .map(|m| m.len())
+                .unwrap_or(0);
+            prefix = info.downloaded_size.min(on_disk);
+            let resumable = supports_range && total_size.is_some_and(|total| prefix <= total);
+            if !resumable {
+                tracing::info!(
+                    "Can't continue {} from byte {}; restarting from scratch",
+                    id,
+                    prefix
+                );
+                remove_file_if_exists(&temp_path(&info.file_path)).await?;
+                prefix = 0;
+            }
+            info.downloaded_size = prefix;
+        }
         self.persistence.save_download(&info)?;
         self.emit_download_update(&info).await;
+        if renamed || moved {
+            self.emit_info_update(&info);
+        }
 
-        Ok(())
+        let url = &resolved_url;
+        let file_path = &info.file_path;
+        let options = &info.options;
+        let if_range = if mirror { None } else { if_range_validator(&info) };
+        let limiter = options
+            .rate_limit
+            .filter(|&rate| rate > 0)
+            .map(|rate| Arc::new(RateLimiter::new(rate)));
+
+        if !supports_range || total_size.is_none() {
+            if let Some(total_size) = total_size {
+                let remaining = total_size.saturating_sub(info.downloaded_size);
+                check_free_space(file_path, remaining)?;
+            }
+
+            // Single-threaded download
+            return self
+                .download_single_threaded(
+                    &client,
+                    url,
+                    file_path,
+                    id,
+                    supports_range,
+                    if_range,
+                    options,
+                    limiter.as_deref(),
+                    &stop,
+                )
+                .await;
+        }
+
+        let total_size = total_size.unwrap();
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_string))
+            .unwrap_or_default();
+        let stats = self.persistence.load_host_stats(Some(&host))?;
+        let num_segments = if layout_fits {
+            layout.len()
+        } else if host_stats::range_unreliable(&stats) {
+            tracing::debug!("{} has ignored Range requests before; using one connection", host);
+            1
+        } else {
+            self.calculate_segments(total_size, options, &stats)
+        };
+        // Only a transfer from scratch says how fast the host is
+        let sample_started =
+            (info.downloaded_size == 0 && total_size >= MIN_SAMPLE_SIZE).then(Instant::now);
+
+        if num_segments <= 1 && !layout_fits {
+            check_free_space(file_path, total_size.saturating_sub(info.downloaded_size))?;
+            let result = self
+                .download_single_threaded(
+                    &client,
+                    url,
+                    file_path,
+                    id,
+                    supports_range,
+                    if_range,
+                    options,
+                    limiter.as_deref(),
+                    &stop,
+                )
+                .await;
+            self.record_throughput(&host, 1, total_size, sample_started, &stop, &result);
+            return result;
+        }
+
+        check_free_space(file_path, total_size.saturating_sub(info.downloaded_size))?;
+
+        // Split up only what's still missing, so the prefix is kept
+        if prefix > 0 && !layout_fits {
+            let segments = layout_after_prefix(prefix, total_size, num_segments);
+            self.persistence.save_segments(id, &segments)?;
+        }
+
+        // Multi-threaded segmented download
+        let self_arc = Arc::new(self.clone_for_task());
+        let result = self_arc
+            .download_segmented(
+                &client,
+                url,
+                file_path,
+                total_size,
+                num_segments,
+                id,
+                if_range,
+                options,
+                limiter.clone(),
+                Arc::clone(&stop),
+            )
+            .await;
+
+        match result {
+            Err(e) if e.downcast_ref::<RangeNotHonoured>().is_some() => {
+                tracing::warn!(
+                    "{} advertises Range support but {} for {}; falling back to a single connection",
+                    host,
+                    e,
+                    id
+                );
+
+                if let Err(e) = self.persistence.record_range_failure(&host, num_segments) {
+                    tracing::warn!("Failed to record Range failure for {}: {}", host, e);
+                }
+
+                // Whatever the segments wrote may be copies of the wrong
+                // bytes; the single-threaded transfer truncates the file
+                self.persistence.delete_segments(id)?;
+
+                self.download_single_threaded(
+                    &client,
+                    url,
+                    file_path,
+                    id,
+                    false,
+                    None,
+                    options,
+                    limiter.as_deref(),
+                    &stop,
+                )
+                .await
+            }
+            Err(e) if e.downcast_ref::<RemoteChanged>().is_some() => {
+                // Start over on the next attempt, which sees the new file
+                self.persistence.delete_segments(id)?;
+                let mut info = self
+                    .get_download_info(id)
+                    .await
+                    .context("Download not found")?;
+                remove_file_if_exists(&temp_path(&info.file_path)).await?;
+                info.downloaded_size = 0;
+                self.persistence.save_download(&info)?;
+                Err(e)
+            }
+            other => {
+                self.record_throughput(
+                    &host,
+                    num_segments,
+                    total_size,
+                    sample_started,
+                    &stop,
+                    &other,
+                );
+                other
+            }
+        }
     }
 
-    fn build_client(
+    /// Feed a finished transfer's rate into the host's statistics, if it
+    /// started from scratch and wasn't interrupted
+    fn record_throughput(
         &self,
-        cookies: Option<&str>,
-        referrer: Option<&str>,
-        user_agent: Option<&str>,
-    ) -> Result<reqwest::Client> {
-        let mut builder = reqwest::Client::builder();
+        host: &str,
+        segments: usize,
+        bytes: u64,
+        started: Option<Instant>,
+        stop: &AtomicBool,
+        result: &Result<()>,
+    ) {
+        let Some(started) = started else {
+            return;
+        };
+        if result.is_err() || stop.load(Ordering::SeqCst) || host.is_empty() {
+            return;
+        }
+        let secs = started.elapsed().as_secs_f64().max(0.001);
+        if let Err(e) = self
+            .persistence
+            .record_host_throughput(host, segments, bytes as f64 / secs)
+        {
+            tracing::warn!("Failed to record throughput for {}: {}", host, e);
+        }
+    }
 
-        if let Some(ua) = user_agent {
-            builder = builder.user_agent(ua);
-        } else {
-            builder = builder.user_agent("GripDL/1.0");
+    /// Download an HLS stream: resolve a master playlist to its
+    /// highest-bandwidth variant, fetch the media segments over several
+    /// connections, decrypt AES-128 ones and append them in order. Byte
+    /// totals aren't known up front, so `total_size` is extrapolated from
+    /// the share of segments done. A resumed stream starts over.
+    async fn download_hls(
+        &self,
+        client: &reqwest::Client,
+        playlist_url: reqwest::Url,
+        id: &str,
+        stop: &AtomicBool,
+    ) -> Result<()> {
+        let mut info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        let options = info.options.clone();
+        let limiter = options
+            .rate_limit
+            .filter(|&rate| rate > 0)
+            .map(RateLimiter::new);
+
+        let mut playlist_url = playlist_url;
+        let mut playlist = self.fetch_playlist(client, &playlist_url, id, &options, stop).await?;
+        if let Playlist::Master(variants) = &playlist {
+            let variant = hls::best_variant(variants).context("Master playlist has no variants")?;
+            tracing::debug!("Picked {} bps HLS variant for {}", variant.bandwidth, id);
+            playlist_url = variant.uri.clone();
+            playlist = self.fetch_playlist(client, &playlist_url, id, &options, stop).await?;
+        }
+        let Playlist::Media(media) = playlist else {
+            anyhow::bail!("HLS variant playlist points at another master playlist");
+        };
+        if !media.ended {
+            anyhow::bail!("Live HLS streams are not supported");
+        }
+        if media.segments.is_empty() {
+            anyhow::bail!("HLS playlist has no segments");
         }
 
-        if let Some(ref_str) = referrer {
-            builder = builder.referer(true);
+        // The segments are saved as one transport stream (or fragmented MP4
+        // when there's an init section), not as the playlist itself
+        let is_playlist_name = info
+            .file_path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("m3u8"));
+        if info.options.file_name.is_none() && is_playlist_name {
+            let ext = if media.init.is_some() { "mp4" } else { "ts" };
+            let file_path =
+                resolve_target_path(&info.file_path.with_extension(ext), info.options.on_conflict)?;
+            info.file_name = file_path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            info.file_path = file_path;
         }
+        info.total_size = None;
+        info.downloaded_size = 0;
+        info.status = DownloadStatus::Downloading;
+        self.persistence.save_download(&info)?;
+        self.emit_download_update(&info).await;
 
-        let client = builder.build()?;
+        let mut keys = HashMap::new();
+        for key in media.segments.iter().filter_map(|s| s.key.as_ref()) {
+            if keys.contains_key(&key.uri) {
+                continue;
+            }
+            let bytes = self
+                .fetch_hls_part(client, &key.uri, id, &options, None, stop)
+                .await
+                .context("Failed to fetch HLS key")?;
+            let key_bytes: [u8; 16] = bytes
+                .as_slice()
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("HLS key is {} bytes, expected 16", bytes.len()))?;
+            keys.insert(key.uri.clone(), key_bytes);
+        }
+
+        let mut file = File::create(temp_path(&info.file_path)).await?;
+        let mut downloaded = 0u64;
+        if let Some(init) = &media.init {
+            let bytes = self
+                .fetch_hls_part(client, init, id, &options, limiter.as_ref(), stop)
+                .await?;
+            file.write_all(&bytes).await?;
+            downloaded += bytes.len() as u64;
+        }
+
+        let connections = options
+            .max_segments
+            .unwrap_or_else(|| self.max_segments.min(self.machine_segment_cap(&options)))
+            .clamp(1, SEGMENT_LIMIT);
+        let count = media.segments.len() as u64;
+        let jobs: Vec<_> = media
+            .segments
+            .into_iter()
+            .map(|segment| {
+                let key = segment.key.map(|key| (keys[&key.uri], key.iv));
+                (segment.uri, key)
+            })
+            .collect();
+        let options_ref = &options;
+        let limiter_ref = limiter.as_ref();
+        let mut parts = futures::stream::iter(jobs)
+            .map(|(uri, key)| async move {
+                let bytes = self
+                    .fetch_hls_part(client, &uri, id, options_ref, limiter_ref, stop)
+                    .await?;
+                match key {
+                    Some((key, iv)) => hls::decrypt(&bytes, &key, &iv),
+                    None => Ok(bytes),
+                }
+            })
+            .buffered(connections);
 
-        // Set cookies if provided
-        if let Some(cookie_str) = cookies {
-            // Parse and set cookies
-            // This is simplified - you might want to use a cookie jar
+        let max_size = self.max_size_for(&options);
+        let mut done = 0u64;
+        let mut last_update = Instant::now();
+        while let Some(part) = parts.next().await {
+            let part = part?;
+            file.write_all(&part).await?;
+            downloaded += part.len() as u64;
+            done += 1;
+            if let Some(limit) = max_size.filter(|&limit| downloaded > limit) {
+                return Err(too_large(downloaded, limit));
+            }
+
+            let stopped = stop.load(Ordering::SeqCst);
+            if stopped || last_update.elapsed() >= PROGRESS_UPDATE_INTERVAL {
+                last_update = Instant::now();
+                info.downloaded_size = downloaded;
+                info.total_size = Some(downloaded * count / done);
+                info.updated_at = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs() as i64;
+                self.persistence.save_download(&info)?;
+                self.emit_download_update(&info).await;
+            }
+            if stopped {
+                return Ok(());
+            }
         }
+        file.flush().await?;
 
-        Ok(client)
+        info.total_size = Some(downloaded);
+        info.downloaded_size = downloaded;
+        self.persistence.save_download(&info)
     }
 
-    fn extract_filename(&self, url: &str) -> Option<String> {
-        url.split('/').last().and_then(|s| {
-            s.split('?').next().filter(|s| !s.is_empty()).map(|s| s.to_string())
-        })
+    async fn fetch_playlist(
+        &self,
+        client: &reqwest::Client,
+        url: &reqwest::Url,
+        id: &str,
+        options: &DownloadOptions,
+        stop: &AtomicBool,
+    ) -> Result<Playlist> {
+        let bytes = self.fetch_hls_part(client, url, id, options, None, stop).await?;
+        hls::parse(&String::from_utf8_lossy(&bytes), url)
     }
 
-    pub async fn pause_download(&self, id: &str) -> Result<()> {
-        if let Some(tx) = self.active_downloads.lock().get(id) {
-            tx.send(DownloadCommand::Pause).await?;
-            
-            let mut info = self.get_download_info(id).await.unwrap();
-            info.status = DownloadStatus::Paused;
-            info.updated_at = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64;
-            self.persistence.save_download(&info)?;
-            self.emit_download_update(&info).await;
+    /// GET a playlist, key or media segment in full, retrying transient
+    /// errors like any other transfer. Each attempt holds a connection
+    /// slot for the host, like a segment does. Stopping while waiting for
+    /// a slot or a retry gives up with an error.
+    async fn fetch_hls_part(
+        &self,
+        client: &reqwest::Client,
+        url: &reqwest::Url,
+        id: &str,
+        options: &DownloadOptions,
+        limiter: Option<&RateLimiter>,
+        stop: &AtomicBool,
+    ) -> Result<Vec<u8>> {
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let mut attempt = 0;
+        loop {
+            let connection = tokio::select! {
+                permit = self.connections.acquire(&host) => permit,
+                _ = sleep_unless_stopped(Duration::MAX, stop) => {
+                    anyhow::bail!("Stopped while waiting for a connection to {}", host)
+                }
+            };
+            let result = async {
+                let mut response = check_status(
+                    self.transport
+                        .get(client, url.as_str(), options.read_timeout())
+                        .await?,
+                )?;
+                let mut bytes = Vec::new();
+                while let Some(chunk) = next_chunk(&mut response, options.stall_timeout()).await? {
+                    bytes.extend_from_slice(&chunk);
+                    if let Some(limiter) = limiter {
+                        limiter.consume(chunk.len() as u64).await;
+                    }
+                    self.speed_limit.consume(chunk.len() as u64).await;
+                }
+                Ok::<_, anyhow::Error>(bytes)
+            }
+            .await;
+
+            match result {
+                Err(e) if is_transient(&e) && attempt < self.max_retries => {
+                    attempt += 1;
+                    let delay = self.backoff(&e, attempt);
+                    tracing::warn!(
+                        "Fetching {} hit a transient error ({}), retry {}/{} in {:?}",
+                        url,
+                        e,
+                        attempt,
+                        self.max_retries,
+                        delay
+                    );
+                    self.note_throttle(id, &e, delay).await;
+                    drop(connection);
+                    if !sleep_unless_stopped(delay, stop).await {
+                        return Err(e);
+                    }
+                }
+                other => return other,
+            }
         }
-        Ok(())
     }
 
-    pub async fn resume_download(&self, id: &str) -> Result<()> {
-        if let Some(tx) = self.active_downloads.lock().get(id) {
-            tx.send(DownloadCommand::Resume).await?;
-            
-            let mut info = self.get_download_info(id).await.unwrap();
-            info.status = DownloadStatus::Downloading;
-            info.updated_at = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64;
-            self.persistence.save_download(&info)?;
-            self.emit_download_update(&info).await;
+    /// How long to wait before retrying after `error`: what the server
+    /// asked for if it was rate limiting us, otherwise exponential backoff
+    fn backoff(&self, error: &anyhow::Error, attempt: u32) -> Duration {
+        throttled(error)
+            .and_then(|t| t.retry_after)
+            .unwrap_or_else(|| self.retry_delay(attempt))
+    }
+
+    /// Open fewer connections to a host that is rate limiting us, and let
+    /// the UI show that the download is waiting on it
+    async fn note_throttle(&self, id: &str, error: &anyhow::Error, delay: Duration) {
+        let Some(throttle) = throttled(error) else {
+            return;
+        };
+        self.connections.throttle(&throttle.host);
+
+        if let Some(mut info) = self.get_download_info(id).await {
+            info.throttled_secs = Some(delay.as_secs().max(1));
+            self.emit_info_update(&info);
         }
-        Ok(())
     }
 
-    pub async fn cancel_download(&self, id: &str) -> Result<()> {
-        if let Some(tx) = self.active_downloads.lock().get(id) {
-            tx.send(DownloadCommand::Cancel).await?;
-            
+    /// Exponential backoff for the given (1-based) retry attempt
+    fn retry_delay(&self, attempt: u32) -> Duration {
+        self.retry_backoff_base
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(MAX_RETRY_BACKOFF)
+    }
+
+    /// How many segments to split a download into. Without an explicit
+    /// per-download count, the host's past throughput picks it, capped by
+    /// the manager's limit and what the machine can handle.
+    fn calculate_segments(
+        &self,
+        total_size: u64,
+        options: &DownloadOptions,
+        stats: &[HostStats],
+    ) -> usize {
+        let max_segments = match options.max_segments {
+            Some(max_segments) => max_segments,
+            None => {
+                let limit = self.max_segments.min(self.machine_segment_cap(options));
+                host_stats::preferred_segments(stats).map_or(limit, |n| n.min(limit))
+            }
+        }
+        .clamp(1, SEGMENT_LIMIT);
+        let min_segment_size = options.min_segment_size.unwrap_or(self.min_segment_size);
+        self.segment_strategy.segment_count(total_size, max_segments, min_segment_size)
+    }
+
+    /// Most segments this machine should run for one download: a few per
+    /// CPU core, and no more write buffers than fit the memory budget
+    fn machine_segment_cap(&self, options: &DownloadOptions) -> usize {
+        let mut cap = SEGMENT_LIMIT;
+        if self.segments_per_core > 0 {
+            let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+            cap = cap.min(cores * self.segments_per_core);
+        }
+        if let Some(budget) = self.segment_memory_budget {
+            let buffer = self.write_buffer_size_for(options) as u64;
+            cap = cap.min((budget / buffer) as usize);
+        }
+        cap.max(1)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn download_segmented(
+        self: Arc<Self>,
+        client: &reqwest::Client,
+        url: &str,
+        file_path: &Path,
+        total_size: u64,
+        num_segments: usize,
+        id: &str,
+        if_range: Option<&str>,
+        options: &DownloadOptions,
+        limiter: Option<Arc<RateLimiter>>,
+        stop: Arc<AtomicBool>,
+    ) -> Result<()> {
+        // Reuse the layout from a previous attempt if it still describes
+        // this file, otherwise split it up fresh. Progress recorded by older
+        // versions refers to separate `.part` files, which are dropped.
+        let mut segments = self.persistence.load_segments(id)?;
+        if has_legacy_partials(file_path) {
+            discard_legacy_partials(file_path, segments.len()).await?;
+            segments.clear();
+        }
+        if segments.last().map(|s| s.end) != Some(total_size - 1) {
+            let segment_size = total_size / num_segments as u64;
+            segments = (0..num_segments)
+                .map(|i| Segment {
+                    index: i,
+                    start: i as u64 * segment_size,
+                    end: if i == num_segments - 1 {
+                        total_size - 1
+                    } else {
+                        (i + 1) as u64 * segment_size - 1
+                    },
+                    downloaded: 0,
+                })
+                .collect();
+            self.persistence.save_segments(id, &segments)?;
+        }
+
+        // Every segment writes at its own offset in the temp file. Don't
+        // truncate: a resumed download keeps the bytes already in place.
+        let file_path = temp_path(file_path);
+        let file_path = file_path.as_path();
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(file_path)
+            .await?;
+        if self.preallocate {
+            let path = file_path.to_path_buf();
+            tokio::task::spawn_blocking(move || preallocate(&path, total_size)).await??;
+        }
+
+        // Each segment owns its own counter; the download total is their sum
+        let progress: Arc<[AtomicU64]> = segments
+            .iter()
+            .map(|s| AtomicU64::new(s.downloaded))
+            .collect();
+
+        let mut pending = segments;
+        let mut redispatches = 0;
+        loop {
+            let mut handles = Vec::new();
+            for segment in pending.drain(..) {
+                let file_path = file_path.to_path_buf();
+                let url = url.to_string();
+                let client = client.clone();
+                let id = id.to_string();
+                let manager = Arc::clone(&self);
+                let stop = Arc::clone(&stop);
+                let progress = Arc::clone(&progress);
+                let if_range = if_range.map(str::to_string);
+                let options = options.clone();
+                let limiter = limiter.clone();
+                let task_segment = segment.clone();
+
+                let handle = tokio::spawn(async move {
+                    manager
+                        .download_segment(
+                            &client,
+                            &url,
+                            &file_path,
+                            task_segment,
+                            &id,
+                            &progress,
+                            if_range.as_deref(),
+                            &options,
+                            limiter.as_deref(),
+                            &stop,
+                        )
+                        .await
+                });
+
+                handles.push((segment, handle));
+            }
+
+            // Wait for every segment and collect the ones that failed
+            let mut failed = Vec::new();
+            let mut handles = handles.into_iter();
+            while let Some((segment, handle)) = handles.next() {
+                let result = match handle.await {
+                    Ok(result) => result,
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = result {
+                    if e.downcast_ref::<RangeNotHonoured>().is_some()
+                        || e.downcast_ref::<RemoteChanged>().is_some()
+                    {
+                        // No segment's bytes can be trusted; stop the rest
+                        // and let the caller start over
+                        for (_, handle) in handles {
+                            handle.abort();
+                            let _ = handle.await;
+                        }
+                        return Err(e);
+                    }
+                    failed.push((segment, e));
+                }
+            }
+
+            if failed.is_empty() || stop.load(Ordering::SeqCst) {
+                break;
+            }
+
+            if redispatches >= MAX_SEGMENT_REDISPATCHES {
+                let (segment, e) = failed.swap_remove(0);
+                return Err(e.context(format!(
+                    "Segment {} failed after {} attempts",
+                    segment.index,
+                    redispatches + 1
+                )));
+            }
+
+            // Hand each failed segment to a fresh task that continues from
+            // the bytes it already wrote
+            redispatches += 1;
+            for (mut segment, e) in failed {
+                tracing::warn!(
+                    "Segment {} of {} failed ({}), re-dispatching {}/{}",
+                    segment.index,
+                    id,
+                    e,
+                    redispatches,
+                    MAX_SEGMENT_REDISPATCHES
+                );
+                segment.downloaded = progress[segment.index].load(Ordering::SeqCst);
+                pending.push(segment);
+            }
+        }
+
+        if stop.load(Ordering::SeqCst) {
+            // Record what made it to disk; the layout stays for the resume
             let mut info = self.get_download_info(id).await.unwrap();
-            info.status = DownloadStatus::Cancelled;
+            info.downloaded_size = progress.iter().map(|p| p.load(Ordering::SeqCst)).sum();
             info.updated_at = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_secs() as i64;
             self.persistence.save_download(&info)?;
             self.emit_download_update(&info).await;
+            return Ok(());
         }
-        Ok(())
-    }
 
-    pub async fn get_download_info(&self, id: &str) -> Option<DownloadInfo> {
-        self.persistence
-            .load_downloads()
-            .ok()?
-            .into_iter()
-            .find(|d| d.id == id)
-    }
+        let downloaded: u64 = progress.iter().map(|p| p.load(Ordering::SeqCst)).sum();
+        if downloaded < total_size {
+            return Err(incomplete(downloaded, total_size));
+        }
 
-    pub async fn get_all_downloads(&self) -> Vec<DownloadInfo> {
-        self.persistence.load_downloads().unwrap_or_default()
+        self.persistence.delete_segments(id)?;
+        let mut info = self.get_download_info(id).await.unwrap();
+        info.downloaded_size = total_size;
+        self.persistence.save_download(&info)
     }
 
-    async fn emit_download_update(&self, info: &DownloadInfo) {
-        let _ = self.app_handle.emit("download-update", info);
-    }
+    /// Download one segment, retrying its byte range in place on transient
+    /// errors before reporting it as failed
+    #[allow(clippy::too_many_arguments)]
+    async fn download_segment(
+        self: Arc<Self>,
+        client: &reqwest::Client,
+        url: &str,
+        file_path: &Path,
+        mut segment: Segment,
+        id: &str,
+        progress: &[AtomicU64],
+        if_range: Option<&str>,
+        options: &DownloadOptions,
+        limiter: Option<&RateLimiter>,
+        stop: &AtomicBool,
+    ) -> Result<u64> {
+        let host = reqwest::Url::parse(url)?
+            .host_str()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
 
-    fn clone_for_task(&self) -> Self {
-        Self {
-            app_handle: self.app_handle.clone(),
-            persistence: DownloadPersistence::new(&self.app_handle)
-                .expect("Failed to create persistence"),
-            active_downloads: self.active_downloads.clone(),
+        let mut attempt = 0;
+        loop {
+            // Give the connection slot up between attempts, so a lowered
+            // limit for a throttling host takes effect
+            let connection = tokio::select! {
+                permit = self.connections.acquire(&host) => permit,
+                _ = sleep_unless_stopped(Duration::MAX, stop) => return Ok(segment.downloaded),
+            };
+            let result = Arc::clone(&self)
+                .try_download_segment(
+                    client,
+                    url,
+                    file_path,
+                    segment.clone(),
+                    id,
+                    progress,
+                    if_range,
+                    options,
+                    limiter,
+                    stop,
+                )
+                .await;
+
+            match result {
+                Err(e) if is_transient(&e) && attempt < self.max_retries => {
+                    attempt += 1;
+                    let delay = self.backoff(&e, attempt);
+                    tracing::warn!(
+                        "Segment {} of {} hit a transient error ({}), retry {}/{} in {:?}",
+                        segment.index,
+                        id,
+                        e,
+                        attempt,
+                        self.max_retries,
+                        delay
+                    );
+                    self.note_throttle(id, &e, delay).await;
+                    drop(connection);
+                    segment.downloaded = progress[segment.index].load(Ordering::SeqCst);
+                    if !sleep_unless_stopped(delay, stop).await {
+                        return Ok(segment.downloaded);
+                    }
+                }
+                other => return other,
+            }
         }
     }
-}
 
+    #[allow(clippy::too_many_arguments)]
+    async fn try_download_segment(
+        self: Arc<Self>,
+        client: &reqwest::Client,
+        url: &str,
+        file_path: &Path,
+        segment: Segment,
+        id: &str,
+        progress: &[AtomicU64],
+        if_range: Option<&str>,
+        options: &DownloadOptions,
+        limiter: Option<&RateLimiter>,
+        stop: &AtomicBool,
+    ) -> Result<u64> {
+        // Continue after the bytes recorded for this segment; anything a
+        // previous attempt wrote past them is simply overwritten
+        let mut downloaded = segment.downloaded;
+        let segment_len = segment.end - segment.start + 1;
+        progress[segment.index].store(downloaded, Ordering::SeqCst);
+
+        if downloaded >= segment_len {
+            return Ok(downloaded);
+        }
+
+        let mut file = OpenOptions::new().write(true).open(file_path).await?;
+        file.seek(SeekFrom::Start(segment.start + downloaded)).await?;
+        let mut file = BufWriter::with_capacity(self.write_buffer_size_for(options), file);
+
+        let range_start = segment.start + downloaded;
+        let mut response = check_status(
+            self.transport
+                .get_range(
+                    client,
+                    url,
+                    range_start,
+                    Some(segment.end),
+                    if_range,
+                    options.read_timeout(),
+                )
+                .await?,
+        )?;
+        // A full response to an If-Range request means the validator no
+        // longer matches: the file changed since the download started
+        if if_range.is_some() && response.status() == reqwest::StatusCode::OK {
+            return Err(RemoteChanged(url.to_string()).into());
+        }
+        check_content_range(&response, range_start, segment.end)?;
+
+        let mut last_saved_at = Instant::now();
+        let mut last_reported_at = Instant::now();
+        while let Some(chunk) = next_chunk(&mut response, options.stall_timeout()).await? {
+            file.write_all(&chunk).await?;
+            downloaded += chunk.len() as u64;
+            progress[segment.index].store(downloaded, Ordering::SeqCst);
+            if let Some(limiter) = limiter {
+                limiter.consume(chunk.len() as u64).await;
+            }
+            self.speed_limit.consume(chunk.len() as u64).await;
+
+            // Record progress on a fixed interval, once the bytes are in
+            // the file
+            if last_saved_at.elapsed() >= self.segment_save_interval {
+                last_saved_at = Instant::now();
+                file.flush().await?;
+                self.persistence
+                    .update_segment_progress(id, segment.index, downloaded)?;
+            }
+
+            if last_reported_at.elapsed() >= PROGRESS_UPDATE_INTERVAL {
+                last_reported_at = Instant::now();
+                let mut info = self.get_download_info(id).await.unwrap();
+                info.downloaded_size = progress.iter().map(|p| p.load(Ordering::SeqCst)).sum();
+                self.emit_download_update(&info).await;
+            }
+
+            if stop.load(Ordering::SeqCst) {
+                tracing::debug!("Segment {} of {} interrupted", segment.index, id);
+                break;
+            }
+        }
+
+        file.flush().await?;
+        self.persistence
+            .update_segment_progress(id, segment.index, downloaded)?;
+
+        // The server closed the connection early; retry the rest
+        if downloaded < segment_len && !stop.load(Ordering::SeqCst) {
+            return Err(incomplete(downloaded, segment_len));
+        }
+        Ok(downloaded)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn download_single_threaded(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        file_path: &Path,
+        id: &str,
+        supports_range: bool,
+        if_range: Option<&str>,
+        options: &DownloadOptions,
+        limiter: Option<&RateLimiter>,
+        stop: &AtomicBool,
+    ) -> Result<()> {
+        // Pick up where a previous attempt left off, trusting only bytes that
+        // are both recorded in the DB and actually present on disk
+        let offset = if supports_range {
+            let persisted = self
+                .get_download_info(id)
+                .await
+                .map(|info| info.downloaded_size)
+                .unwrap_or(0);
+            let on_disk = tokio::fs::metadata(temp_path(file_path))
+                .await
+                .map(|m| m.len())
+                .unwrap_or(0);
+            persisted.min(on_disk)
+        } else {
+            0
+        };
+
+        // All of it arrived before the app stopped, e.g. while verifying; a
+        // Range starting past the end would only be refused
+        let total_size = self.get_download_info(id).await.and_then(|info| info.total_size);
+        if offset > 0 && total_size == Some(offset) {
+            let file = OpenOptions::new().write(true).open(temp_path(file_path)).await?;
+            file.set_len(offset).await?;
+            return Ok(());
+        }
+
+        let response = if offset > 0 {
+            self.transport
+                .get_range(client, url, offset, None, if_range, options.read_timeout())
+                .await?
+        } else {
+            self.transport.get(client, url, options.read_timeout()).await?
+        };
+        let mut response = check_status(response)?;
+
+        // Nothing written yet, so the GET may still supply the real filename
+        // if the HEAD response didn't
+        let mut file_path = file_path.to_path_buf();
+        if offset == 0 {
+            let mut info = self.get_download_info(id).await.unwrap();
+            if self.apply_server_filename(&mut info, response.headers())? {
+                self.persistence.save_download(&info)?;
+                self.emit_info_update(&info);
+                file_path = info.file_path;
+            }
+        }
+        let file_path = temp_path(&file_path);
+        let file_path = file_path.as_path();
+
+        let (file, mut downloaded) =
+            if offset > 0 && response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+                let file = OpenOptions::new().append(true).open(file_path).await?;
+                // Drop any tail that was written but never recorded
+                file.set_len(offset).await?;
+                (file, offset)
+            } else {
+                if offset > 0 && if_range.is_some() {
+                    tracing::warn!("{} changed on the server, restarting {} from byte 0", url, id);
+                } else if offset > 0 {
+                    tracing::warn!(
+                        "Server ignored Range request for {}, restarting from byte 0",
+                        id
+                    );
+                }
+                (File::create(file_path).await?, 0)
+            };
+        let mut file = BufWriter::with_capacity(self.write_buffer_size_for(options), file);
+
+        // What the transfer should add up to: the response's own length if
+        // it has one, otherwise what the HEAD request reported
+        let expected_size = match response.content_length() {
+            Some(len) => Some(downloaded + len),
+            None => self
+                .get_download_info(id)
+                .await
+                .and_then(|info| info.total_size),
+        };
+
+        let max_size = self.max_size_for(options);
+        if let (Some(limit), Some(size)) = (max_size, expected_size) {
+            if size > limit {
+                return Err(too_large(size, limit));
+            }
+        }
+
+        let mut last_update = Instant::now();
+        while let Some(chunk) = next_chunk(&mut response, options.stall_timeout()).await? {
+            file.write_all(&chunk).await?;
+            downloaded += chunk.len() as u64;
+            if let Some(limiter) = limiter {
+                limiter.consume(chunk.len() as u64).await;
+            }
+            self.speed_limit.consume(chunk.len() as u64).await;
+            // The server didn't say how big the file is, or lied about it
+            if let Some(limit) = max_size.filter(|&limit| downloaded > limit) {
+                return Err(too_large(downloaded, limit));
+            }
+
+            // Update progress periodically, and always before stopping.
+            // Only bytes that reached the file are recorded.
+            let stopped = stop.load(Ordering::SeqCst);
+            if stopped || last_update.elapsed() >= PROGRESS_UPDATE_INTERVAL {
+                last_update = Instant::now();
+                file.flush().await?;
+                let mut info = self.get_download_info(id).await.unwrap();
+                info.downloaded_size = downloaded;
+                info.updated_at = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs() as i64;
+                self.persistence.save_download(&info)?;
+                self.emit_download_update(&info).await;
+            }
+
+            if stopped {
+                file.flush().await?;
+                return Ok(());
+            }
+        }
+        file.flush().await?;
+
+        // Record what arrived even if it falls short, so a retry resumes
+        // from there
+        let mut info = self.get_download_info(id).await.unwrap();
+        info.downloaded_size = downloaded;
+        self.persistence.save_download(&info)?;
+
+        match expected_size {
+            Some(expected) if downloaded < expected => Err(incomplete(downloaded, expected)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Persist a backend's progress and send it to the frontend
+    pub(crate) async fn report_progress(&self, info: &DownloadInfo) -> Result<()> {
+        self.persistence.save_download(info)?;
+        self.emit_download_update(info).await;
+        Ok(())
+    }
+
+    /// Hash the finished file and mark the download completed, or fail it
+    /// if the hash doesn't match the expected checksum
+    async fn complete_download(&self, id: &str) -> Result<TransferOutcome> {
+        let mut info = self.get_download_info(id).await.unwrap();
+        // Hashing a large file takes a while; say so rather than sit at 100%
+        info.status = DownloadStatus::Verifying;
+        self.persistence.save_download(&info)?;
+        self.emit_download_update(&info).await;
+
+        let (algo, expected) = match &info.options.expected_checksum {
+            Some((algo, expected)) => (*algo, Some(expected.trim().to_ascii_lowercase())),
+            None => (HashAlgo::default(), None),
+        };
+
+        // Backends that don't write a temp file (torrents) write in place
+        let temp = temp_path(&info.file_path);
+        let written = if temp.exists() { temp.clone() } else { info.file_path.clone() };
+        // A folder (a multi-file torrent) has no single digest; its
+        // content was verified piece by piece instead
+        let actual = if written.is_dir() {
+            None
+        } else {
+            let path = written.clone();
+            Some(tokio::task::spawn_blocking(move || hash_file(&path, algo)).await??)
+        };
+        info.checksum = actual.as_ref().map(|actual| format!("{}:{}", algo.prefix(), actual));
+        info.updated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        if let (Some(expected), Some(actual)) = (expected, actual) {
+            if expected != actual {
+                // Keep the computed hash for inspection; the task marks it failed
+                self.persistence.save_download(&info)?;
+                return Err(DownloadError::ChecksumMismatch { expected, actual }.into());
+            }
+        }
+
+        // Atomic on one filesystem: the real name appears only now, complete
+        if written == temp {
+            // Something may have taken the name while the transfer ran;
+            // the conflict policy applies to it too
+            let target = resolve_target_path(&info.file_path, info.options.on_conflict)?;
+            if target != info.file_path {
+                tracing::info!(
+                    "{} appeared meanwhile; saving as {}",
+                    info.file_path.display(),
+                    target.display()
+                );
+                sidecar::remove(&info.file_path);
+                info.file_name = target
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or(info.file_name);
+                info.file_path = target;
+            }
+            tokio::fs::rename(&temp, &info.file_path)
+                .await
+                .with_context(|| format!("Failed to rename {}", temp.display()))?;
+        }
+
+        info.status = DownloadStatus::Completed;
+        self.persistence.save_download(&info)?;
+        self.emit_download_update(&info).await;
+
+        // Off the download task, so a slow command doesn't hold its slot
+        if let Some(action) = info.options.on_complete.clone() {
+            let manager = self.clone_for_task();
+            tokio::spawn(async move { manager.run_post_action(info, action).await });
+        }
+
+        Ok(TransferOutcome::Completed)
+    }
+
+    /// Carry out a completed download's `on_complete` action. A failure is
+    /// logged and reported with `post-action-failed`; it doesn't fail the
+    /// download.
+    async fn run_post_action(&self, mut info: DownloadInfo, action: PostAction) {
+        let result = match self.check_post_action_allowed(&action) {
+            Ok(()) => {
+                let path = info.file_path.clone();
+                tokio::task::spawn_blocking(move || action.execute(&path))
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|result| result)
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(path) if path != info.file_path => {
+                info.file_name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or(info.file_name);
+                info.file_path = path;
+                if let Err(e) = self.persistence.save_download(&info) {
+                    tracing::error!("Failed to record the new location of {}: {}", info.id, e);
+                }
+                self.emit_info_update(&info);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::error!("Post-download action for {} failed: {:#}", info.id, e);
+                self.events.emit(
+                    "post-action-failed",
+                    &PostActionFailed {
+                        id: info.id,
+                        file_path: info.file_path,
+                        error: format!("{:#}", e),
+                    },
+                );
+            }
+        }
+    }
+
+    fn check_post_action_allowed(&self, action: &PostAction) -> Result<()> {
+        if matches!(action, PostAction::Run { .. })
+            && !self.allow_post_commands.load(Ordering::Relaxed)
+        {
+            anyhow::bail!("Running a command after a download is turned off in the settings");
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn build_client(
+        &self,
+        url: &str,
+        cookies: Option<&str>,
+        cookies_structured: Option<&[CookieSpec]>,
+        referrer: Option<&str>,
+        user_agent: Option<&str>,
+        custom_headers: Option<&HashMap<String, String>>,
+        options: &DownloadOptions,
+    ) -> Result<(reqwest::Client, RedirectChain)> {
+        let redirects = RedirectChain::default();
+        let same_site_only = options
+            .same_site_redirects
+            .unwrap_or(self.redirect_policy.same_site_only);
+        // Downloads are saved byte for byte as served. Asking for identity
+        // and never decoding keeps Content-Length and Range offsets
+        // meaningful, and a `.gz` file isn't unpacked on the way in.
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(options.connect_timeout())
+            .redirect(self.redirect_policy.to_reqwest(same_site_only, redirects.clone()))
+            .no_gzip()
+            .no_brotli()
+            .no_deflate();
+
+        let local_address = options.local_address.or(self.local_address);
+        let ip_preference = options
+            .ip_preference
+            .unwrap_or(self.ip_preference)
+            .for_local_address(local_address)?;
+        builder = builder.local_address(local_address);
+        if ip_preference != IpPreference::Any {
+            builder = builder.dns_resolver(Arc::new(PreferenceResolver(ip_preference)));
+        }
+
+        if let Some(proxy) = self.effective_proxy(options) {
+            let proxy = reqwest::Proxy::all(proxy)
+                .with_context(|| format!("Invalid proxy URL {}", redact_proxy(proxy)))?;
+            builder = builder.proxy(proxy);
+        }
+
+        builder = builder.user_agent(self.user_agents.lock().resolve(user_agent, url));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+
+        // Send the page the download came from on every request (HEAD,
+        // GET and each segment), instead of letting redirects rewrite it
+        if let Some(ref_str) = referrer {
+            match HeaderValue::from_str(ref_str) {
+                Ok(value) => {
+                    headers.insert(REFERER, value);
+                    builder = builder.referer(false);
+                }
+                Err(_) => tracing::warn!("Ignoring invalid referrer {:?}", ref_str),
+            }
+        }
+
+        if let Some(auth) = &options.auth {
+            headers.insert(AUTHORIZATION, auth.header_value()?);
+        }
+
+        for (name, value) in custom_headers.into_iter().flatten() {
+            let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.trim().as_bytes()),
+                HeaderValue::from_str(value.trim()),
+            ) else {
+                tracing::warn!("Skipping invalid header {:?}", name);
+                continue;
+            };
+
+            // Never let a forwarded header override one GripDL manages
+            if MANAGED_HEADERS.contains(&name) || headers.contains_key(&name) {
+                tracing::warn!("Skipping custom header {} that GripDL sets itself", name);
+                continue;
+            }
+
+            headers.insert(name, value);
+        }
+
+        builder = builder.default_headers(headers);
+
+        // Forward the browser session
+        if cookies.is_some() || cookies_structured.is_some() {
+            let url = reqwest::Url::parse(url).context("Invalid download URL")?;
+            let jar = cookies::jar(&url, cookies, cookies_structured);
+            builder = builder.cookie_provider(Arc::new(jar));
+        }
+
+        let client = builder.build()?;
+
+        Ok((client, redirects))
+    }
+
+    /// Switch to the filename the server suggests via Content-Disposition,
+    /// as long as nothing has been written under the current name yet.
+    /// Returns whether the name changed.
+    fn apply_server_filename(&self, info: &mut DownloadInfo, headers: &HeaderMap) -> Result<bool> {
+        if info.options.file_name.is_some()
+            || info.downloaded_size > 0
+            || info.file_path.exists()
+            || temp_path(&info.file_path).exists()
+        {
+            return Ok(false);
+        }
+
+        let Some(name) = headers
+            .get(CONTENT_DISPOSITION)
+            .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+            .and_then(|v| filename_from_content_disposition(&v))
+        else {
+            return Ok(false);
+        };
+
+        let file_path =
+            resolve_target_path(&info.file_path.with_file_name(&name), info.options.on_conflict)?;
+        if file_path == info.file_path {
+            return Ok(false);
+        }
+
+        tracing::debug!("Using server-provided filename {:?} for {}", name, info.id);
+        sidecar::remove(&info.file_path);
+        info.file_name = file_path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or(name);
+        info.file_path = file_path;
+        Ok(true)
+    }
+
+    /// Send a download's row for a change other than its status, like a
+    /// new path, category or priority. Sent directly: `emit_download_update`
+    /// coalesces progress updates, which could drop this one, and would
+    /// announce a status transition again.
+    fn emit_info_update(&self, info: &DownloadInfo) {
+        let mut info = info.clone();
+        info.progress = info.fraction_done();
+        info.queue_position = self.queue_position(&info.id);
+        self.events.emit("download-update", &info);
+    }
+
+    /// Save into the folder of the category rule matching the response's
+    /// Content-Type, unless the user picked a folder or the file has
+    /// already been started. Returns whether the path changed.
+    fn apply_category_rule(&self, info: &mut DownloadInfo, headers: &HeaderMap) -> Result<bool> {
+        if info.options.dest_dir.is_some()
+            || info.downloaded_size > 0
+            || info.file_path.exists()
+            || temp_path(&info.file_path).exists()
+        {
+            return Ok(false);
+        }
+        let Some(content_type) = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) else {
+            return Ok(false);
+        };
+        let Some(rule) = category::rule_for(&self.category_rules.lock(), content_type).cloned()
+        else {
+            return Ok(false);
+        };
+
+        ensure_writable_dir(&rule.dir)?;
+        let file_path =
+            resolve_target_path(&rule.dir.join(&info.file_name), info.options.on_conflict)?;
+        if info.options.category.is_none() {
+            info.category = Some(rule.category);
+        }
+        if file_path == info.file_path {
+            return Ok(false);
+        }
+
+        tracing::debug!("Saving {} to {} by category rule", info.id, rule.dir.display());
+        sidecar::remove(&info.file_path);
+        info.file_name = file_path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| info.file_name.clone());
+        info.file_path = file_path;
+        Ok(true)
+    }
+
+    fn effective_proxy<'a>(&'a self, options: &'a DownloadOptions) -> Option<&'a str> {
+        options
+            .proxy
+            .as_deref()
+            .or(self.default_proxy.as_deref())
+            .filter(|p| !p.is_empty())
+    }
+
+    /// Change the proxy used by downloads that don't set their own. Takes
+    /// effect for downloads started afterwards.
+    pub fn set_default_proxy(&mut self, proxy: Option<String>) -> Result<()> {
+        let proxy = proxy.filter(|p| !p.trim().is_empty());
+        if let Some(p) = &proxy {
+            reqwest::Proxy::all(p.as_str())
+                .with_context(|| format!("Invalid proxy URL {}", redact_proxy(p)))?;
+        }
+        self.default_proxy = proxy;
+        Ok(())
+    }
+
+    pub fn set_segment_defaults(&mut self, max_segments: usize, min_segment_size: u64) -> Result<()> {
+        if !(1..=SEGMENT_LIMIT).contains(&max_segments) {
+            anyhow::bail!("max_segments must be between 1 and {}", SEGMENT_LIMIT);
+        }
+        if min_segment_size == 0 {
+            anyhow::bail!("min_segment_size must be positive");
+        }
+        self.max_segments = max_segments;
+        self.min_segment_size = min_segment_size;
+        Ok(())
+    }
+
+    /// Cap segments per download by the machine: `segments_per_core` per
+    /// CPU core (0 for no cap) and as many write buffers as fit in
+    /// `memory_budget` bytes (`None` or 0 for no cap). Downloads that set
+    /// their own `max_segments` aren't capped.
+    pub fn set_segment_resource_limits(
+        &mut self,
+        segments_per_core: usize,
+        memory_budget: Option<u64>,
+    ) {
+        self.segments_per_core = segments_per_core;
+        self.segment_memory_budget = memory_budget.filter(|&budget| budget > 0);
+    }
+
+    pub fn set_segment_strategy(&mut self, strategy: SegmentStrategy) -> Result<()> {
+        strategy.validate()?;
+        self.segment_strategy = strategy;
+        Ok(())
+    }
+
+    /// Takes effect for downloads started afterwards
+    pub fn set_redirect_policy(&mut self, policy: RedirectPolicy) -> Result<()> {
+        policy.validate()?;
+        self.redirect_policy = policy;
+        Ok(())
+    }
+
+    /// Bind connections to `local_address` and limit them to the IP versions
+    /// `ip_preference` allows. Binding to an address also limits connections
+    /// to its IP version. Takes effect for downloads started afterwards.
+    pub fn set_network_preferences(
+        &mut self,
+        local_address: Option<IpAddr>,
+        ip_preference: IpPreference,
+    ) -> Result<()> {
+        ip_preference.for_local_address(local_address)?;
+        self.local_address = local_address;
+        self.ip_preference = ip_preference;
+        Ok(())
+    }
+
+    pub fn set_preallocate(&mut self, enabled: bool) {
+        self.preallocate = enabled;
+    }
+
+    /// Cap the size of downloads that don't set their own limit; `None`
+    /// or 0 removes the cap. Takes effect for downloads started afterwards.
+    pub fn set_max_size(&mut self, max_size: Option<u64>) {
+        self.max_size = max_size.filter(|&limit| limit > 0);
+    }
+
+    pub fn set_write_buffer_size(&mut self, bytes: usize) -> Result<()> {
+        if !WRITE_BUFFER_SIZES.contains(&bytes) {
+            anyhow::bail!(
+                "write_buffer_size must be between {} and {} bytes",
+                WRITE_BUFFER_SIZES.start(),
+                WRITE_BUFFER_SIZES.end()
+            );
+        }
+        self.write_buffer_size = bytes;
+        Ok(())
+    }
+
+    /// A crash loses at most this much of each segment's progress. Takes
+    /// effect for downloads started afterwards.
+    pub fn set_segment_save_interval(&mut self, interval: Duration) -> Result<()> {
+        if !SEGMENT_SAVE_INTERVALS.contains(&interval) {
+            anyhow::bail!(
+                "The segment save interval must be between {}ms and {}s",
+                SEGMENT_SAVE_INTERVALS.start().as_millis(),
+                SEGMENT_SAVE_INTERVALS.end().as_secs()
+            );
+        }
+        self.segment_save_interval = interval;
+        Ok(())
+    }
+
+    fn write_buffer_size_for(&self, options: &DownloadOptions) -> usize {
+        options
+            .write_buffer_size
+            .unwrap_or(self.write_buffer_size)
+            .clamp(*WRITE_BUFFER_SIZES.start(), *WRITE_BUFFER_SIZES.end())
+    }
+
+    /// The size limit that applies to a download, if any
+    fn max_size_for(&self, options: &DownloadOptions) -> Option<u64> {
+        options.max_size.or(self.max_size).filter(|&limit| limit > 0)
+    }
+
+    /// Replace and save the speed schedule, applying it right away
+    pub fn set_speed_schedule(&self, schedule: SpeedSchedule) -> Result<()> {
+        schedule.validate()?;
+        self.persistence
+            .save_setting(speed_schedule::SETTINGS_KEY, &schedule)?;
+        *self.speed_schedule.lock() = schedule;
+        self.apply_speed_schedule();
+        Ok(())
+    }
+
+    pub fn speed_schedule(&self) -> SpeedSchedule {
+        self.speed_schedule.lock().clone()
+    }
+
+    /// Set the overall speed limit to the one the schedule has for now.
+    /// Running downloads follow it from their next chunk.
+    pub fn apply_speed_schedule(&self) {
+        let limit = self.speed_schedule.lock().current_limit();
+        if limit != self.speed_limit.rate() {
+            match limit {
+                Some(rate) => tracing::info!("Speed schedule: limiting to {} bytes/s", rate),
+                None => tracing::info!("Speed schedule: full speed"),
+            }
+            self.speed_limit.set_rate(limit);
+        }
+    }
+
+    pub fn set_notification_settings(&self, settings: NotificationSettings) {
+        *self.notifications.lock() = settings;
+    }
+
+    /// Replace the rules that sort downloads into folders by Content-Type.
+    /// Earlier rules win.
+    pub fn set_category_rules(&self, rules: Vec<CategoryRule>) -> Result<()> {
+        let rules = rules
+            .into_iter()
+            .map(CategoryRule::normalize)
+            .collect::<Result<Vec<_>>>()?;
+        *self.category_rules.lock() = rules;
+        Ok(())
+    }
+
+    /// Replace the default user agent and the per-host overrides
+    pub fn set_user_agent_settings(&self, settings: UserAgentSettings) -> Result<()> {
+        *self.user_agents.lock() = settings.normalize()?;
+        Ok(())
+    }
+
+    /// The user agent requests for `info` send to its primary URL
+    pub fn effective_user_agent(&self, info: &DownloadInfo) -> String {
+        self.user_agents
+            .lock()
+            .resolve(info.user_agent.as_deref(), &info.url)
+    }
+
+    pub fn set_allow_post_commands(&self, allow: bool) {
+        self.allow_post_commands.store(allow, Ordering::Relaxed);
+    }
+
+    /// Keep a `<file>.gripdl` JSON file next to each unfinished download
+    /// for other tools to read. Off by default. Sidecars already written
+    /// are still removed when their download completes or is cancelled.
+    pub fn set_sidecar_files(&self, enabled: bool) {
+        self.sidecar_files.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn set_persist_credentials(&self, enabled: bool) -> Result<()> {
+        self.persistence.set_persist_credentials(enabled)
+    }
+
+    pub fn set_purge_finished_credentials(&self, enabled: bool) -> Result<()> {
+        self.persistence.set_purge_finished_credentials(enabled)
+    }
+
+    /// Forget the cookies, credentials and custom headers stored for a
+    /// download. A later retry goes out without them.
+    pub async fn purge_credentials(&self, id: &str) -> Result<()> {
+        // A running transfer would write them straight back
+        if self.active_downloads.lock().contains_key(id) {
+            anyhow::bail!("Pause the download before purging its credentials");
+        }
+        if self.get_download_info(id).await.is_none() {
+            anyhow::bail!("Download not found");
+        }
+
+        self.persistence.purge_credentials(id)?;
+        if let Some(info) = self.get_download_info(id).await {
+            self.emit_download_update(&info).await;
+        }
+        Ok(())
+    }
+
+    fn extract_filename(&self, url: &str) -> Option<String> {
+        url.split('/').last().and_then(|s| {
+            s.split('?').next().filter(|s| !s.is_empty()).map(|s| s.to_string())
+        })
+    }
+
+    /// Apply a pause, resume, stop, cancel or retry from the UI
+    pub async fn set_download_state(&self, id: &str, action: DownloadAction) -> Result<()> {
+        match action {
+            DownloadAction::Pause => self.pause_download(id).await,
+            DownloadAction::Resume => self.resume_download(id).await,
+            DownloadAction::Stop => self.stop_and_keep(id).await,
+            DownloadAction::Cancel => self.cancel_and_discard(id).await,
+            DownloadAction::Retry => self.retry_download(id).await,
+        }
+    }
+
+    /// Pause a running or queued download. Pausing a paused one does
+    /// nothing; anything else is an error rather than a status change.
+    pub async fn pause_download(&self, id: &str) -> Result<()> {
+        let info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        match info.status {
+            DownloadStatus::Paused => Ok(()),
+            // The download task persists the Paused status once the
+            // transfer has actually stopped and flushed its progress
+            DownloadStatus::Pending | DownloadStatus::Queued | DownloadStatus::Downloading => {
+                self.send_command(id, DownloadCommand::Pause).await
+            }
+            _ => anyhow::bail!("Only running or queued downloads can be paused"),
+        }
+    }
+
+    /// Pause every running or queued download. Returns the affected ids.
+    pub async fn pause_all(&self) -> Result<Vec<String>> {
+        let ids: Vec<String> = self.active_downloads.lock().keys().cloned().collect();
+
+        let mut paused = Vec::new();
+        for id in ids {
+            let Some(mut info) = self.get_download_info(&id).await else {
+                continue;
+            };
+            if !matches!(
+                info.status,
+                DownloadStatus::Pending | DownloadStatus::Queued | DownloadStatus::Downloading
+            ) {
+                continue;
+            }
+            if let Err(e) = self.send_command(&id, DownloadCommand::Pause).await {
+                tracing::warn!("Failed to pause {}: {}", id, e);
+                continue;
+            }
+
+            // Record the new status right away so the batch event is accurate;
+            // the task confirms it once the transfer has flushed
+            info.status = DownloadStatus::Paused;
+            info.updated_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64;
+            self.persistence.save_download(&info)?;
+            paused.push(info);
+        }
+
+        self.emit_batch_update(&paused).await;
+        Ok(paused.into_iter().map(|info| info.id).collect())
+    }
+
+    /// Resume every paused download. Cancelled and failed downloads are left
+    /// alone. Returns the affected ids.
+    pub async fn resume_all(&self) -> Result<Vec<String>> {
+        let paused: Vec<DownloadInfo> = self
+            .get_all_downloads()
+            .await
+            .into_iter()
+            .filter(|d| matches!(d.status, DownloadStatus::Paused))
+            .collect();
+
+        let mut resumed = Vec::new();
+        for info in paused {
+            match self.resume_download(&info.id).await {
+                Ok(()) => resumed.push(info),
+                Err(e) => tracing::warn!("Failed to resume {}: {}", info.id, e),
+            }
+        }
+
+        self.emit_batch_update(&resumed).await;
+        Ok(resumed.into_iter().map(|info| info.id).collect())
+    }
+
+    /// Resume a paused download. Resuming one that is already running or
+    /// queued does nothing; anything else is an error.
+    pub async fn resume_download(&self, id: &str) -> Result<()> {
+        let info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        match info.status {
+            DownloadStatus::Paused => {}
+            DownloadStatus::Pending
+            | DownloadStatus::Queued
+            | DownloadStatus::Downloading
+            | DownloadStatus::Verifying
+                if self.active_downloads.lock().contains_key(id) =>
+            {
+                return Ok(());
+            }
+            _ => anyhow::bail!("Only paused downloads can be resumed"),
+        }
+
+        // A task that exits between the lookup and the send can't take the
+        // command; start a new one then, as for a download paused before a
+        // restart. It continues from the saved progress.
+        if self.send_command(id, DownloadCommand::Resume).await.is_err() {
+            self.wait_for_task_exit(id).await;
+            self.spawn_download_task(&info);
+        }
+        Ok(())
+    }
+
+    /// Start a failed or cancelled download over with its stored metadata,
+    /// continuing from whatever partial data is still usable
+    pub async fn retry_download(&self, id: &str) -> Result<()> {
+        if self.active_downloads.lock().contains_key(id) {
+            anyhow::bail!("Download is already running");
+        }
+
+        let mut info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        match info.status {
+            DownloadStatus::Failed(_) | DownloadStatus::Cancelled => {}
+            DownloadStatus::Completed => anyhow::bail!("Download has already completed"),
+            _ => anyhow::bail!("Only failed or cancelled downloads can be retried"),
+        }
+
+        // Segment progress is tracked per segment; a single-threaded download
+        // can only resume from bytes that are actually on disk
+        if self.persistence.load_segments(id)?.is_empty() {
+            let on_disk = tokio::fs::metadata(temp_path(&info.file_path))
+                .await
+                .map(|m| m.len())
+                .unwrap_or(0);
+            info.downloaded_size = info.downloaded_size.min(on_disk);
+        }
+
+        info.status = DownloadStatus::Pending;
+        info.updated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        self.persistence.save_download(&info)?;
+        self.spawn_download_task(&info);
+        self.emit_download_update(&info).await;
+        Ok(())
+    }
+
+    /// Stop a download with `keep_partial`, otherwise cancel it for good.
+    /// See [`Self::stop_and_keep`] and [`Self::cancel_and_discard`].
+    /// With `wait`, a cancel returns only once the download's task has
+    /// finished (or given up waiting), so a retry or delete right after
+    /// can't race it. A stopped download's task stays to be resumed.
+    pub async fn cancel_download(&self, id: &str, keep_partial: bool, wait: bool) -> Result<()> {
+        if keep_partial {
+            return self.stop_and_keep(id).await;
+        }
+        self.cancel_and_discard(id).await?;
+        if wait {
+            self.wait_for_task_exit(id).await;
+        }
+        Ok(())
+    }
+
+    /// Stop a download but keep its partial file and segment progress. It
+    /// ends up `Paused` and picks up where it left off when resumed.
+    pub async fn stop_and_keep(&self, id: &str) -> Result<()> {
+        if self.active_downloads.lock().contains_key(id) {
+            return self.pause_download(id).await;
+        }
+
+        let info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        match info.status {
+            DownloadStatus::Paused => Ok(()),
+            // Paused rather than left for the scheduler to start
+            DownloadStatus::Pending | DownloadStatus::Scheduled | DownloadStatus::Queued => {
+                self.set_status(id, DownloadStatus::Paused).await
+            }
+            _ => anyhow::bail!("Only unfinished downloads can be stopped"),
+        }
+    }
+
+    /// Cancel a download and delete its partial file and segment state.
+    /// It ends up `Cancelled` and can only be retried from the start.
+    pub async fn cancel_and_discard(&self, id: &str) -> Result<()> {
+        if self
+            .send_command(id, DownloadCommand::Cancel { keep_partial: false })
+            .await
+            .is_ok()
+        {
+            return Ok(());
+        }
+
+        let info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        if let DownloadStatus::Completed = info.status {
+            anyhow::bail!("Completed downloads can't be cancelled");
+        }
+        // Not running: whatever it left behind goes, whatever its status
+        self.mark_cancelled(id, false).await
+    }
+
+    /// Open a download's file with its default application
+    pub async fn open_file(&self, id: &str) -> Result<()> {
+        let info = self.existing_file(id).await?;
+        opener::open(&info.file_path)
+    }
+
+    /// Reveal a download's file in the system file manager
+    pub async fn open_containing_folder(&self, id: &str) -> Result<()> {
+        let info = self.existing_file(id).await?;
+        opener::reveal(&info.file_path)
+    }
+
+    /// Move a completed download's file into `new_dir`, keeping its name,
+    /// and record the new location
+    pub async fn move_download(&self, id: &str, new_dir: &Path) -> Result<DownloadInfo> {
+        if self.active_downloads.lock().contains_key(id) {
+            anyhow::bail!("Cannot move a download that is in progress");
+        }
+        let mut info = self.existing_file(id).await?;
+        if !matches!(info.status, DownloadStatus::Completed) {
+            anyhow::bail!("Only completed downloads can be moved");
+        }
+        if !new_dir.is_absolute() {
+            anyhow::bail!("{} is not an absolute path", new_dir.display());
+        }
+        ensure_writable_dir(new_dir)?;
+
+        let target = new_dir.join(&info.file_name);
+        if target == info.file_path {
+            return Ok(info);
+        }
+        if target.exists() {
+            anyhow::bail!("File already exists: {}", target.display());
+        }
+        let from = info.file_path.clone();
+        let to = target.clone();
+        tokio::task::spawn_blocking(move || post_action::move_file(&from, &to)).await??;
+
+        info.file_path = target;
+        info.updated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        self.persistence.save_download(&info)?;
+        self.emit_info_update(&info);
+        Ok(info)
+    }
+
+    /// Re-hash a completed download's file against the checksum recorded
+    /// when it finished, without downloading anything
+    pub async fn verify_download(&self, id: &str) -> Result<VerifyReport> {
+        let info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        if !matches!(info.status, DownloadStatus::Completed) {
+            anyhow::bail!("Only completed downloads can be verified");
+        }
+        Ok(self.verify(info).await)
+    }
+
+    /// `verify_download` for every completed download, a few files at a
+    /// time. Each report also goes out as a `download-verified` event as
+    /// soon as it's ready. A file that can't be checked gets its own
+    /// `Unreadable` report rather than failing the rest.
+    pub async fn verify_all(&self) -> Result<Vec<VerifyReport>> {
+        let completed: Vec<_> = self
+            .persistence
+            .load_downloads()?
+            .into_iter()
+            .filter(|d| matches!(d.status, DownloadStatus::Completed))
+            .collect();
+        let reports = futures::stream::iter(completed)
+            .map(|info| self.verify(info))
+            .buffered(VERIFY_CONCURRENCY)
+            .collect()
+            .await;
+        Ok(reports)
+    }
+
+    async fn verify(&self, mut info: DownloadInfo) -> VerifyReport {
+        let path = info.file_path.clone();
+        let checksum = info.checksum.clone();
+        let verification =
+            tokio::task::spawn_blocking(move || integrity::verify(&path, checksum.as_deref()))
+                .await
+                .unwrap_or_else(|e| Verification::Unreadable {
+                    message: e.to_string(),
+                });
+        if let Verification::Missing = verification {
+            info.file_missing = true;
+            self.emit_info_update(&info);
+        }
+
+        let report = VerifyReport {
+            id: info.id,
+            file_path: info.file_path,
+            verification,
+        };
+        self.events.emit("download-verified", &report);
+        report
+    }
+
+    /// Look up a download whose file should be on disk, flagging it to the
+    /// UI if the user moved or deleted it
+    async fn existing_file(&self, id: &str) -> Result<DownloadInfo> {
+        let mut info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        if !info.file_path.exists() {
+            info.file_missing = true;
+            self.emit_download_update(&info).await;
+            anyhow::bail!("File {} no longer exists", info.file_path.display());
+        }
+        Ok(info)
+    }
+
+    /// Remove a download from the list, stopping it first if it's running.
+    /// With `delete_file` the downloaded file goes too; a partly written
+    /// segmented file is always removed.
+    pub async fn delete_download(&self, id: &str, delete_file: bool) -> Result<()> {
+        let info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+
+        if delete_file {
+            if let DownloadStatus::Completed = info.status {
+                if !info.file_path.exists() {
+                    anyhow::bail!("File {} no longer exists", info.file_path.display());
+                }
+            }
+        }
+
+        if self
+            .send_command(id, DownloadCommand::Cancel { keep_partial: !delete_file })
+            .await
+            .is_ok()
+        {
+            self.wait_for_task_exit(id).await;
+        }
+        self.queue.remove(id);
+        self.emit_queue_positions();
+
+        self.discard_partials(id).await?;
+        if delete_file {
+            remove_file_if_exists(&temp_path(&info.file_path)).await?;
+            remove_file_if_exists(&info.file_path).await?;
+        }
+        self.persistence.delete_download(id)?;
+        // A kept partial keeps its sidecar, so adding the URL again can
+        // continue it
+        if !temp_path(&info.file_path).exists() {
+            sidecar::remove(&info.file_path);
+        }
+        self.compact_if_bloated();
+
+        self.events.emit("download-removed", id);
+        Ok(())
+    }
+
+    /// Remove every completed, cancelled or failed download from the list,
+    /// optionally with its file. Returns how many were removed.
+    pub async fn clear_completed(&self, remove_files: bool) -> Result<usize> {
+        let finished: Vec<DownloadInfo> = self
+            .get_all_downloads()
+            .await
+            .into_iter()
+            .filter(|d| {
+                matches!(
+                    d.status,
+                    DownloadStatus::Completed | DownloadStatus::Cancelled | DownloadStatus::Failed(_)
+                ) && !self.active_downloads.lock().contains_key(&d.id)
+            })
+            .collect();
+
+        if remove_files {
+            for info in &finished {
+                for path in [temp_path(&info.file_path), info.file_path.clone()] {
+                    if let Err(e) = remove_file_if_exists(&path).await {
+                        tracing::warn!("Failed to remove {}: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+
+        let ids: Vec<String> = finished.into_iter().map(|info| info.id).collect();
+        let removed = self.persistence.delete_downloads(&ids)?;
+        self.compact_if_bloated();
+
+        self.events.emit("downloads-removed", &ids);
+        Ok(removed)
+    }
+
+    /// VACUUM and ANALYZE the database. Returns the reclaimed bytes.
+    pub async fn compact_database(&self) -> Result<u64> {
+        let persistence = self.persistence.clone();
+        tokio::task::spawn_blocking(move || persistence.compact()).await?
+    }
+
+    /// Compact the database in the background once deletions have freed
+    /// enough space in it
+    fn compact_if_bloated(&self) {
+        match self.persistence.needs_compaction() {
+            Ok(true) => {
+                let persistence = self.persistence.clone();
+                tokio::task::spawn_blocking(move || {
+                    if let Err(e) = persistence.compact() {
+                        tracing::warn!("Failed to compact the downloads database: {}", e);
+                    }
+                });
+            }
+            Ok(false) => {}
+            Err(e) => tracing::warn!("Failed to check the downloads database size: {}", e),
+        }
+    }
+
+    /// Pause every transfer before the app exits, so the bytes on disk are
+    /// recorded and the downloads come back paused rather than stuck as
+    /// downloading. Downloads being verified aren't paused but given the
+    /// same time to finish hashing and renaming.
+    pub async fn shutdown(&self) {
+        match self.pause_all().await {
+            Ok(ids) if !ids.is_empty() => {
+                tracing::info!("Pausing {} downloads before exit", ids.len())
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to pause downloads before exit: {}", e),
+        }
+
+        let ids: Vec<String> = self.active_downloads.lock().keys().cloned().collect();
+        let mut verifying = Vec::new();
+        for id in ids {
+            let Some(info) = self.get_download_info(&id).await else {
+                continue;
+            };
+            if let DownloadStatus::Verifying = info.status {
+                if let Some(task) = self.active_downloads.lock().get(&id) {
+                    verifying.push(task.finished.clone());
+                }
+            }
+        }
+
+        // A transfer leaves `live` once it has flushed and parked
+        let stopped = async {
+            while !self.live.lock().is_empty() {
+                tokio::time::sleep(TASK_EXIT_POLL_INTERVAL).await;
+            }
+            futures::future::join_all(verifying).await;
+        };
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, stopped).await.is_err() {
+            tracing::warn!("Some downloads did not stop before exit");
+        }
+    }
+
+    /// Give a task that was told to stop a moment to wind down
+    async fn wait_for_task_exit(&self, id: &str) {
+        let Some(finished) = self
+            .active_downloads
+            .lock()
+            .get(id)
+            .map(|task| task.finished.clone())
+        else {
+            return;
+        };
+        if tokio::time::timeout(TASK_EXIT_TIMEOUT, finished).await.is_err() {
+            tracing::warn!("Download task {} did not stop in time", id);
+        }
+    }
+
+    async fn mark_cancelled(&self, id: &str, keep_partial: bool) -> Result<()> {
+        let info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        self.discard_partials(id).await?;
+
+        // A single-threaded download writes a contiguous prefix, which is
+        // worth keeping
+        if !keep_partial {
+            remove_file_if_exists(&temp_path(&info.file_path)).await?;
+        }
+
+        self.set_status(id, DownloadStatus::Cancelled).await
+    }
+
+    /// Drop the segment state of a download along with the partly written
+    /// temp file, whose holes make it useless on its own
+    async fn discard_partials(&self, id: &str) -> Result<()> {
+        let info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        let num_segments = self.persistence.load_segments(id)?.len();
+        if num_segments == 0 {
+            return Ok(());
+        }
+        discard_legacy_partials(&info.file_path, num_segments).await?;
+        remove_file_if_exists(&temp_path(&info.file_path)).await?;
+        self.persistence.delete_segments(id)
+    }
+
+    /// Hand a command to a download's task. Fails if there is no task to
+    /// take it, so callers don't act as if it had been delivered.
+    async fn send_command(&self, id: &str, command: DownloadCommand) -> Result<()> {
+        let tx = self
+            .active_downloads
+            .lock()
+            .get(id)
+            .map(|task| task.commands.clone())
+            .context("Download is not running")?;
+        tx.send(command)
+            .await
+            .map_err(|_| anyhow::anyhow!("Download task is no longer running"))
+    }
+
+    async fn set_status(&self, id: &str, status: DownloadStatus) -> Result<()> {
+        let mut info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        info.status = status;
+        info.updated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        self.persistence.save_download(&info)?;
+        self.emit_download_update(&info).await;
+        Ok(())
+    }
+
+    pub async fn get_download_info(&self, id: &str) -> Option<DownloadInfo> {
+        let mut info = self.persistence.load_download(id).ok()??;
+        info.queue_position = self.queue_position(id);
+        Some(info)
+    }
+
+    /// Lifetime totals and the hosts most downloaded from
+    pub fn get_statistics(&self) -> Result<Statistics> {
+        self.persistence.load_statistics(TOP_HOSTS)
+    }
+
+    /// Add the time a run of the transfer took to the statistics, with the
+    /// download's size if the run completed it
+    async fn record_transfer(&self, id: &str, elapsed: Duration, completed: bool) {
+        let Some(info) = self.get_download_info(id).await else {
+            return;
+        };
+        let host = reqwest::Url::parse(info.resolved_url.as_deref().unwrap_or(&info.url))
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_ascii_lowercase))
+            .unwrap_or_default();
+        let bytes = completed.then_some(info.downloaded_size);
+        if let Err(e) = self.persistence.record_transfer(id, &host, elapsed.as_secs_f64(), bytes) {
+            tracing::warn!("Failed to record transfer statistics for {}: {}", id, e);
+        }
+    }
+
+    /// Past throughput by segment count, for one host or all of them
+    pub fn get_host_stats(&self, host: Option<&str>) -> Result<Vec<HostStats>> {
+        self.persistence.load_host_stats(host)
+    }
+
+    /// All downloads, with queued ones last in the order they will start
+    pub async fn get_all_downloads(&self) -> Vec<DownloadInfo> {
+        let mut downloads = self.persistence.load_downloads().unwrap_or_default();
+        for info in &mut downloads {
+            info.queue_position = self.queue_position(&info.id);
+        }
+        downloads.sort_by_key(|d| d.queue_position);
+        downloads
+    }
+
+    /// 1-based place of a download waiting for a slot
+    fn queue_position(&self, id: &str) -> Option<usize> {
+        self.queue.position(id).map(|position| position + 1)
+    }
+
+    /// Send the place of every waiting download as `queue-positions`, after
+    /// the queue changed
+    fn emit_queue_positions(&self) {
+        let positions: Vec<QueuePosition> = self
+            .queue
+            .waiting()
+            .into_iter()
+            .enumerate()
+            .map(|(index, id)| QueuePosition {
+                id,
+                queue_position: index + 1,
+            })
+            .collect();
+        self.events.emit("queue-positions", &positions);
+    }
+
+    /// A filtered, sorted page of downloads. Without a query this is every
+    /// download, in the order of [`Self::get_all_downloads`].
+    pub async fn query_downloads(&self, query: Option<DownloadQuery>) -> Result<DownloadPage> {
+        match query {
+            Some(query) => {
+                let mut page = self.persistence.query_downloads(&query)?;
+                for info in &mut page.downloads {
+                    info.queue_position = self.queue_position(&info.id);
+                }
+                Ok(page)
+            }
+            None => {
+                let downloads = self.get_all_downloads().await;
+                let total = downloads.len();
+                Ok(DownloadPage { downloads, total })
+            }
+        }
+    }
+
+    /// Write every download, with its segment state, to a JSON file.
+    /// Cookies, credentials and custom headers stay on this machine.
+    /// Returns how many downloads were exported.
+    pub async fn export_downloads(&self, path: &Path) -> Result<usize> {
+        let mut entries = Vec::new();
+        for mut info in self.persistence.load_downloads()? {
+            info.cookies = None;
+            info.cookies_structured = None;
+            info.headers = None;
+            let segments = self.persistence.load_segments(&info.id)?;
+            entries.push(ExportedDownload { info, segments });
+        }
+
+        let count = entries.len();
+        history::write(path, entries)?;
+        Ok(count)
+    }
+
+    /// Load downloads from a file written by `export_downloads`, merging
+    /// by id: a local entry updated at the same time or later wins. Files
+    /// are placed in this machine's download directory. Finished downloads
+    /// come in as history; unfinished ones come in paused, resumable from
+    /// their partial file if it's there. Returns how many were imported.
+    pub async fn import_downloads(&self, path: &Path) -> Result<usize> {
+        let entries = history::read(path)?;
+        let downloads_dir = self.default_download_dir()?;
+        let local = self.persistence.load_downloads()?;
+
+        let mut imported: Vec<DownloadInfo> = Vec::new();
+        for ExportedDownload { mut info, mut segments } in entries {
+            if self.active_downloads.lock().contains_key(&info.id) {
+                continue;
+            }
+            let existing = local.iter().find(|d| d.id == info.id);
+            if existing.is_some_and(|d| d.updated_at >= info.updated_at) {
+                continue;
+            }
+
+            // The file may come from anywhere; hold it to what a new
+            // download would have to pass
+            let supported = validate_url(&info.url).and_then(|()| backend::for_url(&info.url));
+            if let Err(e) = supported {
+                tracing::warn!("Skipping imported download {}: {:#}", info.id, e);
+                continue;
+            }
+            if let Some(action) = info.options.on_complete.take() {
+                let checked = match &action {
+                    PostAction::Run { .. } => {
+                        Err(anyhow::anyhow!("commands aren't imported from another machine"))
+                    }
+                    _ => action.validate().and_then(|()| self.check_post_action_allowed(&action)),
+                };
+                match checked {
+                    Ok(()) => info.options.on_complete = Some(action),
+                    Err(e) => tracing::warn!(
+                        "Dropping the completion action of imported download {}: {:#}",
+                        info.id,
+                        e
+                    ),
+                }
+            }
+
+            let file_name = sanitize_filename(&info.file_name);
+            if file_name.is_empty() {
+                tracing::warn!("Skipping imported download {} without a file name", info.id);
+                continue;
+            }
+            // Never share a file, or a partial, with another download. The
+            // entry being updated may keep its own.
+            let own = existing.map(|d| d.file_path.as_path());
+            let taken = |p: &Path| {
+                (p.exists() && own != Some(p))
+                    || local.iter().chain(&imported).any(|d| d.id != info.id && d.file_path == p)
+            };
+            info.file_path = free_path(&downloads_dir.join(&file_name), taken);
+            info.file_name = info
+                .file_path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or(file_name);
+            info.options.dest_dir = None;
+
+            match info.status {
+                DownloadStatus::Completed
+                | DownloadStatus::Failed(_)
+                | DownloadStatus::Cancelled => segments.clear(),
+                _ => {
+                    info.status = DownloadStatus::Paused;
+                    // Progress only counts if the partial file came along
+                    if !temp_path(&info.file_path).exists() {
+                        segments.clear();
+                        info.downloaded_size = 0;
+                    }
+                }
+            }
+
+            self.persistence.save_download(&info)?;
+            self.persistence
+                .set_tags(&info.id, &category::normalize_tags(&info.tags)?)?;
+            if segments.is_empty() {
+                self.persistence.delete_segments(&info.id)?;
+            } else {
+                self.persistence.save_segments(&info.id, &segments)?;
+            }
+            imported.push(info);
+        }
+
+        self.emit_batch_update(&imported).await;
+        Ok(imported.len())
+    }
+
+    /// Change a download's priority, moving it within the queue if it's
+    /// waiting for a slot
+    pub async fn reorder_download(&self, id: &str, priority: i32) -> Result<()> {
+        let mut info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        info.priority = priority;
+        self.persistence.save_download(&info)?;
+        self.queue.set_priority(id, priority);
+        self.emit_info_update(&info);
+        self.emit_queue_positions();
+        Ok(())
+    }
+
+    /// File a download under `category`, or under none
+    pub async fn set_category(&self, id: &str, category: Option<&str>) -> Result<()> {
+        let mut info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        info.category = category.map(category::normalize_label).transpose()?.flatten();
+        self.persistence.save_download(&info)?;
+        self.emit_info_update(&info);
+        Ok(())
+    }
+
+    /// Replace a download's tags; an empty list clears them
+    pub async fn set_tags(&self, id: &str, tags: &[String]) -> Result<()> {
+        let tags = category::normalize_tags(tags)?;
+        let mut info = self
+            .get_download_info(id)
+            .await
+            .context("Download not found")?;
+        self.persistence.set_tags(id, &tags)?;
+        info.tags = tags;
+        self.emit_info_update(&info);
+        Ok(())
+    }
+
+    /// Give a download a priority above every other unfinished download
+    pub async fn move_to_top(&self, id: &str) -> Result<()> {
+        let top = self
+            .unfinished_priorities(id)
+            .await
+            .max()
+            .map_or(0, |p| p.saturating_add(1));
+        self.reorder_download(id, top).await
+    }
+
+    /// Give a download a priority below every other unfinished download
+    pub async fn move_to_bottom(&self, id: &str) -> Result<()> {
+        let bottom = self
+            .unfinished_priorities(id)
+            .await
+            .min()
+            .map_or(0, |p| p.saturating_sub(1));
+        self.reorder_download(id, bottom).await
+    }
+
+    /// Priorities of the downloads other than `id` that may still start
+    async fn unfinished_priorities(&self, id: &str) -> impl Iterator<Item = i32> {
+        let id = id.to_string();
+        self.get_all_downloads()
+            .await
+            .into_iter()
+            .filter(move |d| d.id != id)
+            .filter(|d| {
+                matches!(
+                    d.status,
+                    DownloadStatus::Pending
+                        | DownloadStatus::Scheduled
+                        | DownloadStatus::Queued
+                        | DownloadStatus::Paused
+                )
+            })
+            .map(|d| d.priority)
+    }
+
+    /// Cap the connections open at once, overall and to a single host
+    pub fn set_connection_limits(&self, max_total: usize, max_per_host: usize) -> Result<()> {
+        self.connections.set_limits(max_total, max_per_host)?;
+        tracing::info!(
+            "Connection limits set to {} total, {} per host",
+            max_total,
+            max_per_host
+        );
+        Ok(())
+    }
+
+    pub fn set_max_concurrent(&self, limit: usize) -> Result<()> {
+        self.queue.set_limit(limit)?;
+        tracing::info!("Concurrent download limit set to {}", limit);
+        Ok(())
+    }
+
+    /// The settings in force, including changes made with the individual
+    /// setters since they were last saved
+    pub fn settings(&self) -> Settings {
+        let (max_connections, max_connections_per_host) = self.connections.limits();
+        Settings {
+            download_dir: self.download_dir.clone(),
+            max_concurrent: self.queue.limit(),
+            max_connections,
+            max_connections_per_host,
+            max_segments: self.max_segments,
+            min_segment_size: self.min_segment_size,
+            segments_per_core: self.segments_per_core,
+            segment_memory_budget: self.segment_memory_budget,
+            segment_strategy: self.segment_strategy,
+            default_proxy: self.default_proxy.clone(),
+            max_size: self.max_size,
+            preallocate: self.preallocate,
+            write_buffer_size: self.write_buffer_size,
+            segment_save_interval_ms: self.segment_save_interval.as_millis() as u64,
+            redirect_policy: self.redirect_policy,
+            local_address: self.local_address,
+            ip_preference: self.ip_preference,
+            notifications: *self.notifications.lock(),
+            user_agents: self.user_agents.lock().clone(),
+            category_rules: self.category_rules.lock().clone(),
+            sidecar_files: self.sidecar_files.load(Ordering::Relaxed),
+            allow_post_commands: self.allow_post_commands.load(Ordering::Relaxed),
+            persist_credentials: self.persistence.persist_credentials(),
+            purge_finished_credentials: self.persistence.purge_finished_credentials(),
+        }
+    }
+
+    /// Apply and save `settings`. Limits and notifications reach running
+    /// downloads; the rest applies to downloads started afterwards. If any
+    /// value is invalid, nothing changes.
+    pub fn update_settings(&mut self, settings: Settings) -> Result<()> {
+        let previous = self.settings();
+        if let Err(e) = self.apply_settings(settings) {
+            self.restore_settings(previous);
+            return Err(e);
+        }
+        self.save_settings()
+    }
+
+    /// Save the settings in force, e.g. after one was changed on its own
+    pub fn save_settings(&self) -> Result<()> {
+        self.persistence.save_setting(settings::SETTINGS_KEY, &self.settings())
+    }
+
+    /// Stops at the first invalid value, with the ones before it applied
+    fn apply_settings(&mut self, settings: Settings) -> Result<()> {
+        if settings.download_dir.as_ref().is_some_and(|dir| !dir.is_absolute()) {
+            anyhow::bail!("download_dir must be an absolute path");
+        }
+        // Only on a change: turning persistence on fails without a keychain,
+        // and either switch rewrites stored credentials
+        if settings.persist_credentials != self.persistence.persist_credentials() {
+            self.set_persist_credentials(settings.persist_credentials)?;
+        }
+        if settings.purge_finished_credentials != self.persistence.purge_finished_credentials() {
+            self.set_purge_finished_credentials(settings.purge_finished_credentials)?;
+        }
+        self.set_segment_defaults(settings.max_segments, settings.min_segment_size)?;
+        self.set_segment_strategy(settings.segment_strategy)?;
+        self.set_segment_resource_limits(settings.segments_per_core, settings.segment_memory_budget);
+        self.set_redirect_policy(settings.redirect_policy)?;
+        self.set_network_preferences(settings.local_address, settings.ip_preference)?;
+        self.set_write_buffer_size(settings.write_buffer_size)?;
+        self.set_segment_save_interval(Duration::from_millis(settings.segment_save_interval_ms))?;
+        self.set_default_proxy(settings.default_proxy)?;
+        self.set_category_rules(settings.category_rules)?;
+        self.set_user_agent_settings(settings.user_agents)?;
+        self.set_max_concurrent(settings.max_concurrent)?;
+        self.set_connection_limits(settings.max_connections, settings.max_connections_per_host)?;
+        self.download_dir = settings.download_dir;
+        self.set_max_size(settings.max_size);
+        self.set_preallocate(settings.preallocate);
+        self.set_notification_settings(settings.notifications);
+        self.set_sidecar_files(settings.sidecar_files);
+        self.set_allow_post_commands(settings.allow_post_commands);
+        Ok(())
+    }
+
+    /// Go back to settings known to be valid after applying others failed
+    fn restore_settings(&mut self, settings: Settings) {
+        if let Err(e) = self.apply_settings(settings) {
+            tracing::error!("Failed to restore the previous settings: {:#}", e);
+        }
+    }
+
+    /// The folder downloads go to unless they pick their own
+    fn default_download_dir(&self) -> Result<PathBuf> {
+        match &self.download_dir {
+            Some(dir) => Ok(dir.clone()),
+            None => self
+                .system_download_dir
+                .clone()
+                .context("Failed to get download directory"),
+        }
+    }
+
+    /// Keep a download's sidecar in step with its row: rewritten while it's
+    /// unfinished, removed once it completes or is cancelled without
+    /// keeping its partial
+    async fn sync_sidecar(&self, info: &DownloadInfo) {
+        match info.status {
+            DownloadStatus::Completed => sidecar::remove(&info.file_path),
+            DownloadStatus::Cancelled if !temp_path(&info.file_path).exists() => {
+                sidecar::remove(&info.file_path)
+            }
+            _ if self.sidecar_files.load(Ordering::Relaxed) => {
+                let segments = self.persistence.load_segments(&info.id).unwrap_or_default();
+                if let Err(e) = sidecar::write(info, &segments).await {
+                    tracing::warn!("Failed to write the sidecar of {}: {:#}", info.id, e);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn emit_download_update(&self, info: &DownloadInfo) {
+        let mut info = info.clone();
+
+        let status_change = {
+            let mut live = self.live.lock();
+            if let DownloadStatus::Downloading = info.status {
+                if !live.contains_key(&info.id) {
+                    self.emit_lifecycle("download-started", &info);
+                }
+                let progress = live.entry(info.id.clone()).or_default();
+                // Segments report independently; coalesce them into one event
+                // per interval. Status changes always go out.
+                if progress
+                    .speed
+                    .last_sample_at()
+                    .is_some_and(|at| at.elapsed() < PROGRESS_UPDATE_INTERVAL)
+                {
+                    return;
+                }
+                progress.speed.record(info.downloaded_size);
+                progress.downloaded = info.downloaded_size;
+                progress.total_size = info.total_size;
+                info.speed_bps = progress.speed.bytes_per_sec();
+                info.eta_secs = info.total_size.and_then(|total| {
+                    progress
+                        .speed
+                        .eta_secs(total.saturating_sub(info.downloaded_size))
+                });
+                false
+            } else {
+                live.remove(&info.id);
+                true
+            }
+        };
+        info.progress = info.fraction_done();
+        info.queue_position = self.queue_position(&info.id);
+        self.sync_sidecar(&info).await;
+
+        self.events.emit("download-update", &info);
+        if status_change {
+            let event = match info.status {
+                DownloadStatus::Queued => Some("download-queued"),
+                DownloadStatus::Verifying => Some("download-verifying"),
+                DownloadStatus::Paused => Some("download-paused"),
+                DownloadStatus::Completed => Some("download-completed"),
+                DownloadStatus::Failed(_) => Some("download-failed"),
+                DownloadStatus::Cancelled => Some("download-cancelled"),
+                _ => None,
+            };
+            if let Some(event) = event {
+                self.emit_lifecycle(event, &info);
+            }
+            let settings = *self.notifications.lock();
+            self.events.notify(settings, &info);
+        }
+        self.emit_summary(status_change);
+    }
+
+    /// A targeted event for a status transition, so the UI can notify on
+    /// the ones that matter without diffing `download-update`s
+    fn emit_lifecycle(&self, event: &str, info: &DownloadInfo) {
+        let payload = DownloadLifecycleEvent {
+            id: info.id.clone(),
+            file_name: info.file_name.clone(),
+            file_path: info.file_path.clone(),
+            error: match &info.status {
+                DownloadStatus::Failed(error) => Some(error.clone()),
+                _ => None,
+            },
+            checksum: info.checksum.clone(),
+        };
+        self.events.emit(event, &payload);
+    }
+
+    /// Aggregate progress across all downloads, e.g. for a tray icon.
+    /// Progress-only summaries share the per-download update interval.
+    fn emit_summary(&self, force: bool) {
+        {
+            let mut last = self.last_summary_at.lock();
+            if !force && last.is_some_and(|at| at.elapsed() < PROGRESS_UPDATE_INTERVAL) {
+                return;
+            }
+            *last = Some(Instant::now());
+        }
+
+        let live = self.live.lock();
+        let summary = DownloadsSummary {
+            active: live.len(),
+            queued: self.queue.waiting_count(),
+            downloaded: live.values().map(|p| p.downloaded).sum(),
+            total: live.values().filter_map(|p| p.total_size).sum(),
+            speed_bps: live.values().filter_map(|p| p.speed.bytes_per_sec()).sum(),
+        };
+        drop(live);
+
+        self.events.emit("downloads-summary", &summary);
+    }
+
+    /// One event for a bulk action, so the UI can refresh once
+    async fn emit_batch_update(&self, infos: &[DownloadInfo]) {
+        self.events.emit("downloads-batch-update", infos);
+    }
+
+    fn clone_for_task(&self) -> Self {
+        Self {
+            events: self.events.clone(),
+            persistence: self.persistence.clone(),
+            active_downloads: self.active_downloads.clone(),
+            queue: self.queue.clone(),
+            connections: self.connections.clone(),
+            transport: self.transport.clone(),
+            live: self.live.clone(),
+            last_summary_at: self.last_summary_at.clone(),
+            notifications: self.notifications.clone(),
+            allow_post_commands: self.allow_post_commands.clone(),
+            sidecar_files: self.sidecar_files.clone(),
+            category_rules: self.category_rules.clone(),
+            user_agents: self.user_agents.clone(),
+            speed_limit: self.speed_limit.clone(),
+            speed_schedule: self.speed_schedule.clone(),
+            max_retries: self.max_retries,
+            retry_backoff_base: self.retry_backoff_base,
+            default_proxy: self.default_proxy.clone(),
+            max_segments: self.max_segments,
+            min_segment_size: self.min_segment_size,
+            segments_per_core: self.segments_per_core,
+            segment_memory_budget: self.segment_memory_budget,
+            segment_strategy: self.segment_strategy,
+            preallocate: self.preallocate,
+            max_size: self.max_size,
+            write_buffer_size: self.write_buffer_size,
+            segment_save_interval: self.segment_save_interval,
+            redirect_policy: self.redirect_policy,
+            download_dir: self.download_dir.clone(),
+            system_download_dir: self.system_download_dir.clone(),
+            local_address: self.local_address,
+            ip_preference: self.ip_preference,
+        }
+    }
+}
+
+/// Plain HTTP(S) downloads, the default backend
+pub struct HttpBackend;
+
+#[async_trait]
+impl DownloadBackend for HttpBackend {
+    async fn download(&self, ctx: &DownloadCtx<'_>, control: ControlHandle) -> Result<()> {
+        ctx.manager.download_http(ctx.id, control.flag()).await
+    }
+}
+
+/// Lowercase hex digest of a file. Blocking; run it off the async runtime.
+pub(crate) fn hash_file(path: &Path, algo: HashAlgo) -> Result<String> {
+    use sha2::Digest;
+
+    fn digest<D: Digest>(path: &Path) -> Result<String> {
+        let mut file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open {} for hashing", path.display()))?;
+        let mut hasher = D::new();
+        let mut buf = vec![0u8; 1024 * 1024];
+        loop {
+            let n = std::io::Read::read(&mut file, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect())
+    }
+
+    match algo {
+        HashAlgo::Sha256 => digest::<sha2::Sha256>(path),
+        HashAlgo::Sha1 => digest::<sha1::Sha1>(path),
+        HashAlgo::Md5 => digest::<md5::Md5>(path),
+    }
+}
+
+/// Statuses of servers that don't answer HEAD like the GET that follows:
+/// no HEAD support at all, or signed URLs only valid for GET
+fn head_refused(status: reqwest::StatusCode) -> bool {
+    matches!(
+        status,
+        reqwest::StatusCode::METHOD_NOT_ALLOWED
+            | reqwest::StatusCode::NOT_IMPLEMENTED
+            | reqwest::StatusCode::FORBIDDEN
+    )
+}
+
+/// A mirror serves a different file than the download's primary URL
+#[derive(Debug)]
+struct MirrorMismatch(String);
+
+impl std::fmt::Display for MirrorMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for MirrorMismatch {}
+
+/// Sort a failure into a [`DownloadError`] by the first cause in its chain
+/// that says what went wrong. The message stays the top-level one.
+fn classify_error(error: &anyhow::Error) -> DownloadError {
+    let message = error.to_string();
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<DownloadError>() {
+            return e.clone();
+        }
+        if cause.is::<Throttled>() {
+            return DownloadError::Throttled { message };
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            if let Some(status) = e.status() {
+                return DownloadError::Http {
+                    status: status.as_u16(),
+                    message,
+                };
+            }
+            if e.is_timeout() {
+                return DownloadError::Timeout { message };
+            }
+            if e.is_connect() || e.is_request() || e.is_body() {
+                return DownloadError::Network { message };
+            }
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return match e.kind() {
+                std::io::ErrorKind::TimedOut => DownloadError::Timeout { message },
+                std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::UnexpectedEof => DownloadError::Network { message },
+                std::io::ErrorKind::StorageFull => DownloadError::DiskFull { message },
+                _ => DownloadError::Io { message },
+            };
+        }
+    }
+    DownloadError::Other { message }
+}
+
+/// Whether an error is the source's fault, so another mirror may do
+/// better: an HTTP error status, a stalled transfer, or a mismatched file
+fn is_mirror_failure(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_status() || e.is_timeout();
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return e.kind() == std::io::ErrorKind::TimedOut;
+        }
+        cause.is::<MirrorMismatch>() || cause.is::<Throttled>()
+    })
+}
+
+/// The server asked us to back off: a 429, or a 503 with `Retry-After`
+#[derive(Debug)]
+struct Throttled {
+    host: String,
+    status: reqwest::StatusCode,
+    retry_after: Option<Duration>,
+}
+
+impl Throttled {
+    fn from_response(response: &reqwest::Response) -> Option<Self> {
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        let throttled = status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            || (status == reqwest::StatusCode::SERVICE_UNAVAILABLE && retry_after.is_some());
+        throttled.then(|| Throttled {
+            host: response
+                .url()
+                .host_str()
+                .unwrap_or_default()
+                .to_ascii_lowercase(),
+            status,
+            retry_after,
+        })
+    }
+}
+
+impl std::fmt::Display for Throttled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is rate limiting downloads ({})", self.host, self.status)?;
+        if let Some(delay) = self.retry_after {
+            write!(f, ", retry after {}s", delay.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Throttled {}
+
+fn throttled(error: &anyhow::Error) -> Option<&Throttled> {
+    error.chain().find_map(|cause| cause.downcast_ref::<Throttled>())
+}
+
+/// `Retry-After` as either delay-seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+}
+
+/// `error_for_status`, with rate limiting reported as [`Throttled`] so it
+/// is retried after the delay the server asked for
+fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    if let Some(throttle) = Throttled::from_response(&response) {
+        return Err(throttle.into());
+    }
+    Ok(response.error_for_status()?)
+}
+
+/// A ranged request came back with something other than the requested
+/// bytes, so the server can't be used for segmented downloads
+#[derive(Debug)]
+struct RangeNotHonoured(String);
+
+impl std::fmt::Display for RangeNotHonoured {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RangeNotHonoured {}
+
+/// The server answered an `If-Range` request with the whole file: it is
+/// no longer the file the partial download was taken from
+#[derive(Debug)]
+struct RemoteChanged(String);
+
+impl std::fmt::Display for RemoteChanged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} changed on the server", self.0)
+    }
+}
+
+impl std::error::Error for RemoteChanged {}
+
+fn header_string(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Whether the validators in a fresh response say the file differs from
+/// the one recorded for the download. Missing validators prove nothing.
+fn validators_changed(info: &DownloadInfo, headers: &HeaderMap) -> bool {
+    let etag = header_string(headers, ETAG);
+    if let (Some(old), Some(new)) = (&info.etag, &etag) {
+        return old != new;
+    }
+    let last_modified = header_string(headers, LAST_MODIFIED);
+    matches!((&info.last_modified, &last_modified), (Some(old), Some(new)) if old != new)
+}
+
+/// A layout for a file whose first `prefix` bytes are already in place:
+/// one finished segment holding them, and the rest split `count` ways
+fn layout_after_prefix(prefix: u64, total_size: u64, count: usize) -> Vec<Segment> {
+    let mut segments = vec![Segment {
+        index: 0,
+        start: 0,
+        end: prefix - 1,
+        downloaded: prefix,
+    }];
+    let remaining = total_size - prefix;
+    let count = (count as u64).min(remaining);
+    let segment_size = remaining / count.max(1);
+    for i in 0..count {
+        let start = prefix + i * segment_size;
+        segments.push(Segment {
+            index: i as usize + 1,
+            start,
+            end: if i == count - 1 {
+                total_size - 1
+            } else {
+                start + segment_size - 1
+            },
+            downloaded: 0,
+        });
+    }
+    segments
+}
+
+/// What to send as `If-Range` when resuming: a strong ETag, else the
+/// Last-Modified date. Weak ETags aren't allowed there.
+fn if_range_validator(info: &DownloadInfo) -> Option<&str> {
+    info.etag
+        .as_deref()
+        .filter(|etag| !etag.starts_with("W/"))
+        .or(info.last_modified.as_deref())
+}
+
+fn too_large(size: u64, limit: u64) -> anyhow::Error {
+    DownloadError::TooLarge {
+        limit,
+        message: format!("exceeds max size: {} bytes, limit is {}", size, limit),
+    }
+    .into()
+}
+
+/// Size of the response body as announced in `Content-Length`
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok())
+}
+
+/// Whether the server says it serves byte ranges
+fn accepts_ranges(headers: &HeaderMap) -> bool {
+    headers
+        .get(ACCEPT_RANGES)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|s| s == "bytes")
+}
+
+/// The full size from a `Content-Range: bytes <start>-<end>/<size>` header
+fn content_range_total(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .rsplit_once('/')?
+        .1
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Make sure a response to `Range: bytes=<start>-<end>` is a 206 whose
+/// `Content-Range` covers exactly those bytes. Some servers advertise
+/// `Accept-Ranges` and then send the whole file anyway.
+fn check_content_range(response: &reqwest::Response, start: u64, end: u64) -> Result<()> {
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Err(RangeNotHonoured(format!(
+            "answered a ranged request with {}",
+            response.status()
+        ))
+        .into());
+    }
+
+    let content_range = response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let served = content_range
+        .strip_prefix("bytes ")
+        .and_then(|rest| rest.split('/').next())
+        .and_then(|range| range.split_once('-'))
+        .and_then(|(a, b)| Some((a.trim().parse::<u64>().ok()?, b.trim().parse::<u64>().ok()?)));
+    if served != Some((start, end)) {
+        return Err(RangeNotHonoured(format!(
+            "sent Content-Range '{}' for bytes {}-{}",
+            content_range, start, end
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Reserve `len` bytes for a file so the OS can lay it out contiguously
+/// and a full disk fails the download up front. Uses `fallocate` and its
+/// platform equivalents rather than a sparse `set_len`. Blocking.
+fn preallocate(path: &Path, len: u64) -> Result<()> {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    fs2::FileExt::allocate(&file, len)
+        .with_context(|| format!("Failed to reserve {} bytes for {}", len, path.display()))
+}
+
+/// `<file>.gripdl-tmp`, which a download writes to until it has completed
+/// and been verified. Only then is it renamed to `file_path`, so a file
+/// under its real name is always complete.
+pub(crate) fn temp_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+    name.push(TEMP_SUFFIX);
+    file_path.with_file_name(name)
+}
+
+/// The partial that a removed download of `url` left for `file_path`:
+/// its temp file, described by a sidecar naming the same URL. Without an
+/// ETag or Last-Modified to confirm the file with, it's not reused.
+async fn leftover_partial(file_path: &Path, url: &str) -> Option<sidecar::Partial> {
+    let on_disk = tokio::fs::metadata(temp_path(file_path)).await.ok()?.len();
+    let mut partial = sidecar::read(file_path).await?;
+    if partial.url != url || (partial.etag.is_none() && partial.last_modified.is_none()) {
+        return None;
+    }
+    // Segments write at their offsets, so only a prefix is measured by size
+    if partial.segments.is_empty() {
+        partial.downloaded_size = partial.downloaded_size.min(on_disk);
+    }
+    Some(partial)
+}
+
+/// Move bytes that an older version wrote straight to the target file
+/// over to the temp file, so the download can continue from them
+async fn adopt_in_place_partial(info: &DownloadInfo) -> Result<()> {
+    let temp = temp_path(&info.file_path);
+    if info.downloaded_size > 0 && !temp.exists() && info.file_path.is_file() {
+        tokio::fs::rename(&info.file_path, &temp).await?;
+    }
+    Ok(())
+}
+
+/// `<file>.part.<i>`, where older versions kept each segment until merging
+fn legacy_partial_path(file_path: &Path, index: usize) -> PathBuf {
+    let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".part.{}", index));
+    file_path.with_file_name(name)
+}
+
+fn has_legacy_partials(file_path: &Path) -> bool {
+    legacy_partial_path(file_path, 0).exists()
+}
+
+/// Remove the per-segment files an older version left behind
+async fn discard_legacy_partials(file_path: &Path, num_segments: usize) -> Result<()> {
+    for i in 0..num_segments {
+        remove_file_if_exists(&legacy_partial_path(file_path, i)).await?;
+    }
+    Ok(())
+}
+
+async fn remove_file_if_exists(path: &Path) -> Result<()> {
+    // Torrents download into folders, which may hold more than GripDL put
+    // there, so those are never removed wholesale
+    if path.is_dir() {
+        tracing::warn!("Leaving folder {} in place", path.display());
+        return Ok(());
+    }
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Failed to remove {}", path.display())),
+    }
+}
+
+/// A transfer that ended before delivering every byte. Reported as an
+/// unexpected EOF so it goes down the retry path.
+fn incomplete(got: u64, expected: u64) -> anyhow::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        format!("incomplete: got {} of {} bytes", got, expected),
+    )
+    .into()
+}
+
+/// Whether an error is a network hiccup worth retrying rather than a
+/// permanent failure
+fn is_transient(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(throttle) = cause.downcast_ref::<Throttled>() {
+            return throttle.retry_after.is_none_or(|d| d <= MAX_RETRY_AFTER);
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_timeout() || e.is_connect() || e.is_request() || e.is_body();
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return matches!(
+                e.kind(),
+                std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::UnexpectedEof
+            );
+        }
+        false
+    })
+}
+
+fn is_connect_error(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .any(|e| e.is_connect())
+}
+
+/// Strip credentials from a proxy URL so it can be logged or shown
+fn redact_proxy(proxy: &str) -> String {
+    match reqwest::Url::parse(proxy) {
+        Ok(mut url) => {
+            let _ = url.set_username("");
+            let _ = url.set_password(None);
+            url.to_string()
+        }
+        Err(_) => "<unparseable>".to_string(),
+    }
+}
+
+/// Sleep for `delay`, waking early if a pause/cancel is requested.
+/// Returns false if the wait was cut short.
+async fn sleep_unless_stopped(delay: Duration, stop: &AtomicBool) -> bool {
+    let step = Duration::from_millis(100);
+    let mut waited = Duration::ZERO;
+    while waited < delay {
+        if stop.load(Ordering::SeqCst) {
+            return false;
+        }
+        tokio::time::sleep(step.min(delay - waited)).await;
+        waited += step;
+    }
+    !stop.load(Ordering::SeqCst)
+}
+
+/// Stall watchdog for a response body: if no data arrives within
+/// `stall_timeout` the transfer is treated as a timed-out connection, which
+/// sends it down the retry path
+async fn next_chunk(
+    response: &mut reqwest::Response,
+    stall_timeout: Duration,
+) -> Result<Option<Bytes>> {
+    match tokio::time::timeout(stall_timeout, response.chunk()).await {
+        Ok(chunk) => Ok(chunk?),
+        Err(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("download stalled: no data for {}s", stall_timeout.as_secs()),
+        )
+        .into()),
+    }
+}
+
+/// Extract the filename from a Content-Disposition header value, preferring
+/// the RFC 5987 `filename*` form over plain `filename`
+fn filename_from_content_disposition(value: &str) -> Option<String> {
+    let mut plain = None;
+    let mut extended = None;
+
+    for (key, val) in content_disposition_params(value) {
+        match key.to_ascii_lowercase().as_str() {
+            "filename*" => {
+                // charset'language'percent-encoded-value
+                let mut parts = val.splitn(3, '\'');
+                if let (Some(charset), Some(_), Some(encoded)) =
+                    (parts.next(), parts.next(), parts.next())
+                {
+                    let bytes = percent_decode(encoded);
+                    extended = Some(if charset.eq_ignore_ascii_case("iso-8859-1") {
+                        bytes.iter().map(|&b| b as char).collect()
+                    } else {
+                        String::from_utf8_lossy(&bytes).into_owned()
+                    });
+                }
+            }
+            "filename" => plain = Some(val),
+            _ => {}
+        }
+    }
+
+    extended
+        .or(plain)
+        .map(|name| sanitize_filename(&name))
+        .filter(|name| !name.is_empty())
+}
+
+/// Split the `key=value` parameters that follow the disposition type,
+/// honoring quoted strings (which may themselves contain `;`)
+fn content_disposition_params(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = value.chars().peekable();
+
+    // Skip the disposition type ("attachment" / "inline")
+    for c in chars.by_ref() {
+        if c == ';' {
+            break;
+        }
+    }
+
+    loop {
+        let key: String = chars
+            .by_ref()
+            .skip_while(|c| c.is_whitespace() || *c == ';')
+            .take_while(|c| *c != '=')
+            .collect();
+        if key.is_empty() {
+            break;
+        }
+
+        let mut val = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => val.extend(chars.next()),
+                    '"' => break,
+                    c => val.push(c),
+                }
+            }
+            // Drop anything between the closing quote and the next parameter
+            for c in chars.by_ref() {
+                if c == ';' {
+                    break;
+                }
+            }
+        } else {
+            for c in chars.by_ref() {
+                if c == ';' {
+                    break;
+                }
+                val.push(c);
+            }
+        }
+
+        params.push((key.trim().to_string(), val.trim().to_string()));
+    }
+
+    params
+}
+
+/// Reject a URL that can't be downloaded before anything is recorded for
+/// it, with a reason the user can act on
+fn validate_url(url: &str) -> Result<()> {
+    if url.trim().is_empty() {
+        anyhow::bail!("The URL is empty");
+    }
+    if url.get(..7).is_some_and(|prefix| prefix.eq_ignore_ascii_case("magnet:")) {
+        if cfg!(feature = "torrent") {
+            return Ok(());
+        }
+        anyhow::bail!("Magnet links need a build with BitTorrent support");
+    }
+
+    let parsed = reqwest::Url::parse(url.trim())
+        .map_err(|e| anyhow::anyhow!("\"{}\" is not a valid URL ({})", url, e))?;
+    match parsed.scheme() {
+        "http" | "https" => {
+            if parsed.host_str().is_none_or(str::is_empty) {
+                anyhow::bail!("The URL has no host: {}", url);
+            }
+            Ok(())
+        }
+        "data" | "file" => LocalSource::parse(url).transpose().map(drop),
+        "ftp" | "ftps" | "sftp" => {
+            anyhow::bail!("{} downloads are not supported", parsed.scheme().to_ascii_uppercase())
+        }
+        "blob" => anyhow::bail!("blob: URLs only exist inside the page that created them"),
+        "javascript" | "mailto" | "tel" | "about" | "chrome" | "moz-extension" => {
+            anyhow::bail!("{}: links are not downloadable", parsed.scheme())
+        }
+        scheme => anyhow::bail!("Unsupported URL scheme {}:", scheme),
+    }
+}
+
+pub(crate) fn percent_decode(input: &str) -> Vec<u8> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    out
+}
+
+/// Make a server-supplied name safe to use as a single path component
+pub(crate) fn sanitize_filename(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '<' | '>' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    // Leading dots would hide the file or walk up the tree (".."); Windows
+    // drops trailing dots and spaces, so `a.` and `a` would be one file
+    let cleaned = cleaned
+        .trim()
+        .trim_start_matches('.')
+        .trim_end_matches(['.', ' '])
+        .trim_start();
+
+    // Windows opens a device for these names, whatever the extension
+    let stem = cleaned.split('.').next().unwrap_or_default().trim_end();
+    if is_reserved_windows_name(stem) {
+        format!("_{}", cleaned)
+    } else {
+        cleaned.to_string()
+    }
+}
+
+fn is_reserved_windows_name(stem: &str) -> bool {
+    let upper = stem.to_ascii_uppercase();
+    match upper.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => {
+            (upper.starts_with("COM") || upper.starts_with("LPT"))
+                && upper.len() == 4
+                && matches!(upper.as_bytes()[3], b'1'..=b'9')
+        }
+    }
+}
+
+/// Make sure downloads can be saved into `dir`, creating it and any
+/// missing parents
+fn ensure_writable_dir(dir: &Path) -> Result<()> {
+    if !dir.exists() {
+        if on_missing_volume(dir) {
+            anyhow::bail!("Cannot save to {}: its drive is not connected", dir.display());
+        }
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Cannot create the folder {}", dir.display()))?;
+    }
+    if !dir.is_dir() {
+        anyhow::bail!("Cannot save to {}: it is not a folder", dir.display());
+    }
+
+    // Permission bits don't tell the whole story (ACLs, read-only mounts),
+    // so try it for real
+    let probe = dir.join(format!(".gripdl-write-test-{}", Uuid::new_v4().simple()));
+    std::fs::File::create(&probe).with_context(|| {
+        format!(
+            "Cannot save to {}: the folder is read-only or you lack permission",
+            dir.display()
+        )
+    })?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(())
+}
+
+/// Where removable drives are mounted on macOS and Linux
+#[cfg(unix)]
+const VOLUME_ROOTS: [&str; 4] = ["/Volumes", "/media", "/run/media", "/mnt"];
+
+/// Whether the missing `dir` belongs on a drive that isn't mounted, so
+/// creating it would put it on the system drive instead. That's the case
+/// when the closest existing parent is on the same device as the folder
+/// drives are mounted under.
+#[cfg(unix)]
+fn on_missing_volume(dir: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let Some(root) = VOLUME_ROOTS.iter().map(Path::new).find(|root| dir.starts_with(root))
+    else {
+        return false;
+    };
+    let Some(existing) = dir.ancestors().find(|p| p.exists()) else {
+        return false;
+    };
+    match (std::fs::metadata(root), std::fs::metadata(existing)) {
+        (Ok(root), Ok(existing)) => root.dev() == existing.dev(),
+        _ => false,
+    }
+}
+
+/// A missing drive letter already fails `create_dir_all`
+#[cfg(not(unix))]
+fn on_missing_volume(_dir: &Path) -> bool {
+    false
+}
+
+/// Apply the conflict policy to a prospective target path
+pub(crate) fn resolve_target_path(path: &Path, policy: ConflictPolicy) -> Result<PathBuf> {
+    if !path.exists() {
+        return Ok(path.to_path_buf());
+    }
+
+    match policy {
+        ConflictPolicy::Overwrite => Ok(path.to_path_buf()),
+        ConflictPolicy::Rename => Ok(free_path(path, Path::exists)),
+        ConflictPolicy::Fail => anyhow::bail!("File already exists: {}", path.display()),
+    }
+}
+
+/// `path` if it's free, otherwise the first of `name (1).ext`,
+/// `name (2).ext`, ... that isn't taken
+pub(crate) fn free_path(path: &Path, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    if !taken(path) {
+        return path.to_path_buf();
+    }
+
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+
+    (1..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| !taken(candidate))
+        .expect("unbounded search always finds a free name")
+}
+
+/// Fail early if the volume holding `path` can't fit `needed` more bytes
+pub(crate) fn check_free_space(path: &Path, needed: u64) -> Result<()> {
+    let dir = path.parent().unwrap_or(path);
+    let available = match fs2::available_space(dir) {
+        Ok(available) => available,
+        Err(e) => {
+            // Not being able to tell shouldn't block the download
+            tracing::warn!("Could not determine free space in {}: {}", dir.display(), e);
+            return Ok(());
+        }
+    };
+
+    if available < needed {
+        return Err(DownloadError::DiskFull {
+            message: format!(
+                "insufficient disk space: {} bytes needed, {} available",
+                needed, available
+            ),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    const URL: &str = "https://example.com/file.bin";
+
+    /// Drops everything; the tests look at the database and files instead
+    struct NoEvents;
+
+    impl EventSink for NoEvents {
+        fn emit_value(&self, _event: &str, _payload: serde_json::Value) {}
+
+        fn notify(&self, _settings: NotificationSettings, _info: &DownloadInfo) {}
+    }
+
+    /// A manager with its own database and download folder under the
+    /// system's temp folder
+    fn test_manager(transport: Arc<MockTransport>) -> DownloadManager {
+        let dir = std::env::temp_dir().join(format!("gripdl-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let persistence = DownloadPersistence::open_at(dir.join("downloads.db"), None).unwrap();
+        DownloadManager::with_parts(Arc::new(NoEvents), persistence, transport, Some(dir))
+    }
+
+    fn test_file(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Start a download of `URL` and wait for its task to finish
+    async fn download(manager: &DownloadManager, options: DownloadOptions) -> DownloadInfo {
+        let id = manager
+            .start_download(URL.to_string(), None, None, None, None, None, options, None)
+            .await
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let info = manager.get_download_info(&id).await.unwrap();
+            let finished =
+                matches!(info.status, DownloadStatus::Completed | DownloadStatus::Failed(_));
+            if finished && !manager.active_downloads.lock().contains_key(&id) {
+                return info;
+            }
+            assert!(Instant::now() < deadline, "download stuck at {:?}", info.status);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Split even small files into `segments`
+    fn segmented(manager: &mut DownloadManager, segments: usize) -> DownloadOptions {
+        manager
+            .set_segment_strategy(SegmentStrategy {
+                single_below: 0,
+                medium_segments: segments,
+                ..SegmentStrategy::default()
+            })
+            .unwrap();
+        DownloadOptions {
+            max_segments: Some(segments),
+            min_segment_size: Some(1),
+            ..DownloadOptions::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn single_connection_download_completes() {
+        let file = test_file(100_000);
+        let mut transport = MockTransport::new(file.clone());
+        transport.advertise_ranges = false;
+        transport.honour_ranges = false;
+        let transport = Arc::new(transport);
+        let manager = test_manager(transport.clone());
+
+        let info = download(&manager, DownloadOptions::default()).await;
+
+        assert!(matches!(info.status, DownloadStatus::Completed), "{:?}", info.status);
+        assert_eq!(info.total_size, Some(file.len() as u64));
+        assert_eq!(std::fs::read(&info.file_path).unwrap(), file);
+        assert!(transport.requests.lock().contains(&"GET".to_string()));
+    }
+
+    #[tokio::test]
+    async fn segments_reassemble_the_file() {
+        let file = test_file(100_000);
+        let transport = Arc::new(MockTransport::new(file.clone()));
+        let mut manager = test_manager(transport.clone());
+        let options = segmented(&mut manager, 4);
+
+        let info = download(&manager, options).await;
+
+        assert!(matches!(info.status, DownloadStatus::Completed), "{:?}", info.status);
+        assert_eq!(transport.ranged_requests(), 4);
+        assert_eq!(std::fs::read(&info.file_path).unwrap(), file);
+    }
+
+    #[tokio::test]
+    async fn ignored_ranges_fall_back_to_one_connection() {
+        let file = test_file(100_000);
+        let mut transport = MockTransport::new(file.clone());
+        transport.honour_ranges = false;
+        let transport = Arc::new(transport);
+        let mut manager = test_manager(transport.clone());
+        let options = segmented(&mut manager, 4);
+
+        let info = download(&manager, options).await;
+
+        assert!(matches!(info.status, DownloadStatus::Completed), "{:?}", info.status);
+        assert_eq!(std::fs::read(&info.file_path).unwrap(), file);
+        let stats = manager.persistence.load_host_stats(Some("example.com")).unwrap();
+        assert!(host_stats::range_unreliable(&stats));
+    }
+
+    #[tokio::test]
+    async fn truncated_response_fails_the_download() {
+        let file = test_file(100_000);
+        let mut transport = MockTransport::new(file);
+        transport.advertise_ranges = false;
+        transport.honour_ranges = false;
+        transport.truncate_at = Some(60_000);
+        let mut manager = test_manager(Arc::new(transport));
+        manager.max_retries = 0;
+
+        let info = download(&manager, DownloadOptions::default()).await;
+
+        match info.status {
+            DownloadStatus::Failed(error) => {
+                assert!(error.to_string().contains("got 60000 of 100000 bytes"), "{}", error)
+            }
+            status => panic!("expected a failure, got {:?}", status),
+        }
+        assert_eq!(info.downloaded_size, 60_000);
+    }
+
+    #[test]
+    fn sanitize_filename_makes_windows_safe_names() {
+        assert_eq!(sanitize_filename("a<b>c:d\"e|f?g*h.txt"), "a_b_c_d_e_f_g_h.txt");
+        assert_eq!(sanitize_filename("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(sanitize_filename("report. . "), "report");
+        assert_eq!(sanitize_filename("CON"), "_CON");
+        assert_eq!(sanitize_filename("nul.txt"), "_nul.txt");
+        assert_eq!(sanitize_filename("Com1.tar.gz"), "_Com1.tar.gz");
+        assert_eq!(sanitize_filename("lpt9 .log"), "_lpt9 .log");
+        assert_eq!(sanitize_filename("COM10.txt"), "COM10.txt");
+        assert_eq!(sanitize_filename("console.log"), "console.log");
+        assert_eq!(sanitize_filename("..."), "");
+    }
+
+    #[test]
+    fn validate_url_handles_multibyte_prefixes() {
+        // Byte 7 falls inside the multibyte character in each of these
+        assert!(validate_url("data:,é").is_ok());
+        assert!(validate_url("ftp://é.example/file").is_err());
+        assert!(validate_url("magne€t:?xt=urn:btih:0").is_err());
+    }
+}
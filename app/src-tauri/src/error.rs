@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+/// Why a download failed, in a form the frontend can act on: `kind` says
+/// what went wrong (and whether retrying may help), `message` says it to
+/// the user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum DownloadError {
+    /// Couldn't connect, or the connection broke off
+    Network { message: String },
+    /// The server or proxy stopped responding
+    Timeout { message: String },
+    /// The server answered with an error status
+    Http { status: u16, message: String },
+    /// The server kept rate limiting us, or asked us to wait too long
+    Throttled { message: String },
+    /// Not enough room for the file
+    DiskFull { message: String },
+    /// Bigger than the download's maximum size
+    TooLarge { limit: u64, message: String },
+    /// Reading or writing a local file failed
+    Io { message: String },
+    /// The finished file doesn't match the expected digest
+    ChecksumMismatch { expected: String, actual: String },
+    /// Anything else: unsupported URLs or servers, bad playlists, ...
+    Other { message: String },
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Network { message }
+            | DownloadError::Timeout { message }
+            | DownloadError::Http { message, .. }
+            | DownloadError::Throttled { message }
+            | DownloadError::DiskFull { message }
+            | DownloadError::TooLarge { message, .. }
+            | DownloadError::Io { message }
+            | DownloadError::Other { message } => f.write_str(message),
+            DownloadError::ChecksumMismatch { expected, actual } => {
+                write!(f, "checksum mismatch: expected {} got {}", expected, actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
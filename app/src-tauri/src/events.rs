@@ -0,0 +1,34 @@
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+use crate::downloader::DownloadInfo;
+use crate::notifications::{self, NotificationSettings};
+
+/// Where the download manager reports to: events for the UI and desktop
+/// notifications. The app sends both through its `AppHandle`; anything
+/// else, like a test, can take them instead.
+pub trait EventSink: Send + Sync {
+    fn emit_value(&self, event: &str, payload: serde_json::Value);
+
+    /// Tell the user a download has completed or failed, if they want to know
+    fn notify(&self, settings: NotificationSettings, info: &DownloadInfo);
+}
+
+impl dyn EventSink {
+    pub fn emit<S: Serialize + ?Sized>(&self, event: &str, payload: &S) {
+        match serde_json::to_value(payload) {
+            Ok(payload) => self.emit_value(event, payload),
+            Err(e) => tracing::warn!("Failed to serialize the {} event: {}", event, e),
+        }
+    }
+}
+
+impl EventSink for AppHandle {
+    fn emit_value(&self, event: &str, payload: serde_json::Value) {
+        let _ = Emitter::emit(self, event, payload);
+    }
+
+    fn notify(&self, settings: NotificationSettings, info: &DownloadInfo) {
+        notifications::notify(self, settings, info);
+    }
+}
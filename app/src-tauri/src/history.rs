@@ -0,0 +1,46 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::downloader::{DownloadInfo, Segment};
+
+/// Bumped when the file layout changes incompatibly
+const FORMAT_VERSION: u32 = 1;
+
+/// A download history file, as written by `export_downloads`
+#[derive(Serialize, Deserialize)]
+struct HistoryFile {
+    version: u32,
+    downloads: Vec<ExportedDownload>,
+}
+
+/// A download with the segment state needed to resume it
+#[derive(Serialize, Deserialize)]
+pub struct ExportedDownload {
+    #[serde(flatten)]
+    pub info: DownloadInfo,
+    #[serde(default)]
+    pub segments: Vec<Segment>,
+}
+
+pub fn write(path: &Path, downloads: Vec<ExportedDownload>) -> Result<()> {
+    let file = HistoryFile {
+        version: FORMAT_VERSION,
+        downloads,
+    };
+    let json = serde_json::to_vec_pretty(&file)?;
+    std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+pub fn read(path: &Path) -> Result<Vec<ExportedDownload>> {
+    let json = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let file: HistoryFile =
+        serde_json::from_slice(&json).context("Not a GripDL download history file")?;
+    if file.version > FORMAT_VERSION {
+        anyhow::bail!(
+            "The history file is from a newer version of GripDL (format {})",
+            file.version
+        );
+    }
+    Ok(file.downloads)
+}
@@ -0,0 +1,298 @@
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use anyhow::{bail, Context, Result};
+use reqwest::Url;
+
+/// MIME types servers use for `.m3u8` playlists
+const PLAYLIST_CONTENT_TYPES: [&str; 3] = [
+    "application/vnd.apple.mpegurl",
+    "application/x-mpegurl",
+    "audio/mpegurl",
+];
+
+pub enum Playlist {
+    /// Lists the same stream at several bitrates
+    Master(Vec<Variant>),
+    Media(MediaPlaylist),
+}
+
+pub struct Variant {
+    pub bandwidth: u64,
+    pub uri: Url,
+}
+
+pub struct MediaPlaylist {
+    /// `#EXT-X-MAP` initialization section, for fragmented MP4 streams
+    pub init: Option<Url>,
+    pub segments: Vec<MediaSegment>,
+    /// Whether the playlist is complete (`#EXT-X-ENDLIST`); live streams
+    /// keep growing
+    pub ended: bool,
+}
+
+pub struct MediaSegment {
+    pub uri: Url,
+    pub key: Option<Key>,
+}
+
+/// AES-128 key for a run of segments
+#[derive(Clone)]
+pub struct Key {
+    pub uri: Url,
+    pub iv: [u8; 16],
+}
+
+/// Whether a response looks like an HLS playlist, by content type or by
+/// the `.m3u8` extension
+pub fn is_playlist(url: &Url, content_type: Option<&str>) -> bool {
+    let by_type = content_type
+        .and_then(|ct| ct.split(';').next())
+        .map(|ct| ct.trim().to_ascii_lowercase())
+        .is_some_and(|ct| PLAYLIST_CONTENT_TYPES.contains(&ct.as_str()));
+    by_type || url.path().to_ascii_lowercase().ends_with(".m3u8")
+}
+
+/// Parse a playlist fetched from `base`, which relative URIs resolve against
+pub fn parse(text: &str, base: &Url) -> Result<Playlist> {
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+    if lines.next() != Some("#EXTM3U") {
+        bail!("Not an HLS playlist (missing #EXTM3U)");
+    }
+
+    let mut variants = Vec::new();
+    let mut pending_bandwidth = None;
+
+    let mut media = MediaPlaylist {
+        init: None,
+        segments: Vec::new(),
+        ended: false,
+    };
+    let mut sequence: u64 = 0;
+    let mut key: Option<(Url, Option<[u8; 16]>)> = None;
+
+    for line in lines {
+        if let Some(attrs) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            let bandwidth = attribute(attrs, "BANDWIDTH")
+                .and_then(|b| b.parse().ok())
+                .unwrap_or(0);
+            pending_bandwidth = Some(bandwidth);
+        } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            sequence = value.parse().context("Invalid #EXT-X-MEDIA-SEQUENCE")?;
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-KEY:") {
+            key = match attribute(attrs, "METHOD").as_deref() {
+                Some("NONE") => None,
+                Some("AES-128") => {
+                    let uri = attribute(attrs, "URI").context("#EXT-X-KEY without a URI")?;
+                    let iv = attribute(attrs, "IV").map(|iv| parse_iv(&iv)).transpose()?;
+                    Some((base.join(&uri)?, iv))
+                }
+                Some(method) => bail!("Unsupported HLS encryption method {}", method),
+                None => bail!("#EXT-X-KEY without a METHOD"),
+            };
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-MAP:") {
+            if attribute(attrs, "BYTERANGE").is_some() {
+                bail!("Byte-range HLS initialization sections are not supported");
+            }
+            let uri = attribute(attrs, "URI").context("#EXT-X-MAP without a URI")?;
+            media.init = Some(base.join(&uri)?);
+        } else if line.starts_with("#EXT-X-BYTERANGE") {
+            bail!("Byte-range HLS segments are not supported");
+        } else if line == "#EXT-X-ENDLIST" {
+            media.ended = true;
+        } else if line.starts_with('#') {
+            // Other tags (durations, discontinuities, ...) don't affect
+            // which bytes make up the file
+        } else if let Some(bandwidth) = pending_bandwidth.take() {
+            variants.push(Variant {
+                bandwidth,
+                uri: base.join(line)?,
+            });
+        } else {
+            // Without an explicit IV, a segment's IV is its sequence number
+            let key = key.as_ref().map(|(uri, iv)| Key {
+                uri: uri.clone(),
+                iv: iv.unwrap_or_else(|| u128::from(sequence).to_be_bytes()),
+            });
+            media.segments.push(MediaSegment {
+                uri: base.join(line)?,
+                key,
+            });
+            sequence += 1;
+        }
+    }
+
+    if !variants.is_empty() {
+        return Ok(Playlist::Master(variants));
+    }
+    Ok(Playlist::Media(media))
+}
+
+/// The highest-bandwidth variant of a master playlist
+pub fn best_variant(variants: &[Variant]) -> Option<&Variant> {
+    variants.iter().max_by_key(|v| v.bandwidth)
+}
+
+/// Decrypt one AES-128-CBC segment
+pub fn decrypt(data: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Result<Vec<u8>> {
+    cbc::Decryptor::<aes::Aes128>::new(key.into(), iv.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(data)
+        .map_err(|_| anyhow::anyhow!("Failed to decrypt HLS segment (wrong key?)"))
+}
+
+/// Value of `name` in an attribute list like `BANDWIDTH=1280000,URI="a,b"`
+fn attribute(attrs: &str, name: &str) -> Option<String> {
+    let mut rest = attrs;
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once('=')?;
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                let next = quoted[end + 1..].trim_start_matches(',');
+                (&quoted[..end], next)
+            }
+            None => match after.split_once(',') {
+                Some((value, next)) => (value, next),
+                None => (after, ""),
+            },
+        };
+        if key.trim().eq_ignore_ascii_case(name) {
+            return Some(value.to_string());
+        }
+        rest = next;
+    }
+    None
+}
+
+fn parse_iv(value: &str) -> Result<[u8; 16]> {
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    let iv = u128::from_str_radix(hex, 16).with_context(|| format!("Invalid IV {}", value))?;
+    Ok(iv.to_be_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://example.com/video/index.m3u8").unwrap()
+    }
+
+    fn media(text: &str) -> MediaPlaylist {
+        match parse(text, &base()).unwrap() {
+            Playlist::Media(media) => media,
+            Playlist::Master(_) => panic!("expected a media playlist"),
+        }
+    }
+
+    #[test]
+    fn parses_master_playlists() {
+        let text = "#EXTM3U\n\
+                    #EXT-X-STREAM-INF:BANDWIDTH=800000,CODECS=\"avc1.4d401e,mp4a.40.2\"\n\
+                    low/index.m3u8\n\
+                    #EXT-X-STREAM-INF:BANDWIDTH=2400000,RESOLUTION=1280x720\n\
+                    high/index.m3u8\n";
+        let Playlist::Master(variants) = parse(text, &base()).unwrap() else {
+            panic!("expected a master playlist");
+        };
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants[0].bandwidth, 800000);
+        let best = best_variant(&variants).unwrap();
+        assert_eq!(
+            best.uri.as_str(),
+            "https://example.com/video/high/index.m3u8"
+        );
+    }
+
+    #[test]
+    fn parses_media_playlists() {
+        let media = media(
+            "#EXTM3U\n\
+             #EXT-X-TARGETDURATION:10\n\
+             #EXT-X-MAP:URI=\"init.mp4\"\n\
+             #EXTINF:10.0,\n\
+             seg0.m4s\n\
+             #EXTINF:10.0,\n\
+             https://cdn.example.com/seg1.m4s\n\
+             #EXT-X-ENDLIST\n",
+        );
+        assert_eq!(
+            media.init.unwrap().as_str(),
+            "https://example.com/video/init.mp4"
+        );
+        let uris: Vec<_> = media.segments.iter().map(|s| s.uri.as_str()).collect();
+        assert_eq!(
+            uris,
+            [
+                "https://example.com/video/seg0.m4s",
+                "https://cdn.example.com/seg1.m4s"
+            ]
+        );
+        assert!(media.segments.iter().all(|s| s.key.is_none()));
+        assert!(media.ended);
+    }
+
+    #[test]
+    fn iv_defaults_to_the_media_sequence() {
+        let media = media(
+            "#EXTM3U\n\
+             #EXT-X-MEDIA-SEQUENCE:7\n\
+             #EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"\n\
+             seg7.ts\n\
+             seg8.ts\n\
+             #EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0x000102030405060708090A0B0C0D0E0F\n\
+             seg9.ts\n\
+             #EXT-X-KEY:METHOD=NONE\n\
+             seg10.ts\n",
+        );
+        let ivs: Vec<_> = media
+            .segments
+            .iter()
+            .map(|s| s.key.as_ref().map(|k| k.iv))
+            .collect();
+        assert_eq!(ivs[0], Some(7u128.to_be_bytes()));
+        assert_eq!(ivs[1], Some(8u128.to_be_bytes()));
+        assert_eq!(ivs[2], Some(std::array::from_fn(|i| i as u8)));
+        assert_eq!(ivs[3], None);
+        let key = media.segments[0].key.as_ref().unwrap();
+        assert_eq!(key.uri.as_str(), "https://example.com/video/key.bin");
+        assert!(!media.ended);
+    }
+
+    #[test]
+    fn rejects_byte_ranges() {
+        let segment = "#EXTM3U\n#EXT-X-BYTERANGE:1000@0\nall.ts\n";
+        assert!(parse(segment, &base()).is_err());
+        let init = "#EXTM3U\n#EXT-X-MAP:URI=\"all.mp4\",BYTERANGE=\"720@0\"\nall.mp4\n";
+        assert!(parse(init, &base()).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_encryption_and_non_playlists() {
+        let sample_aes = "#EXTM3U\n#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"key\"\nseg.ts\n";
+        assert!(parse(sample_aes, &base()).is_err());
+        assert!(parse("<html></html>", &base()).is_err());
+    }
+
+    #[test]
+    fn attribute_handles_quoted_commas() {
+        let attrs = "BANDWIDTH=1280000,CODECS=\"avc1.4d401e,mp4a.40.2\",URI=\"a,b.m3u8\"";
+        assert_eq!(attribute(attrs, "bandwidth").as_deref(), Some("1280000"));
+        assert_eq!(
+            attribute(attrs, "CODECS").as_deref(),
+            Some("avc1.4d401e,mp4a.40.2")
+        );
+        assert_eq!(attribute(attrs, "URI").as_deref(), Some("a,b.m3u8"));
+        assert_eq!(attribute(attrs, "IV"), None);
+    }
+
+    #[test]
+    fn parse_iv_accepts_hex_with_or_without_prefix() {
+        let expected = 0xABCDu128.to_be_bytes();
+        assert_eq!(parse_iv("0xABCD").unwrap(), expected);
+        assert_eq!(parse_iv("0Xabcd").unwrap(), expected);
+        assert_eq!(parse_iv("abcd").unwrap(), expected);
+        assert!(parse_iv("0xnothex").is_err());
+    }
+}
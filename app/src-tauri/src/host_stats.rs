@@ -0,0 +1,64 @@
+use serde::Serialize;
+
+/// Transfers smaller than this say more about latency than throughput and
+/// aren't recorded
+pub const MIN_SAMPLE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Weight of a new sample in a segment count's moving average
+pub const SAMPLE_WEIGHT: f64 = 0.3;
+
+/// Segment counts this close to the best throughput count as just as fast
+const GOOD_ENOUGH: f64 = 0.9;
+
+/// Samples of a segment count before half as many is tried
+const EXPLORE_AFTER: u64 = 3;
+
+/// How downloads from a host performed with a given number of segments
+#[derive(Debug, Clone, Serialize)]
+pub struct HostStats {
+    pub host: String,
+    pub segments: usize,
+    /// Completed transfers averaged into `avg_bps`
+    pub samples: u64,
+    /// Moving average of the aggregate transfer rate
+    pub avg_bps: f64,
+    /// Times the host advertised Range support but ignored it with this
+    /// many segments
+    pub range_failures: u64,
+    pub updated_at: i64,
+}
+
+/// Segment count to start a download from this host with: the fewest that
+/// came within 10% of the best throughput seen. Once that count has been
+/// seen a few times, half as many is tried, which is easier on the server
+/// and often just as fast. `None` without any data.
+pub fn preferred_segments(stats: &[HostStats]) -> Option<usize> {
+    let measured: Vec<&HostStats> = stats.iter().filter(|s| s.samples > 0).collect();
+    let best = measured.iter().map(|s| s.avg_bps).fold(0.0, f64::max);
+    if best <= 0.0 {
+        return None;
+    }
+
+    let chosen = measured
+        .iter()
+        .filter(|s| s.avg_bps >= best * GOOD_ENOUGH)
+        .min_by_key(|s| s.segments)?;
+    let half = chosen.segments / 2;
+    if half >= 1 && chosen.samples >= EXPLORE_AFTER && !measured.iter().any(|s| s.segments == half)
+    {
+        return Some(half);
+    }
+    Some(chosen.segments)
+}
+
+/// Whether the host has ignored Range requests more often than it has
+/// honoured them, so segmenting is likely wasted
+pub fn range_unreliable(stats: &[HostStats]) -> bool {
+    let failures: u64 = stats.iter().map(|s| s.range_failures).sum();
+    let successes: u64 = stats
+        .iter()
+        .filter(|s| s.segments > 1)
+        .map(|s| s.samples)
+        .sum();
+    failures > successes
+}
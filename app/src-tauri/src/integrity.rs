@@ -0,0 +1,58 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::downloader::{hash_file, HashAlgo};
+
+/// Files of a library re-hashed at the same time
+pub const VERIFY_CONCURRENCY: usize = 2;
+
+/// How a completed download's file compares to the checksum recorded when
+/// it finished
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "result")]
+pub enum Verification {
+    Ok,
+    Mismatch { expected: String, actual: String },
+    Missing,
+    /// Nothing to compare against: a folder, or a download finished before
+    /// checksums were recorded
+    NoChecksum,
+    Unreadable { message: String },
+}
+
+/// The outcome for one download, from `verify_download` or `verify_all`
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub id: String,
+    pub file_path: PathBuf,
+    #[serde(flatten)]
+    pub verification: Verification,
+}
+
+/// Re-hash `path` with the algorithm `checksum` (`algo:hex`) was made
+/// with. Blocking; run it off the async runtime.
+pub fn verify(path: &Path, checksum: Option<&str>) -> Verification {
+    if !path.exists() {
+        return Verification::Missing;
+    }
+    let Some((algo, expected)) = checksum
+        .and_then(|checksum| checksum.split_once(':'))
+        .and_then(|(prefix, hex)| Some((HashAlgo::from_prefix(prefix)?, hex)))
+    else {
+        return Verification::NoChecksum;
+    };
+    if path.is_dir() {
+        return Verification::NoChecksum;
+    }
+
+    match hash_file(path, algo) {
+        Ok(actual) if actual.eq_ignore_ascii_case(expected) => Verification::Ok,
+        Ok(actual) => Verification::Mismatch {
+            expected: expected.to_ascii_lowercase(),
+            actual,
+        },
+        Err(e) => Verification::Unreadable {
+            message: format!("{:#}", e),
+        },
+    }
+}
@@ -1,6 +1,39 @@
 // Re-export for use as library if needed
+pub mod browser_manifest;
+pub mod backend;
+pub mod category;
+pub mod connections;
+pub mod control_server;
+pub mod cookies;
 pub mod downloader;
+pub mod error;
+pub mod events;
+pub mod history;
+pub mod hls;
+pub mod host_stats;
+pub mod integrity;
+pub mod local_source;
+pub mod logging;
+pub mod network;
 pub mod native_messaging;
+pub mod notifications;
+pub mod opener;
 pub mod persistence;
+pub mod post_action;
+pub mod queue;
+pub mod rate_limit;
+pub mod redirects;
+pub mod scheduler;
+pub mod segment_strategy;
+pub mod secrets;
+pub mod settings;
+pub mod sidecar;
+pub mod speed;
+pub mod speed_schedule;
 pub mod state;
+pub mod statistics;
+pub mod transport;
+pub mod user_agent;
+#[cfg(feature = "torrent")]
+pub mod torrent;
 
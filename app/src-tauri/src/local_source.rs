@@ -0,0 +1,195 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::backend::{ControlHandle, DownloadBackend, DownloadCtx};
+use crate::downloader::{
+    check_free_space, percent_decode, temp_path, DownloadStatus, PROGRESS_UPDATE_INTERVAL,
+};
+
+/// Read size when copying a `file://` source
+const COPY_CHUNK: usize = 256 * 1024;
+
+/// A download URL whose content is available without a network request
+pub enum LocalSource {
+    /// `data:` URI, already decoded
+    Data { mime: String, bytes: Vec<u8> },
+    /// `file://` URL
+    File(PathBuf),
+}
+
+impl LocalSource {
+    /// `None` for URLs that have to be fetched over the network
+    pub fn parse(url: &str) -> Option<Result<Self>> {
+        let (scheme, rest) = url.split_once(':')?;
+        if scheme.eq_ignore_ascii_case("data") {
+            Some(decode_data_url(rest))
+        } else if scheme.eq_ignore_ascii_case("file") {
+            Some(
+                reqwest::Url::parse(url)
+                    .ok()
+                    .and_then(|u| u.to_file_path().ok())
+                    .map(LocalSource::File)
+                    .with_context(|| format!("Invalid file URL {}", url)),
+            )
+        } else {
+            None
+        }
+    }
+
+    /// Name to save the content under when the user didn't pick one
+    pub fn file_name(&self) -> Option<String> {
+        match self {
+            LocalSource::Data { mime, .. } => {
+                extension_for(mime).map(|ext| format!("data.{}", ext))
+            }
+            LocalSource::File(path) => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+        }
+    }
+}
+
+/// Writes out `data:` URIs and copies `file://` sources
+pub struct LocalBackend;
+
+#[async_trait]
+impl DownloadBackend for LocalBackend {
+    async fn download(&self, ctx: &DownloadCtx<'_>, control: ControlHandle) -> Result<()> {
+        let mut info = ctx.info().await?;
+        let source = LocalSource::parse(&info.url).context("Not a data: or file: URL")??;
+
+        let total_size = match &source {
+            LocalSource::Data { bytes, .. } => bytes.len() as u64,
+            LocalSource::File(path) => {
+                let metadata = tokio::fs::metadata(path)
+                    .await
+                    .with_context(|| format!("Cannot read {}", path.display()))?;
+                if !metadata.is_file() {
+                    anyhow::bail!("{} is not a file", path.display());
+                }
+                // An overwrite policy must not truncate the source itself
+                if let Ok(target) = tokio::fs::canonicalize(&info.file_path).await {
+                    if tokio::fs::canonicalize(path).await? == target {
+                        anyhow::bail!("{} is already the target file", path.display());
+                    }
+                }
+                metadata.len()
+            }
+        };
+        check_free_space(&info.file_path, total_size)?;
+
+        info.total_size = Some(total_size);
+        info.downloaded_size = 0;
+        info.status = DownloadStatus::Downloading;
+        ctx.report(&info).await?;
+
+        match source {
+            LocalSource::Data { bytes, .. } => {
+                tokio::fs::write(temp_path(&info.file_path), &bytes).await?;
+            }
+            LocalSource::File(path) => {
+                // Copied by hand rather than with tokio::fs::copy so progress
+                // is reported and a pause takes effect mid-file. A resumed
+                // copy starts over; it's local and cheap.
+                let mut src = File::open(&path)
+                    .await
+                    .with_context(|| format!("Cannot read {}", path.display()))?;
+                let mut dst = File::create(temp_path(&info.file_path)).await?;
+                let mut buf = vec![0u8; COPY_CHUNK];
+                let mut copied = 0u64;
+                let mut last_update = Instant::now();
+                loop {
+                    let n = src.read(&mut buf).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    dst.write_all(&buf[..n]).await?;
+                    copied += n as u64;
+
+                    let stopped = control.is_stopped();
+                    if stopped || last_update.elapsed() >= PROGRESS_UPDATE_INTERVAL {
+                        last_update = Instant::now();
+                        info.downloaded_size = copied;
+                        info.updated_at = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs() as i64;
+                        ctx.report(&info).await?;
+                    }
+                    if stopped {
+                        return Ok(());
+                    }
+                }
+                dst.flush().await?;
+            }
+        }
+
+        info.downloaded_size = total_size;
+        ctx.report(&info).await
+    }
+
+    fn file_name(&self, url: &str) -> Option<String> {
+        LocalSource::parse(url)?.ok()?.file_name()
+    }
+}
+
+/// Decode the part of a `data:` URI after the scheme:
+/// `[<mediatype>][;base64],<data>`
+fn decode_data_url(rest: &str) -> Result<LocalSource> {
+    let (meta, payload) = rest
+        .split_once(',')
+        .context("Malformed data URL: missing ','")?;
+
+    let mut params = meta.split(';');
+    let mime = params
+        .next()
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .unwrap_or("text/plain")
+        .to_ascii_lowercase();
+    let is_base64 = params.any(|p| p.trim().eq_ignore_ascii_case("base64"));
+
+    let decoded = percent_decode(payload);
+    let bytes = if is_base64 {
+        // Browsers tolerate line breaks and spaces inside the payload
+        let compact: Vec<u8> = decoded
+            .into_iter()
+            .filter(|b| !b.is_ascii_whitespace())
+            .collect();
+        base64::engine::general_purpose::STANDARD
+            .decode(&compact)
+            .or_else(|_| base64::engine::general_purpose::STANDARD_NO_PAD.decode(&compact))
+            .context("Malformed data URL: invalid base64")?
+    } else {
+        decoded
+    };
+
+    Ok(LocalSource::Data { mime, bytes })
+}
+
+fn extension_for(mime: &str) -> Option<&'static str> {
+    Some(match mime {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/svg+xml" => "svg",
+        "image/bmp" => "bmp",
+        "image/x-icon" | "image/vnd.microsoft.icon" => "ico",
+        "text/plain" => "txt",
+        "text/html" => "html",
+        "text/css" => "css",
+        "text/csv" => "csv",
+        "application/json" => "json",
+        "application/pdf" => "pdf",
+        "application/zip" => "zip",
+        "audio/mpeg" => "mp3",
+        "video/mp4" => "mp4",
+        _ => return None,
+    })
+}
@@ -0,0 +1,41 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter};
+
+/// Days of logs kept; older files are deleted as new ones start
+const MAX_LOG_FILES: usize = 7;
+
+/// Where the log files go inside the app data directory
+pub fn log_dir(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join("logs")
+}
+
+/// Log to stderr and to a file in `dir` that starts afresh every day.
+/// `RUST_LOG` filters both. Without a usable `dir`, logs go to stderr only.
+pub fn init(dir: Option<&Path>) {
+    let file = dir.and_then(|dir| match file_appender(dir) {
+        Ok(appender) => Some(appender),
+        Err(e) => {
+            eprintln!("Logging to stderr only: {:#}", e);
+            None
+        }
+    });
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(fmt::layer())
+        .with(file.map(|appender| fmt::layer().with_ansi(false).with_writer(appender)))
+        .init();
+}
+
+fn file_appender(dir: &Path) -> Result<RollingFileAppender> {
+    Ok(RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("gripdl")
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(dir)?)
+}
@@ -1,28 +1,102 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod backend;
+mod category;
+mod connections;
+mod control_server;
+mod cookies;
 mod downloader;
-mod native_messaging;
+mod error;
+mod events;
+mod history;
+mod hls;
+mod host_stats;
+mod integrity;
+mod local_source;
+mod logging;
+mod network;
+mod notifications;
+mod opener;
 mod persistence;
+mod post_action;
+mod queue;
+mod rate_limit;
+mod redirects;
+mod scheduler;
+mod segment_strategy;
+mod secrets;
+mod settings;
+mod sidecar;
+mod speed;
+mod speed_schedule;
 mod state;
+mod statistics;
+mod transport;
+mod user_agent;
+#[cfg(feature = "torrent")]
+mod torrent;
 
 use downloader::DownloadManager;
-use native_messaging::NativeMessagingHost;
 use state::AppState;
-use tauri::{Manager, State};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{Manager, RunEvent, State};
 use tokio::sync::RwLock;
 
+/// Set once quitting has started
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn start_download(
     url: String,
     cookies: Option<String>,
+    cookies_structured: Option<Vec<cookies::CookieSpec>>,
     referrer: Option<String>,
     user_agent: Option<String>,
+    headers: Option<HashMap<String, String>>,
+    options: Option<downloader::DownloadOptions>,
+    start_at: Option<i64>,
     state: State<'_, AppState>,
 ) -> Result<String, String> {
     let manager = state.download_manager.read().await;
     manager
-        .start_download(url, cookies, referrer, user_agent)
+        .start_download(
+            url,
+            cookies,
+            cookies_structured,
+            referrer,
+            user_agent,
+            headers,
+            options.unwrap_or_default(),
+            start_at,
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn probe_url(
+    url: String,
+    cookies: Option<String>,
+    cookies_structured: Option<Vec<cookies::CookieSpec>>,
+    referrer: Option<String>,
+    user_agent: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<downloader::ProbeResult, String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .probe_url(
+            &url,
+            cookies.as_deref(),
+            cookies_structured.as_deref(),
+            referrer.as_deref(),
+            user_agent.as_deref(),
+        )
         .await
         .map_err(|e| e.to_string())
 }
@@ -33,6 +107,31 @@ async fn pause_download(id: String, state: State<'_, AppState>) -> Result<(), St
     manager.pause_download(&id).await.map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn set_download_state(
+    id: String,
+    action: downloader::DownloadAction,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .set_download_state(&id, action)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn pause_all(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    let manager = state.download_manager.read().await;
+    manager.pause_all().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn resume_all(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    let manager = state.download_manager.read().await;
+    manager.resume_all().await.map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn resume_download(id: String, state: State<'_, AppState>) -> Result<(), String> {
     let manager = state.download_manager.read().await;
@@ -40,60 +139,582 @@ async fn resume_download(id: String, state: State<'_, AppState>) -> Result<(), S
 }
 
 #[tauri::command]
-async fn cancel_download(id: String, state: State<'_, AppState>) -> Result<(), String> {
+async fn cancel_download(
+    id: String,
+    keep_partial: Option<bool>,
+    wait: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
     let manager = state.download_manager.read().await;
-    manager.cancel_download(&id).await.map_err(|e| e.to_string())
+    manager
+        .cancel_download(&id, keep_partial.unwrap_or(false), wait.unwrap_or(false))
+        .await
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn get_downloads(state: State<'_, AppState>) -> Result<Vec<downloader::DownloadInfo>, String> {
+async fn stop_and_keep(id: String, state: State<'_, AppState>) -> Result<(), String> {
     let manager = state.download_manager.read().await;
-    Ok(manager.get_all_downloads().await)
+    manager.stop_and_keep(&id).await.map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn get_download_info(
+async fn cancel_and_discard(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager.cancel_and_discard(&id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_downloads(path: PathBuf, state: State<'_, AppState>) -> Result<usize, String> {
+    let manager = state.download_manager.read().await;
+    manager.export_downloads(&path).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn import_downloads(path: PathBuf, state: State<'_, AppState>) -> Result<usize, String> {
+    let manager = state.download_manager.read().await;
+    manager.import_downloads(&path).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn compact_database(state: State<'_, AppState>) -> Result<u64, String> {
+    let manager = state.download_manager.read().await;
+    manager.compact_database().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn open_file(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager.open_file(&id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn open_containing_folder(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .open_containing_folder(&id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn clear_completed(
+    remove_files: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<usize, String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .clear_completed(remove_files.unwrap_or(false))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn retry_download(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager.retry_download(&id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn move_download(
     id: String,
+    new_dir: PathBuf,
     state: State<'_, AppState>,
 ) -> Result<downloader::DownloadInfo, String> {
     let manager = state.download_manager.read().await;
     manager
+        .move_download(&id, &new_dir)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn verify_download(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<integrity::VerifyReport, String> {
+    let manager = state.download_manager.read().await;
+    manager.verify_download(&id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn verify_all(state: State<'_, AppState>) -> Result<Vec<integrity::VerifyReport>, String> {
+    let manager = state.download_manager.read().await;
+    manager.verify_all().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_download(
+    id: String,
+    delete_file: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .delete_download(&id, delete_file.unwrap_or(false))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_downloads(
+    query: Option<persistence::DownloadQuery>,
+    state: State<'_, AppState>,
+) -> Result<persistence::DownloadPage, String> {
+    let manager = state.download_manager.read().await;
+    manager.query_downloads(query).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_download_info(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<downloader::DownloadInfo, String> {
+    let manager = state.download_manager.read().await;
+    let mut info = manager
         .get_download_info(&id)
         .await
-        .ok_or_else(|| "Download not found".to_string())
+        .ok_or_else(|| "Download not found".to_string())?;
+    info.effective_user_agent = Some(manager.effective_user_agent(&info));
+    Ok(info)
 }
 
-fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+#[tauri::command]
+async fn set_default_proxy(
+    proxy: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut manager = state.download_manager.write().await;
+    manager
+        .set_default_proxy(proxy)
+        .and_then(|()| manager.save_settings())
+        .map_err(|e| e.to_string())
+}
 
-    tauri::Builder::default()
+#[tauri::command]
+async fn reorder_download(
+    id: String,
+    new_priority: i32,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .reorder_download(&id, new_priority)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_download_category(
+    id: String,
+    category: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .set_category(&id, category.as_deref())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_download_tags(
+    id: String,
+    tags: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager.set_tags(&id, &tags).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn move_to_top(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager.move_to_top(&id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn move_to_bottom(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager.move_to_bottom(&id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn reschedule(
+    id: String,
+    new_time: i64,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .reschedule(&id, new_time)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_segment_defaults(
+    max_segments: usize,
+    min_segment_size: u64,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut manager = state.download_manager.write().await;
+    manager
+        .set_segment_defaults(max_segments, min_segment_size)
+        .and_then(|()| manager.save_settings())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_segment_resource_limits(
+    segments_per_core: usize,
+    memory_budget: Option<u64>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut manager = state.download_manager.write().await;
+    manager.set_segment_resource_limits(segments_per_core, memory_budget);
+    manager.save_settings().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_redirect_policy(
+    policy: redirects::RedirectPolicy,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut manager = state.download_manager.write().await;
+    manager
+        .set_redirect_policy(policy)
+        .and_then(|()| manager.save_settings())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_network_preferences(
+    local_address: Option<std::net::IpAddr>,
+    ip_preference: network::IpPreference,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut manager = state.download_manager.write().await;
+    manager
+        .set_network_preferences(local_address, ip_preference)
+        .and_then(|()| manager.save_settings())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_segment_strategy(
+    strategy: segment_strategy::SegmentStrategy,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut manager = state.download_manager.write().await;
+    manager
+        .set_segment_strategy(strategy)
+        .and_then(|()| manager.save_settings())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_preallocate(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    let mut manager = state.download_manager.write().await;
+    manager.set_preallocate(enabled);
+    manager.save_settings().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_max_size(max_size: Option<u64>, state: State<'_, AppState>) -> Result<(), String> {
+    let mut manager = state.download_manager.write().await;
+    manager.set_max_size(max_size);
+    manager.save_settings().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_write_buffer_size(bytes: usize, state: State<'_, AppState>) -> Result<(), String> {
+    let mut manager = state.download_manager.write().await;
+    manager
+        .set_write_buffer_size(bytes)
+        .and_then(|()| manager.save_settings())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_segment_save_interval(
+    interval_ms: u64,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut manager = state.download_manager.write().await;
+    manager
+        .set_segment_save_interval(Duration::from_millis(interval_ms))
+        .and_then(|()| manager.save_settings())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_speed_schedule(
+    schedule: speed_schedule::SpeedSchedule,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager.set_speed_schedule(schedule).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_speed_schedule(
+    state: State<'_, AppState>,
+) -> Result<speed_schedule::SpeedSchedule, String> {
+    let manager = state.download_manager.read().await;
+    Ok(manager.speed_schedule())
+}
+
+#[tauri::command]
+async fn set_notification_settings(
+    settings: notifications::NotificationSettings,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager.set_notification_settings(settings);
+    manager.save_settings().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_host_stats(
+    host: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<host_stats::HostStats>, String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .get_host_stats(host.as_deref())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_statistics(state: State<'_, AppState>) -> Result<statistics::Statistics, String> {
+    let manager = state.download_manager.read().await;
+    manager.get_statistics().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_category_rules(
+    rules: Vec<category::CategoryRule>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .set_category_rules(rules)
+        .and_then(|()| manager.save_settings())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_user_agent_settings(
+    settings: user_agent::UserAgentSettings,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .set_user_agent_settings(settings)
+        .and_then(|()| manager.save_settings())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_sidecar_files(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager.set_sidecar_files(enabled);
+    manager.save_settings().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_allow_post_commands(allow: bool, state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager.set_allow_post_commands(allow);
+    manager.save_settings().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_persist_credentials(
+    enabled: bool,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .set_persist_credentials(enabled)
+        .and_then(|()| manager.save_settings())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_purge_finished_credentials(
+    enabled: bool,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .set_purge_finished_credentials(enabled)
+        .and_then(|()| manager.save_settings())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn purge_credentials(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager.purge_credentials(&id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_connection_limits(
+    max_total: usize,
+    max_per_host: usize,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .set_connection_limits(max_total, max_per_host)
+        .and_then(|()| manager.save_settings())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_max_concurrent(
+    limit: usize,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let manager = state.download_manager.read().await;
+    manager
+        .set_max_concurrent(limit)
+        .and_then(|()| manager.save_settings())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_settings(state: State<'_, AppState>) -> Result<settings::Settings, String> {
+    let manager = state.download_manager.read().await;
+    Ok(manager.settings())
+}
+
+#[tauri::command]
+async fn update_settings(
+    settings: settings::Settings,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut manager = state.download_manager.write().await;
+    manager.update_settings(settings).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn open_log_folder(app: tauri::AppHandle) -> Result<(), String> {
+    let dir = logging::log_dir(&app.path().app_data_dir().map_err(|e| e.to_string())?);
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    opener::open(&dir).map_err(|e| e.to_string())
+}
+
+fn main() {
+    let app = tauri::Builder::default()
+        .plugin(tauri_plugin_notification::init())
         .setup(|app| {
+            // Packaged builds have no visible stderr, so logs also go to
+            // a file the user can find
+            let log_dir = app.path().app_data_dir().ok().map(|dir| logging::log_dir(&dir));
+            logging::init(log_dir.as_deref());
+
             let app_handle = app.handle().clone();
+            notifications::init(&app_handle);
             
             // Initialize download manager
             let download_manager = DownloadManager::new(app_handle.clone());
             let app_state = AppState {
-                download_manager: RwLock::new(download_manager),
+                download_manager: Arc::new(RwLock::new(download_manager)),
             };
-            app.manage(app_state);
+            scheduler::spawn(app_state.clone());
 
-            // Note: Native messaging host should run as a separate process
-            // For now, we'll handle native download requests via events
-            // In production, create a separate binary for native messaging
+            // The native messaging host runs as a separate process (spawned
+            // by the browser) and hands requests over via this server
+            let app_data_dir = app.path().app_data_dir()?;
+            control_server::spawn(app_state.clone(), app_data_dir);
+
+            app.manage(app_state);
 
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             start_download,
+            probe_url,
             pause_download,
+            set_download_state,
             resume_download,
             cancel_download,
+            stop_and_keep,
+            cancel_and_discard,
             get_downloads,
-            get_download_info
+            get_download_info,
+            open_log_folder,
+            set_default_proxy,
+            set_max_concurrent,
+            get_settings,
+            update_settings,
+            set_connection_limits,
+            reorder_download,
+            set_download_category,
+            set_download_tags,
+            move_to_top,
+            move_to_bottom,
+            pause_all,
+            resume_all,
+            reschedule,
+            move_download,
+            verify_download,
+            verify_all,
+            delete_download,
+            retry_download,
+            open_file,
+            open_containing_folder,
+            clear_completed,
+            set_segment_defaults,
+            set_segment_resource_limits,
+            set_segment_strategy,
+            set_redirect_policy,
+            set_network_preferences,
+            set_preallocate,
+            set_persist_credentials,
+            set_max_size,
+            set_write_buffer_size,
+            set_segment_save_interval,
+            set_speed_schedule,
+            get_speed_schedule,
+            set_notification_settings,
+            set_allow_post_commands,
+            set_sidecar_files,
+            set_category_rules,
+            set_user_agent_settings,
+            get_host_stats,
+            get_statistics,
+            export_downloads,
+            import_downloads,
+            compact_database,
+            set_purge_finished_credentials,
+            purge_credentials
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application");
+
+    app.run(|app_handle, event| {
+        if let RunEvent::ExitRequested { code, api, .. } = event {
+            // The second request is the one issued below, once downloads
+            // have recorded their progress
+            if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+                return;
+            }
+            api.prevent_exit();
+
+            let app_handle = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                {
+                    let state = app_handle.state::<AppState>();
+                    let manager = state.download_manager.read().await;
+                    manager.shutdown().await;
+                }
+                app_handle.exit(code.unwrap_or(0));
+            });
+        }
+    });
 }
 
@@ -1,96 +1,149 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::io::{self, BufRead, BufReader, Write};
-use tauri::AppHandle;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::control_server::{CreatedDownload, Discovery, NewDownload, DISCOVERY_FILE};
+use crate::cookies::CookieSpec;
+
+/// How long the host waits for the running app to accept a download
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Must match `identifier` in tauri.conf.json, which names the app data dir
+const APP_IDENTIFIER: &str = "com.gripdl.app";
 
 #[derive(Debug, Deserialize)]
-struct NativeMessage {
-    url: String,
-    cookies: Option<String>,
-    referrer: Option<String>,
-    user_agent: Option<String>,
+pub struct NativeMessage {
+    #[serde(flatten)]
+    pub targets: Targets,
+    /// Shared by every URL in the message
+    pub cookies: Option<String>,
+    /// Cookies with their scope; preferred over `cookies`
+    #[serde(default)]
+    pub cookies_structured: Option<Vec<CookieSpec>>,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
-struct NativeResponse {
-    success: bool,
-    message: Option<String>,
+/// A message names either one `url` or a batch of `urls`
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Targets {
+    Single { url: String },
+    Batch { urls: Vec<String> },
 }
 
-pub struct NativeMessagingHost;
-
-impl NativeMessagingHost {
-    /// Start the native messaging host as a standalone process
-    /// This should be called from a separate binary or when invoked as native messaging host
-    pub async fn start(app_handle: AppHandle) -> Result<()> {
-        let stdin = io::stdin();
-        let mut reader = BufReader::new(stdin.lock());
-        let mut stdout = io::stdout();
-
-        loop {
-            // Read message length (4 bytes, little-endian)
-            let mut length_bytes = [0u8; 4];
-            if reader.read_exact(&mut length_bytes).is_err() {
-                break; // EOF or error
-            }
-            let length = u32::from_le_bytes(length_bytes) as usize;
-
-            if length == 0 {
-                continue;
-            }
-
-            // Read message content
-            let mut buffer = vec![0u8; length];
-            if reader.read_exact(&mut buffer).is_err() {
-                break;
-            }
-
-            // Parse JSON message
-            let message: NativeMessage = match serde_json::from_slice(&buffer) {
-                Ok(msg) => msg,
-                Err(e) => {
-                    tracing::error!("Failed to parse native message: {}", e);
-                    Self::send_response(&mut stdout, false, Some("Invalid message format".to_string()))?;
-                    continue;
-                }
-            };
-
-            // Emit event to Tauri app - the app will handle starting the download
-            // In a production setup, you might want to use IPC or a local server
-            let app_handle_clone = app_handle.clone();
-            let url = message.url.clone();
-            let cookies = message.cookies.clone();
-            let referrer = message.referrer.clone();
-            let user_agent = message.user_agent.clone();
-
-            // Emit event that the frontend can listen to
-            let _ = app_handle_clone.emit("native-download-request", serde_json::json!({
-                "url": url,
-                "cookies": cookies,
-                "referrer": referrer,
-                "user_agent": user_agent,
-            }));
-
-            Self::send_response(&mut stdout, true, None)?;
+impl Targets {
+    pub fn urls(&self) -> &[String] {
+        match self {
+            Targets::Single { url } => std::slice::from_ref(url),
+            Targets::Batch { urls } => urls,
         }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct NativeResponse {
+    pub success: bool,
+    pub message: Option<String>,
+    /// Ids of the downloads that were created, in request order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ids: Vec<String>,
+}
 
-        Ok(())
+/// Read one length-prefixed message. `Ok(None)` means the browser closed
+/// the pipe.
+pub fn read_message(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    // Read message length (4 bytes, native byte order)
+    let mut length_bytes = [0u8; 4];
+    if let Err(e) = reader.read_exact(&mut length_bytes) {
+        return match e.kind() {
+            io::ErrorKind::UnexpectedEof => Ok(None),
+            _ => Err(e.into()),
+        };
     }
+    let length = u32::from_ne_bytes(length_bytes) as usize;
+
+    // Read message content
+    let mut buffer = vec![0u8; length];
+    reader
+        .read_exact(&mut buffer)
+        .context("Truncated native message")?;
+    Ok(Some(buffer))
+}
+
+pub fn send_response(writer: &mut impl Write, response: &NativeResponse) -> Result<()> {
+    let json = serde_json::to_string(response)?;
+    let length = json.len() as u32;
 
-    fn send_response(
-        stdout: &mut io::Stdout,
-        success: bool,
-        message: Option<String>,
-    ) -> Result<()> {
-        let response = NativeResponse { success, message };
-        let json = serde_json::to_string(&response)?;
-        let length = json.len() as u32;
+    writer.write_all(&length.to_ne_bytes())?;
+    writer.write_all(json.as_bytes())?;
+    writer.flush()?;
 
-        stdout.write_all(&length.to_le_bytes())?;
-        stdout.write_all(json.as_bytes())?;
-        stdout.flush()?;
+    Ok(())
+}
 
-        Ok(())
+/// Hand a download request to the running GripDL instance through its
+/// localhost control server, one download per URL. Fails outright only if
+/// the app can't be found; otherwise reports the new id or the error for
+/// each URL.
+pub fn forward_to_app(message: &NativeMessage) -> Result<Vec<Result<String>>> {
+    let discovery = load_discovery()?;
+    let client = reqwest::blocking::Client::builder()
+        .timeout(FORWARD_TIMEOUT)
+        .build()?;
+
+    Ok(message
+        .targets
+        .urls()
+        .iter()
+        .map(|url| {
+            forward_download(
+                &client,
+                &discovery,
+                &NewDownload {
+                    url: url.clone(),
+                    cookies: message.cookies.clone(),
+                    cookies_structured: message.cookies_structured.clone(),
+                    referrer: message.referrer.clone(),
+                    user_agent: message.user_agent.clone(),
+                    ..Default::default()
+                },
+            )
+        })
+        .collect())
+}
+
+fn forward_download(
+    client: &reqwest::blocking::Client,
+    discovery: &Discovery,
+    download: &NewDownload,
+) -> Result<String> {
+    let response = client
+        .post(format!("http://127.0.0.1:{}/downloads", discovery.port))
+        .bearer_auth(&discovery.token)
+        .json(download)
+        .send()
+        .context("GripDL is not reachable")?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().unwrap_or_default();
+        anyhow::bail!("GripDL rejected the download ({}): {}", status, body);
     }
+
+    let created: CreatedDownload = response.json()?;
+    Ok(created.id)
 }
 
+/// Find the running app's control server. The host has no `AppHandle`, so
+/// it resolves the app data dir the same way Tauri does.
+fn load_discovery() -> Result<Discovery> {
+    let path = dirs::data_dir()
+        .context("No app data directory on this system")?
+        .join(APP_IDENTIFIER)
+        .join(DISCOVERY_FILE);
+    let json = std::fs::read_to_string(&path)
+        .with_context(|| format!("GripDL doesn't seem to be running ({})", path.display()))?;
+    serde_json::from_str(&json).context("Malformed discovery file")
+}
@@ -0,0 +1,68 @@
+use anyhow::Result;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+
+/// Which IP versions connections may use
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IpPreference {
+    #[default]
+    Any,
+    /// Try IPv4 addresses first, falling back to IPv6
+    PreferIpv4,
+    /// Try IPv6 addresses first, falling back to IPv4
+    PreferIpv6,
+    Ipv4Only,
+    Ipv6Only,
+}
+
+impl IpPreference {
+    /// The preference in force when connections are bound to `local_address`:
+    /// only its own IP version, since a socket of the other one wouldn't be
+    /// bound and could leave through any interface
+    pub fn for_local_address(self, local_address: Option<IpAddr>) -> Result<Self> {
+        match (local_address, self) {
+            (None, preference) => Ok(preference),
+            (Some(IpAddr::V4(_)), IpPreference::Ipv6Only)
+            | (Some(IpAddr::V6(_)), IpPreference::Ipv4Only) => {
+                anyhow::bail!("The local address doesn't match the allowed IP version")
+            }
+            (Some(IpAddr::V4(_)), _) => Ok(IpPreference::Ipv4Only),
+            (Some(IpAddr::V6(_)), _) => Ok(IpPreference::Ipv6Only),
+        }
+    }
+
+    /// Drop or reorder resolved addresses. Connections try them in order,
+    /// so a preference only moves its version to the front.
+    fn apply(self, mut addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        match self {
+            IpPreference::Any => {}
+            IpPreference::PreferIpv4 => addrs.sort_by_key(|addr| !addr.is_ipv4()),
+            IpPreference::PreferIpv6 => addrs.sort_by_key(|addr| !addr.is_ipv6()),
+            IpPreference::Ipv4Only => addrs.retain(SocketAddr::is_ipv4),
+            IpPreference::Ipv6Only => addrs.retain(SocketAddr::is_ipv6),
+        }
+        addrs
+    }
+}
+
+/// Resolves host names with the system resolver, then applies an
+/// `IpPreference` to the addresses
+pub struct PreferenceResolver(pub IpPreference);
+
+impl Resolve for PreferenceResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let preference = self.0;
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            let addrs = preference.apply(addrs);
+            if addrs.is_empty() {
+                return Err(
+                    format!("{} has no address of the allowed IP version", name.as_str()).into(),
+                );
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tauri::AppHandle;
+use tauri_plugin_notification::NotificationExt;
+
+use crate::downloader::{DownloadInfo, DownloadStatus};
+use crate::opener;
+
+/// Key of the notification extra holding the file to reveal on click
+const REVEAL_PATH: &str = "revealPath";
+
+/// Which desktop notifications to show
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct NotificationSettings {
+    /// Global switch; off silences every notification
+    pub enabled: bool,
+    pub on_complete: bool,
+    pub on_failure: bool,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            on_complete: true,
+            on_failure: true,
+        }
+    }
+}
+
+/// Reveal a completed download in the file manager when its notification
+/// is clicked
+pub fn init(app_handle: &AppHandle) {
+    let result = app_handle.notification().on_action(|performed| {
+        if performed.action_id() != "tap" {
+            return;
+        }
+        let path = performed
+            .notification()
+            .and_then(|n| n.extra().get(REVEAL_PATH))
+            .and_then(|v| v.as_str());
+        if let Some(path) = path {
+            if let Err(e) = opener::reveal(Path::new(path)) {
+                tracing::warn!("Failed to reveal {}: {}", path, e);
+            }
+        }
+    });
+    if let Err(e) = result {
+        tracing::warn!("Failed to register the notification click handler: {}", e);
+    }
+}
+
+/// Tell the user a download has completed or failed, if they want to know
+pub fn notify(app_handle: &AppHandle, settings: NotificationSettings, info: &DownloadInfo) {
+    if !settings.enabled {
+        return;
+    }
+
+    let builder = app_handle.notification().builder();
+    let builder = match &info.status {
+        DownloadStatus::Completed if settings.on_complete => builder
+            .title(format!("{} downloaded", info.file_name))
+            .body(info.file_path.to_string_lossy())
+            .extra(REVEAL_PATH, info.file_path.to_string_lossy()),
+        DownloadStatus::Failed(error) if settings.on_failure => builder
+            .title(format!("{} failed", info.file_name))
+            .body(error.to_string()),
+        _ => return,
+    };
+    if let Err(e) = builder.show() {
+        tracing::warn!("Failed to show a notification: {}", e);
+    }
+}
@@ -0,0 +1,69 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+/// Open a file with its default application
+pub fn open(path: &Path) -> Result<()> {
+    #[cfg(target_os = "macos")]
+    let mut command = {
+        let mut command = Command::new("open");
+        command.arg(path);
+        command
+    };
+    // Not `cmd /C start`: cmd would interpret `&`, `|` and `^` in a file
+    // name the server picked
+    #[cfg(windows)]
+    let mut command = {
+        let mut command = Command::new("explorer");
+        command.arg(path);
+        command
+    };
+    #[cfg(all(unix, not(target_os = "macos")))]
+    let mut command = {
+        let mut command = Command::new("xdg-open");
+        command.arg(path);
+        command
+    };
+
+    spawn(&mut command)
+}
+
+/// Show a file in the system file manager, selected where supported
+pub fn reveal(path: &Path) -> Result<()> {
+    #[cfg(target_os = "macos")]
+    let mut command = {
+        let mut command = Command::new("open");
+        command.arg("-R").arg(path);
+        command
+    };
+    #[cfg(windows)]
+    let mut command = {
+        let mut select = std::ffi::OsString::from("/select,");
+        select.push(path);
+        let mut command = Command::new("explorer");
+        command.arg(select);
+        command
+    };
+    #[cfg(all(unix, not(target_os = "macos")))]
+    let mut command = {
+        let mut command = Command::new("xdg-open");
+        command.arg(path.parent().context("File has no parent directory")?);
+        command
+    };
+
+    spawn(&mut command)
+}
+
+fn spawn(command: &mut Command) -> Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Failed to run {}", program))?;
+
+    // Reap the launcher in the background; it exits as soon as it has
+    // handed the path over
+    std::thread::spawn(move || {
+        let _ = child.wait();
+    });
+    Ok(())
+}
@@ -1,11 +1,98 @@
-use crate::downloader::{DownloadInfo, DownloadStatus};
+use crate::downloader::{DownloadInfo, DownloadOptions, DownloadStatus, Segment};
+use crate::error::DownloadError;
+use crate::host_stats::{self, HostStats};
+use crate::secrets::{self, Secrets};
+use crate::statistics::{HostVolume, Statistics};
 use anyhow::{Context, Result};
-use rusqlite::{params, Connection};
+use parking_lot::Mutex;
+use rusqlite::types::Value;
+use rusqlite::{
+    params, params_from_iter, Connection, ErrorCode, OptionalExtension, Row, Transaction,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
-use tauri::AppHandle;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
 
+/// Which downloads `get_downloads` returns, and in what order
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DownloadQuery {
+    /// Status names, e.g. `["Completed", "Failed"]`; empty matches all
+    pub statuses: Vec<String>,
+    /// Substring of the file name or URL
+    pub search: Option<String>,
+    /// Only downloads filed under this category
+    pub category: Option<String>,
+    /// Only downloads carrying this tag
+    pub tag: Option<String>,
+    pub sort: SortField,
+    pub descending: bool,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub enum SortField {
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+    Size,
+}
+
+impl SortField {
+    fn column(self) -> &'static str {
+        match self {
+            SortField::CreatedAt => "created_at",
+            SortField::UpdatedAt => "updated_at",
+            SortField::Size => "total_size",
+        }
+    }
+}
+
+/// A page of downloads and how many match the query in total
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadPage {
+    pub downloads: Vec<DownloadInfo>,
+    pub total: usize,
+}
+
+#[derive(Clone)]
 pub struct DownloadPersistence {
     db_path: PathBuf,
+    /// `None` when the OS keychain is unavailable; credentials are then
+    /// never written to disk
+    secrets: Option<Secrets>,
+    persist_credentials: Arc<AtomicBool>,
+    /// Drop credentials as soon as a download is finished with them
+    purge_finished_credentials: Arc<AtomicBool>,
+    /// Credentials kept out of the database, by download id
+    session_credentials: Arc<Mutex<HashMap<String, Credentials>>>,
+}
+
+/// The sensitive columns of a download row, as plaintext
+#[derive(Clone, Default)]
+struct Credentials {
+    cookies: Option<String>,
+    /// `CookieSpec`s as JSON
+    cookies_structured: Option<String>,
+    /// `AuthMethod` as JSON
+    auth: Option<String>,
+    /// Custom headers as JSON; these often carry tokens
+    headers: Option<String>,
+}
+
+impl Credentials {
+    fn is_empty(&self) -> bool {
+        self.cookies.is_none()
+            && self.cookies_structured.is_none()
+            && self.auth.is_none()
+            && self.headers.is_none()
+    }
 }
 
 impl DownloadPersistence {
@@ -20,132 +107,1258 @@ impl DownloadPersistence {
 
         let db_path = app_data_dir.join("downloads.db");
         
-        let persistence = Self { db_path };
+        let secrets = match Secrets::from_keychain() {
+            Ok(secrets) => Some(secrets),
+            Err(e) => {
+                tracing::warn!("{:#}; cookies and credentials will only be kept in memory", e);
+                None
+            }
+        };
+        Self::open_at(db_path, secrets)
+    }
+
+    /// The database at `db_path`, created or migrated as needed. Without
+    /// `secrets`, credentials are only kept in memory.
+    pub(crate) fn open_at(db_path: PathBuf, secrets: Option<Secrets>) -> Result<Self> {
+        let persistence = Self {
+            db_path,
+            secrets,
+            persist_credentials: Arc::new(AtomicBool::new(true)),
+            purge_finished_credentials: Arc::new(AtomicBool::new(false)),
+            session_credentials: Arc::new(Mutex::new(HashMap::new())),
+        };
         persistence.init_db()?;
+        if let Err(e) = persistence.encrypt_legacy_credentials() {
+            tracing::warn!("Failed to encrypt stored credentials: {:#}", e);
+        }
         
         Ok(persistence)
     }
 
-    fn init_db(&self) -> Result<()> {
+    fn open(&self) -> Result<Connection> {
         let conn = Connection::open(&self.db_path)?;
-        
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS downloads (
-                id TEXT PRIMARY KEY,
-                url TEXT NOT NULL,
-                file_path TEXT NOT NULL,
-                file_name TEXT NOT NULL,
-                total_size INTEGER,
-                downloaded_size INTEGER NOT NULL DEFAULT 0,
-                status TEXT NOT NULL,
-                cookies TEXT,
-                referrer TEXT,
-                user_agent TEXT,
-                created_at INTEGER NOT NULL,
-                updated_at INTEGER NOT NULL
-            )",
-            [],
-        )?;
+        // Off by default in SQLite; needed for the segment cascade
+        conn.pragma_update(None, "foreign_keys", true)?;
+        // Every segment writes its progress on its own connection; wait for
+        // the others instead of failing with "database is locked"
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(conn)
+    }
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS download_segments (
-                download_id TEXT NOT NULL,
-                segment_index INTEGER NOT NULL,
-                start_byte INTEGER NOT NULL,
-                end_byte INTEGER NOT NULL,
-                downloaded_bytes INTEGER NOT NULL DEFAULT 0,
-                PRIMARY KEY (download_id, segment_index),
-                FOREIGN KEY (download_id) REFERENCES downloads(id) ON DELETE CASCADE
-            )",
-            [],
-        )?;
+    fn init_db(&self) -> Result<()> {
+        let mut conn = self.open()?;
+        run_migrations(&mut conn).context("Failed to migrate the downloads database")
+    }
+
+    pub fn persist_credentials(&self) -> bool {
+        self.persist_credentials.load(Ordering::Relaxed)
+    }
+
+    pub fn purge_finished_credentials(&self) -> bool {
+        self.purge_finished_credentials.load(Ordering::Relaxed)
+    }
+
+    /// Whether cookies and credentials go to disk (encrypted) or stay in
+    /// memory for the session. Turning it off scrubs them from the database.
+    pub fn set_persist_credentials(&self, enabled: bool) -> Result<()> {
+        self.persist_credentials.store(enabled, Ordering::Relaxed);
+
+        let mut conn = self.open()?;
+        let tx = conn.transaction()?;
+        if enabled {
+            if self.secrets.is_none() {
+                anyhow::bail!("The OS keychain is unavailable; credentials stay in memory");
+            }
+            let held: Vec<_> = self.session_credentials.lock().drain().collect();
+            for (id, credentials) in held {
+                let sealed = self.seal(&id, credentials)?;
+                store_credentials(&tx, &id, &sealed)?;
+            }
+        } else {
+            let rows = read_credentials(&tx)?;
+            let mut held = self.session_credentials.lock();
+            for (id, stored) in rows {
+                held.insert(id, self.reveal(stored));
+            }
+            tx.execute(
+                "UPDATE downloads
+                 SET cookies = NULL, cookies_structured = NULL, auth = NULL, headers = NULL",
+                [],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Forget a download's cookies, credentials and custom headers once it
+    /// has completed, failed or been cancelled. Turning it on also purges
+    /// downloads that are already finished.
+    pub fn set_purge_finished_credentials(&self, enabled: bool) -> Result<()> {
+        self.purge_finished_credentials.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            return Ok(());
+        }
+
+        let mut conn = self.open()?;
+        let tx = conn.transaction()?;
+        let finished = {
+            let mut stmt = tx.prepare(
+                "SELECT id FROM downloads WHERE status IN ('completed', 'failed', 'cancelled')",
+            )?;
+            let ids = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            ids
+        };
+        for id in &finished {
+            store_credentials(&tx, id, &Credentials::default())?;
+        }
+        tx.commit()?;
+
+        let mut held = self.session_credentials.lock();
+        for id in &finished {
+            held.remove(id);
+        }
+        Ok(())
+    }
+
+    /// Forget a download's cookies, credentials and custom headers
+    pub fn purge_credentials(&self, id: &str) -> Result<()> {
+        let mut conn = self.open()?;
+        let tx = conn.transaction()?;
+        store_credentials(&tx, id, &Credentials::default())?;
+        tx.commit()?;
+        self.session_credentials.lock().remove(id);
+        Ok(())
+    }
+
+    /// Rows written before encryption was added still hold plaintext
+    fn encrypt_legacy_credentials(&self) -> Result<()> {
+        if self.secrets.is_none() {
+            return Ok(());
+        }
 
+        let mut conn = self.open()?;
+        let tx = conn.transaction()?;
+        let mut encrypted = 0;
+        for (id, stored) in read_credentials(&tx)? {
+            let plaintext = [
+                &stored.cookies,
+                &stored.cookies_structured,
+                &stored.auth,
+                &stored.headers,
+            ]
+                .into_iter()
+                .flatten()
+                .any(|value| !secrets::is_encrypted(value));
+            if plaintext {
+                let credentials = self.reveal(stored);
+                store_credentials(&tx, &id, &self.seal(&id, credentials)?)?;
+                encrypted += 1;
+            }
+        }
+        tx.commit()?;
+
+        if encrypted > 0 {
+            tracing::info!("Encrypted stored credentials of {} downloads", encrypted);
+        }
         Ok(())
     }
 
+    /// The column values to write for a download's credentials. Anything
+    /// that may not go to disk is held in memory instead and the columns
+    /// are left empty.
+    fn seal(&self, id: &str, credentials: Credentials) -> Result<Credentials> {
+        let secrets = match &self.secrets {
+            Some(secrets) if self.persist_credentials.load(Ordering::Relaxed) => secrets,
+            _ => {
+                let mut held = self.session_credentials.lock();
+                if credentials.is_empty() {
+                    held.remove(id);
+                } else {
+                    held.insert(id.to_string(), credentials);
+                }
+                return Ok(Credentials::default());
+            }
+        };
+
+        self.session_credentials.lock().remove(id);
+        let encrypt = |value: Option<String>| value.map(|v| secrets.encrypt(&v)).transpose();
+        Ok(Credentials {
+            cookies: encrypt(credentials.cookies)?,
+            cookies_structured: encrypt(credentials.cookies_structured)?,
+            auth: encrypt(credentials.auth)?,
+            headers: encrypt(credentials.headers)?,
+        })
+    }
+
+    /// Decrypt stored column values. A value that can't be decrypted is
+    /// dropped rather than failing the whole load.
+    fn reveal(&self, stored: Credentials) -> Credentials {
+        let decrypt = |value: Option<String>| {
+            let value = value?;
+            if !secrets::is_encrypted(&value) {
+                return Some(value);
+            }
+            let Some(secrets) = &self.secrets else {
+                tracing::warn!("Cannot decrypt stored credentials without the keychain");
+                return None;
+            };
+            secrets
+                .decrypt(&value)
+                .map_err(|e| tracing::warn!("{:#}", e))
+                .ok()
+        };
+        Credentials {
+            cookies: decrypt(stored.cookies),
+            cookies_structured: decrypt(stored.cookies_structured),
+            auth: decrypt(stored.auth),
+            headers: decrypt(stored.headers),
+        }
+    }
+
     pub fn save_download(&self, info: &DownloadInfo) -> Result<()> {
-        let conn = Connection::open(&self.db_path)?;
+        let conn = self.open()?;
         
         let status_str = match info.status {
             DownloadStatus::Pending => "pending",
+            DownloadStatus::Scheduled => "scheduled",
+            DownloadStatus::Queued => "queued",
             DownloadStatus::Downloading => "downloading",
+            DownloadStatus::Verifying => "verifying",
             DownloadStatus::Paused => "paused",
             DownloadStatus::Completed => "completed",
             DownloadStatus::Failed(_) => "failed",
             DownloadStatus::Cancelled => "cancelled",
         };
+        let (error_message, error_details) = match &info.status {
+            DownloadStatus::Failed(error) => {
+                (Some(error.to_string()), Some(serde_json::to_string(error)?))
+            }
+            _ => (None, None),
+        };
+        let redirect_chain = (!info.redirect_chain.is_empty())
+            .then(|| serde_json::to_string(&info.redirect_chain))
+            .transpose()?;
+        let finished = matches!(
+            info.status,
+            DownloadStatus::Completed | DownloadStatus::Failed(_) | DownloadStatus::Cancelled
+        );
+        let credentials = if finished && self.purge_finished_credentials.load(Ordering::Relaxed) {
+            self.session_credentials.lock().remove(&info.id);
+            Credentials::default()
+        } else {
+            self.seal(
+                &info.id,
+                Credentials {
+                    cookies: info.cookies.clone(),
+                    cookies_structured: info
+                        .cookies_structured
+                        .as_ref()
+                        .map(serde_json::to_string)
+                        .transpose()?,
+                    auth: info.options.auth.as_ref().map(serde_json::to_string).transpose()?,
+                    headers: info.headers.as_ref().map(serde_json::to_string).transpose()?,
+                },
+            )?
+        };
 
-        conn.execute(
-            "INSERT OR REPLACE INTO downloads 
-            (id, url, file_path, file_name, total_size, downloaded_size, status, cookies, referrer, user_agent, created_at, updated_at)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
-            params![
-                info.id,
-                info.url,
-                info.file_path.to_string_lossy(),
-                info.file_name,
-                info.total_size,
-                info.downloaded_size,
-                status_str,
-                info.cookies,
-                info.referrer,
-                info.user_agent,
-                info.created_at,
-                info.updated_at
-            ],
-        )?;
+        // An upsert rather than INSERT OR REPLACE: replacing deletes the
+        // row first, which would cascade to the download's segments
+        retry_busy(|| {
+            conn.execute(
+                "INSERT INTO downloads
+                (id, url, file_path, file_name, total_size, downloaded_size, status, cookies, referrer, user_agent, options, created_at, updated_at, resolved_url, auth, headers, priority, start_at, error_message, checksum, error_details, category, etag, last_modified, cookies_structured, redirect_chain)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26)
+                ON CONFLICT(id) DO UPDATE SET
+                    url = excluded.url,
+                    file_path = excluded.file_path,
+                    file_name = excluded.file_name,
+                    total_size = excluded.total_size,
+                    downloaded_size = excluded.downloaded_size,
+                    status = excluded.status,
+                    cookies = excluded.cookies,
+                    referrer = excluded.referrer,
+                    user_agent = excluded.user_agent,
+                    options = excluded.options,
+                    created_at = excluded.created_at,
+                    updated_at = excluded.updated_at,
+                    resolved_url = excluded.resolved_url,
+                    auth = excluded.auth,
+                    headers = excluded.headers,
+                    priority = excluded.priority,
+                    start_at = excluded.start_at,
+                    error_message = excluded.error_message,
+                    checksum = excluded.checksum,
+                    error_details = excluded.error_details,
+                    category = excluded.category,
+                    etag = excluded.etag,
+                    last_modified = excluded.last_modified,
+                    cookies_structured = excluded.cookies_structured,
+                    redirect_chain = excluded.redirect_chain",
+                params![
+                    info.id,
+                    info.url,
+                    info.file_path.to_string_lossy(),
+                    info.file_name,
+                    info.total_size,
+                    info.downloaded_size,
+                    status_str,
+                    credentials.cookies,
+                    info.referrer,
+                    info.user_agent,
+                    serde_json::to_string(&info.options)?,
+                    info.created_at,
+                    info.updated_at,
+                    info.resolved_url,
+                    credentials.auth,
+                    credentials.headers,
+                    info.priority,
+                    info.start_at,
+                    error_message,
+                    info.checksum,
+                    error_details,
+                    info.category,
+                    info.etag,
+                    info.last_modified,
+                    credentials.cookies_structured,
+                    redirect_chain
+                ],
+            )?;
+            Ok(())
+        })?;
 
         Ok(())
     }
 
     pub fn load_downloads(&self) -> Result<Vec<DownloadInfo>> {
-        let conn = Connection::open(&self.db_path)?;
-        
-        let mut stmt = conn.prepare(
-            "SELECT id, url, file_path, file_name, total_size, downloaded_size, status, cookies, referrer, user_agent, created_at, updated_at
-             FROM downloads"
+        Ok(self.query_downloads(&DownloadQuery::default())?.downloads)
+    }
+
+    pub fn load_download(&self, id: &str) -> Result<Option<DownloadInfo>> {
+        let conn = self.open()?;
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM downloads WHERE id = ?1", COLUMNS))?;
+        let row = stmt
+            .query_map(params![id], download_from_row)?
+            .next()
+            .transpose()?;
+        Ok(row.map(|(info, stored)| self.with_credentials(info, stored)))
+    }
+
+    /// Downloads of `url`, whether as submitted or after redirects
+    pub fn downloads_with_url(&self, url: &str) -> Result<Vec<DownloadInfo>> {
+        let conn = self.open()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM downloads WHERE url = ?1 OR resolved_url = ?1",
+            COLUMNS
+        ))?;
+        let rows = stmt
+            .query_map(params![url], download_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows
+            .into_iter()
+            .map(|(info, stored)| self.with_credentials(info, stored))
+            .collect())
+    }
+
+    /// One page of the downloads matching `query`, filtered and sorted in SQL
+    pub fn query_downloads(&self, query: &DownloadQuery) -> Result<DownloadPage> {
+        let conn = self.open()?;
+
+        let mut conditions = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+        if !query.statuses.is_empty() {
+            let mut placeholders = Vec::new();
+            for status in &query.statuses {
+                let status = status.to_ascii_lowercase();
+                if !STATUSES.contains(&status.as_str()) {
+                    anyhow::bail!("Unknown status {}", status);
+                }
+                values.push(Value::Text(status));
+                placeholders.push(format!("?{}", values.len()));
+            }
+            conditions.push(format!("status IN ({})", placeholders.join(", ")));
+        }
+        if let Some(search) = query.search.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+            let escaped = search
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            values.push(Value::Text(format!("%{}%", escaped)));
+            let n = values.len();
+            conditions.push(format!(
+                "(file_name LIKE ?{n} ESCAPE '\\' OR url LIKE ?{n} ESCAPE '\\')"
+            ));
+        }
+        if let Some(category) = &query.category {
+            values.push(Value::Text(category.clone()));
+            conditions.push(format!("category = ?{}", values.len()));
+        }
+        if let Some(tag) = &query.tag {
+            values.push(Value::Text(tag.clone()));
+            conditions.push(format!(
+                "EXISTS (SELECT 1 FROM download_tags t \
+                 WHERE t.download_id = downloads.id AND t.tag = ?{})",
+                values.len()
+            ));
+        }
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
+        let total: usize = conn.query_row(
+            &format!("SELECT COUNT(*) FROM downloads {}", filter),
+            params_from_iter(&values),
+            |row| row.get(0),
         )?;
 
-        let download_iter = stmt.query_map([], |row| {
-            let status_str: String = row.get(6)?;
-            let status = match status_str.as_str() {
-                "pending" => DownloadStatus::Pending,
-                "downloading" => DownloadStatus::Downloading,
-                "paused" => DownloadStatus::Paused,
-                "completed" => DownloadStatus::Completed,
-                "failed" => DownloadStatus::Failed("Unknown error".to_string()),
-                "cancelled" => DownloadStatus::Cancelled,
-                _ => DownloadStatus::Pending,
-            };
+        // Ties (and unknown sizes) fall back to creation order
+        let order = format!(
+            "{} {}, created_at, id",
+            query.sort.column(),
+            if query.descending { "DESC" } else { "ASC" }
+        );
+        // SQLite's LIMIT -1 means no limit
+        values.push(Value::Integer(query.limit.map_or(-1, |l| l as i64)));
+        values.push(Value::Integer(query.offset as i64));
+        let sql = format!(
+            "SELECT {} FROM downloads {} ORDER BY {} LIMIT ?{} OFFSET ?{}",
+            COLUMNS,
+            filter,
+            order,
+            values.len() - 1,
+            values.len()
+        );
+
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt
+            .query_map(params_from_iter(&values), download_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let downloads = rows
+            .into_iter()
+            .map(|(info, stored)| self.with_credentials(info, stored))
+            .collect();
+
+        Ok(DownloadPage { downloads, total })
+    }
+
+    /// Fill in a loaded download's credentials, from the database or from
+    /// memory
+    fn with_credentials(&self, mut info: DownloadInfo, stored: Credentials) -> DownloadInfo {
+        let credentials = if stored.is_empty() {
+            self.session_credentials
+                .lock()
+                .get(&info.id)
+                .cloned()
+                .unwrap_or_default()
+        } else {
+            self.reveal(stored)
+        };
+        info.cookies = credentials.cookies;
+        info.cookies_structured = credentials
+            .cookies_structured
+            .and_then(|json| serde_json::from_str(&json).ok());
+        info.options.auth = credentials
+            .auth
+            .and_then(|json| serde_json::from_str(&json).ok());
+        info.headers = credentials
+            .headers
+            .and_then(|json| serde_json::from_str(&json).ok());
+        info
+    }
+
+    /// Replace the segment layout recorded for a download
+    pub fn save_segments(&self, download_id: &str, segments: &[Segment]) -> Result<()> {
+        let mut conn = self.open()?;
+        retry_busy(|| {
+            let tx = conn.transaction()?;
+
+            tx.execute(
+                "DELETE FROM download_segments WHERE download_id = ?1",
+                params![download_id],
+            )?;
+
+            for segment in segments {
+                tx.execute(
+                    "INSERT INTO download_segments
+                    (download_id, segment_index, start_byte, end_byte, downloaded_bytes)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        download_id,
+                        segment.index,
+                        segment.start,
+                        segment.end,
+                        segment.downloaded
+                    ],
+                )?;
+            }
+
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    pub fn load_segments(&self, download_id: &str) -> Result<Vec<Segment>> {
+        let conn = self.open()?;
 
-            Ok(DownloadInfo {
-                id: row.get(0)?,
-                url: row.get(1)?,
-                file_path: PathBuf::from(row.get::<_, String>(2)?),
-                file_name: row.get(3)?,
-                total_size: row.get(4)?,
-                downloaded_size: row.get(5)?,
-                status,
-                cookies: row.get(7)?,
-                referrer: row.get(8)?,
-                user_agent: row.get(9)?,
-                created_at: row.get(10)?,
-                updated_at: row.get(11)?,
+        let mut stmt = conn.prepare(
+            "SELECT segment_index, start_byte, end_byte, downloaded_bytes
+             FROM download_segments
+             WHERE download_id = ?1
+             ORDER BY segment_index",
+        )?;
+
+        let segment_iter = stmt.query_map(params![download_id], |row| {
+            Ok(Segment {
+                index: row.get(0)?,
+                start: row.get(1)?,
+                end: row.get(2)?,
+                downloaded: row.get(3)?,
             })
         })?;
 
-        let mut downloads = Vec::new();
-        for download in download_iter {
-            downloads.push(download?);
+        let mut segments = Vec::new();
+        for segment in segment_iter {
+            segments.push(segment?);
         }
 
-        Ok(downloads)
+        Ok(segments)
+    }
+
+    /// Record a segment's progress and recompute the download's total from
+    /// all of its segments in the same transaction, so concurrent segment
+    /// writers can never leave `downloaded_size` out of step
+    pub fn update_segment_progress(
+        &self,
+        download_id: &str,
+        segment_index: usize,
+        downloaded: u64,
+    ) -> Result<()> {
+        let mut conn = self.open()?;
+        retry_busy(|| {
+            let tx = conn.transaction()?;
+
+            tx.execute(
+                "UPDATE download_segments SET downloaded_bytes = ?1
+                 WHERE download_id = ?2 AND segment_index = ?3",
+                params![downloaded, download_id, segment_index],
+            )?;
+            tx.execute(
+                "UPDATE downloads SET
+                    downloaded_size = (
+                        SELECT COALESCE(SUM(downloaded_bytes), 0)
+                        FROM download_segments WHERE download_id = ?1
+                    ),
+                    updated_at = strftime('%s', 'now')
+                 WHERE id = ?1",
+                params![download_id],
+            )?;
+
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    /// Replace a download's tags
+    pub fn set_tags(&self, download_id: &str, tags: &[String]) -> Result<()> {
+        let mut conn = self.open()?;
+        retry_busy(|| {
+            let tx = conn.transaction()?;
+
+            tx.execute(
+                "DELETE FROM download_tags WHERE download_id = ?1",
+                params![download_id],
+            )?;
+            for tag in tags {
+                tx.execute(
+                    "INSERT OR IGNORE INTO download_tags (download_id, tag) VALUES (?1, ?2)",
+                    params![download_id, tag],
+                )?;
+            }
+
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    pub fn delete_segments(&self, download_id: &str) -> Result<()> {
+        let conn = self.open()?;
+        retry_busy(|| {
+            conn.execute(
+                "DELETE FROM download_segments WHERE download_id = ?1",
+                params![download_id],
+            )?;
+            Ok(())
+        })
     }
 
     pub fn delete_download(&self, id: &str) -> Result<()> {
-        let conn = Connection::open(&self.db_path)?;
-        conn.execute("DELETE FROM downloads WHERE id = ?1", params![id])?;
+        let conn = self.open()?;
+        retry_busy(|| {
+            conn.execute("DELETE FROM downloads WHERE id = ?1", params![id])?;
+            Ok(())
+        })?;
+        self.session_credentials.lock().remove(id);
         Ok(())
     }
+
+    /// Rebuild the database file without the space left behind by deleted
+    /// rows and refresh the query planner's statistics. Returns how many
+    /// bytes the file shrank by.
+    pub fn compact(&self) -> Result<u64> {
+        let size = || std::fs::metadata(&self.db_path).map_or(0, |m| m.len());
+        let before = size();
+        self.open()?.execute_batch("VACUUM; ANALYZE;")?;
+        let reclaimed = before.saturating_sub(size());
+        tracing::info!("Compacted the downloads database, reclaimed {} bytes", reclaimed);
+        Ok(reclaimed)
+    }
+
+    /// Average a completed transfer's rate into the host's statistics for
+    /// `segments` connections
+    pub fn record_host_throughput(&self, host: &str, segments: usize, bps: f64) -> Result<()> {
+        let conn = self.open()?;
+        retry_busy(|| {
+            conn.execute(
+                "INSERT INTO host_stats (host, segments, samples, avg_bps, updated_at)
+                VALUES (?1, ?2, 1, ?3, CAST(strftime('%s', 'now') AS INTEGER))
+                ON CONFLICT(host, segments) DO UPDATE SET
+                    avg_bps = CASE WHEN samples = 0 THEN excluded.avg_bps
+                        ELSE avg_bps * (1 - ?4) + excluded.avg_bps * ?4 END,
+                    samples = samples + 1,
+                    updated_at = excluded.updated_at",
+                params![host, segments, bps, host_stats::SAMPLE_WEIGHT],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Note that the host ignored Range requests from `segments` connections
+    pub fn record_range_failure(&self, host: &str, segments: usize) -> Result<()> {
+        let conn = self.open()?;
+        retry_busy(|| {
+            conn.execute(
+                "INSERT INTO host_stats (host, segments, range_failures, updated_at)
+                VALUES (?1, ?2, 1, CAST(strftime('%s', 'now') AS INTEGER))
+                ON CONFLICT(host, segments) DO UPDATE SET
+                    range_failures = range_failures + 1,
+                    updated_at = excluded.updated_at",
+                params![host, segments],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Statistics for one host, or for all of them
+    pub fn load_host_stats(&self, host: Option<&str>) -> Result<Vec<HostStats>> {
+        let conn = self.open()?;
+        let mut stmt = conn.prepare(
+            "SELECT host, segments, samples, avg_bps, range_failures, updated_at
+            FROM host_stats WHERE ?1 IS NULL OR host = ?1 ORDER BY host, segments",
+        )?;
+        let stats = stmt
+            .query_map(params![host], |row| {
+                Ok(HostStats {
+                    host: row.get(0)?,
+                    segments: row.get(1)?,
+                    samples: row.get(2)?,
+                    avg_bps: row.get(3)?,
+                    range_failures: row.get(4)?,
+                    updated_at: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(stats)
+    }
+
+    /// A setting saved with `save_setting`, or `None` if it never was
+    pub fn load_setting<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let conn = self.open()?;
+        let value: Option<String> = conn
+            .query_row(
+                "SELECT value FROM settings WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        value
+            .map(|json| serde_json::from_str(&json))
+            .transpose()
+            .with_context(|| format!("Invalid {} setting", key))
+    }
+
+    pub fn save_setting<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        let conn = self.open()?;
+        let json = serde_json::to_string(value)?;
+        retry_busy(|| {
+            conn.execute(
+                "INSERT INTO settings (key, value) VALUES (?1, ?2)
+                ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![key, json],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Add a run of a download's transfer to its statistics. `completed`
+    /// with the file's size once the run finished it.
+    pub fn record_transfer(
+        &self,
+        id: &str,
+        host: &str,
+        secs: f64,
+        completed: Option<u64>,
+    ) -> Result<()> {
+        let conn = self.open()?;
+        retry_busy(|| {
+            conn.execute(
+                "INSERT INTO transfer_stats (download_id, host, active_secs, bytes, completed_at)
+                VALUES (?1, ?2, ?3, ?4,
+                    CASE WHEN ?4 IS NULL THEN NULL ELSE CAST(strftime('%s', 'now') AS INTEGER) END)
+                ON CONFLICT(download_id) DO UPDATE SET
+                    host = excluded.host,
+                    active_secs = active_secs + excluded.active_secs,
+                    bytes = COALESCE(excluded.bytes, bytes),
+                    completed_at = COALESCE(excluded.completed_at, completed_at)",
+                params![id, host, secs, completed],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Lifetime totals and the `top_hosts` hosts most downloaded from
+    pub fn load_statistics(&self, top_hosts: usize) -> Result<Statistics> {
+        let conn = self.open()?;
+        let (completed, bytes, completed_secs, active_secs): (u64, u64, f64, f64) = conn
+            .query_row(
+                "SELECT COUNT(completed_at), COALESCE(SUM(bytes), 0),
+                    COALESCE(SUM(CASE WHEN completed_at IS NULL THEN 0 ELSE active_secs END), 0),
+                    COALESCE(SUM(active_secs), 0)
+                FROM transfer_stats",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )?;
+
+        let mut stmt = conn.prepare(
+            "SELECT host, COUNT(*), SUM(bytes), SUM(active_secs)
+            FROM transfer_stats WHERE completed_at IS NOT NULL AND host != ''
+            GROUP BY host ORDER BY SUM(bytes) DESC LIMIT ?1",
+        )?;
+        let top_hosts = stmt
+            .query_map(params![top_hosts], |row| {
+                let bytes: u64 = row.get(2)?;
+                Ok(HostVolume {
+                    host: row.get(0)?,
+                    completed: row.get(1)?,
+                    bytes,
+                    avg_bps: rate(bytes, row.get(3)?),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(Statistics {
+            completed,
+            bytes,
+            active_secs,
+            avg_bps: rate(bytes, completed_secs),
+            top_hosts,
+        })
+    }
+
+    /// Whether deletions have left enough free pages behind to be worth a
+    /// compaction
+    pub fn needs_compaction(&self) -> Result<bool> {
+        let conn = self.open()?;
+        let free_pages: u64 = conn.query_row("PRAGMA freelist_count", [], |row| row.get(0))?;
+        let page_size: u64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        Ok(free_pages * page_size >= AUTO_COMPACT_FREE_BYTES)
+    }
+
+    /// Delete several downloads at once; returns how many rows went
+    pub fn delete_downloads(&self, ids: &[String]) -> Result<usize> {
+        let mut conn = self.open()?;
+        let removed = retry_busy(|| {
+            let tx = conn.transaction()?;
+            let mut removed = 0;
+            {
+                let mut stmt = tx.prepare("DELETE FROM downloads WHERE id = ?1")?;
+                for id in ids {
+                    removed += stmt.execute(params![id])?;
+                }
+            }
+            tx.commit()?;
+            Ok(removed)
+        })?;
+
+        let mut held = self.session_credentials.lock();
+        for id in ids {
+            held.remove(id);
+        }
+        Ok(removed)
+    }
+}
+
+fn read_credentials(tx: &Transaction) -> Result<Vec<(String, Credentials)>> {
+    let mut stmt = tx.prepare(
+        "SELECT id, cookies, auth, headers, cookies_structured FROM downloads
+         WHERE cookies IS NOT NULL OR auth IS NOT NULL OR headers IS NOT NULL
+            OR cookies_structured IS NOT NULL",
+    )?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                Credentials {
+                    cookies: row.get(1)?,
+                    cookies_structured: row.get(4)?,
+                    auth: row.get(2)?,
+                    headers: row.get(3)?,
+                },
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+fn store_credentials(tx: &Transaction, id: &str, credentials: &Credentials) -> Result<()> {
+    tx.execute(
+        "UPDATE downloads SET cookies = ?1, auth = ?2, headers = ?3, cookies_structured = ?4
+         WHERE id = ?5",
+        params![
+            credentials.cookies,
+            credentials.auth,
+            credentials.headers,
+            credentials.cookies_structured,
+            id
+        ],
+    )?;
+    Ok(())
+}
+
+/// How long a connection waits for another one's lock
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Further attempts at a write that still found the database locked
+const BUSY_RETRIES: u32 = 3;
+
+const BUSY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Run a write, trying again after a short pause if the database stayed
+/// locked for the whole busy timeout. Blocking.
+fn retry_busy<T>(mut write: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut attempt = 0;
+    loop {
+        match write() {
+            Err(e) if is_busy(&e) && attempt < BUSY_RETRIES => {
+                attempt += 1;
+                tracing::warn!("Database is locked, retry {}/{}", attempt, BUSY_RETRIES);
+                std::thread::sleep(BUSY_BACKOFF * attempt);
+            }
+            Err(e) if is_busy(&e) => {
+                return Err(e.context("The downloads database stayed locked by another writer"))
+            }
+            result => return result,
+        }
+    }
 }
 
+fn is_busy(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<rusqlite::Error>(),
+        Some(rusqlite::Error::SqliteFailure(err, _))
+            if matches!(err.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
+}
+
+/// Free space left by deleted rows that triggers an automatic compaction
+const AUTO_COMPACT_FREE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// The columns `download_from_row` reads, in order
+const COLUMNS: &str = "id, url, file_path, file_name, total_size, downloaded_size, status, \
+    cookies, referrer, user_agent, options, created_at, updated_at, resolved_url, auth, headers, \
+    priority, start_at, error_message, checksum, error_details, category, etag, last_modified, \
+    cookies_structured, redirect_chain, \
+    (SELECT group_concat(tag, char(10)) FROM download_tags t WHERE t.download_id = downloads.id), \
+    (SELECT CAST(s.bytes / s.active_secs AS INTEGER) FROM transfer_stats s \
+        WHERE s.download_id = downloads.id AND s.completed_at IS NOT NULL AND s.active_secs > 0)";
+
+/// Status names, as stored, of downloads that may still write their file
+pub const UNFINISHED_STATUSES: [&str; 6] =
+    ["pending", "scheduled", "queued", "downloading", "verifying", "paused"];
+
+/// Status names as stored
+const STATUSES: [&str; 9] = [
+    "pending",
+    "scheduled",
+    "queued",
+    "downloading",
+    "verifying",
+    "paused",
+    "completed",
+    "failed",
+    "cancelled",
+];
+
+/// A download row with its credential columns still as stored; they're
+/// filled in by `with_credentials` once decrypted
+fn download_from_row(row: &Row) -> rusqlite::Result<(DownloadInfo, Credentials)> {
+    let status_str: String = row.get(6)?;
+    let status = match status_str.as_str() {
+        "pending" => DownloadStatus::Pending,
+        "scheduled" => DownloadStatus::Scheduled,
+        "queued" => DownloadStatus::Queued,
+        "downloading" => DownloadStatus::Downloading,
+        "verifying" => DownloadStatus::Verifying,
+        "paused" => DownloadStatus::Paused,
+        "completed" => DownloadStatus::Completed,
+        // Rows failed before errors were classified only have the message
+        "failed" => DownloadStatus::Failed(
+            match row
+                .get::<_, Option<String>>(20)?
+                .and_then(|json| serde_json::from_str(&json).ok())
+            {
+                Some(error) => error,
+                None => DownloadError::Other {
+                    message: row
+                        .get::<_, Option<String>>(18)?
+                        .unwrap_or_else(|| "Unknown error".to_string()),
+                },
+            },
+        ),
+        "cancelled" => DownloadStatus::Cancelled,
+        _ => DownloadStatus::Pending,
+    };
+
+    let options: DownloadOptions = row
+        .get::<_, Option<String>>(10)?
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+    // Credentials live in their own columns so resumed downloads can
+    // re-attach them
+    let stored = Credentials {
+        cookies: row.get(7)?,
+        cookies_structured: row.get(24)?,
+        auth: row.get(14)?,
+        headers: row.get(15)?,
+    };
+
+    let mut info = DownloadInfo {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        resolved_url: row.get(13)?,
+        redirect_chain: row
+            .get::<_, Option<String>>(25)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default(),
+        file_path: PathBuf::from(row.get::<_, String>(2)?),
+        file_name: row.get(3)?,
+        total_size: row.get(4)?,
+        downloaded_size: row.get(5)?,
+        status,
+        cookies: None,
+        cookies_structured: None,
+        referrer: row.get(8)?,
+        user_agent: row.get(9)?,
+        headers: None,
+        priority: row.get(16)?,
+        start_at: row.get(17)?,
+        checksum: row.get(19)?,
+        etag: row.get(22)?,
+        last_modified: row.get(23)?,
+        category: row.get(21)?,
+        // Tags can't contain newlines; see `category::normalize_label`
+        tags: row
+            .get::<_, Option<String>>(26)?
+            .map(|tags| tags.split('\n').map(str::to_string).collect())
+            .unwrap_or_default(),
+        file_missing: false,
+        speed_bps: None,
+        eta_secs: None,
+        throttled_secs: None,
+        effective_user_agent: None,
+        progress: None,
+        queue_position: None,
+        avg_speed_bps: row.get(27)?,
+        options,
+        created_at: row.get(11)?,
+        updated_at: row.get(12)?,
+    };
+    info.progress = info.fraction_done();
+    Ok((info, stored))
+}
+
+/// Ordered upgrade steps; step `i` takes the schema from version `i` to
+/// `i + 1`. Version 0 is the original two-table schema. Steps must be
+/// idempotent, since databases written by development builds may already
+/// have some of the columns.
+const MIGRATIONS: &[fn(&Transaction) -> Result<()>] = &[
+    migrate_v1, migrate_v2, migrate_v3, migrate_v4, migrate_v5, migrate_v6, migrate_v7, migrate_v8,
+    migrate_v9, migrate_v10, migrate_v11, migrate_v12,
+];
+
+/// Bring the database up to the latest schema, tracked in `user_version`
+fn run_migrations(conn: &mut Connection) -> Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    if version == 0 && !table_exists(conn, "downloads")? {
+        // Fresh install: no need to replay history
+        let tx = conn.transaction()?;
+        create_schema(&tx)?;
+        tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+        return Ok(tx.commit()?);
+    }
+
+    for (step, migrate) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        migrate(&tx).with_context(|| format!("Migration to version {} failed", step + 1))?;
+        tx.pragma_update(None, "user_version", step + 1)?;
+        tx.commit()?;
+        tracing::info!("Migrated downloads database to version {}", step + 1);
+    }
+
+    Ok(())
+}
+
+/// The latest schema, used for fresh installs
+fn create_schema(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE downloads (
+            id TEXT PRIMARY KEY,
+            url TEXT NOT NULL,
+            resolved_url TEXT,
+            redirect_chain TEXT,
+            file_path TEXT NOT NULL,
+            file_name TEXT NOT NULL,
+            total_size INTEGER,
+            downloaded_size INTEGER NOT NULL DEFAULT 0,
+            status TEXT NOT NULL,
+            cookies TEXT,
+            cookies_structured TEXT,
+            referrer TEXT,
+            user_agent TEXT,
+            options TEXT,
+            auth TEXT,
+            headers TEXT,
+            priority INTEGER NOT NULL DEFAULT 0,
+            start_at INTEGER,
+            error_message TEXT,
+            checksum TEXT,
+            error_details TEXT,
+            category TEXT,
+            etag TEXT,
+            last_modified TEXT,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    create_segments_table(tx)?;
+    create_tags_table(tx)?;
+    create_host_stats_table(tx)?;
+    create_settings_table(tx)?;
+    create_transfer_stats_table(tx)?;
+    create_indexes(tx)?;
+    create_category_indexes(tx)
+}
+
+/// Indexes for the filtered and sorted queries of the history view.
+/// `(status, updated_at)` also serves plain status filters and counts.
+fn create_indexes(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_downloads_created_at ON downloads(created_at);
+         CREATE INDEX IF NOT EXISTS idx_downloads_updated_at ON downloads(updated_at);
+         CREATE INDEX IF NOT EXISTS idx_downloads_status_updated_at
+             ON downloads(status, updated_at);",
+    )?;
+    Ok(())
+}
+
+fn create_tags_table(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS download_tags (
+            download_id TEXT NOT NULL,
+            tag TEXT NOT NULL,
+            PRIMARY KEY (download_id, tag),
+            FOREIGN KEY (download_id) REFERENCES downloads(id) ON DELETE CASCADE
+        );",
+    )?;
+    Ok(())
+}
+
+fn create_host_stats_table(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS host_stats (
+            host TEXT NOT NULL,
+            segments INTEGER NOT NULL,
+            samples INTEGER NOT NULL DEFAULT 0,
+            avg_bps REAL NOT NULL DEFAULT 0,
+            range_failures INTEGER NOT NULL DEFAULT 0,
+            updated_at INTEGER NOT NULL,
+            PRIMARY KEY (host, segments)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Settings as JSON, by name
+fn create_settings_table(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Kept when downloads are removed, so the totals cover everything ever
+/// downloaded
+fn create_transfer_stats_table(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS transfer_stats (
+            download_id TEXT PRIMARY KEY,
+            host TEXT NOT NULL,
+            active_secs REAL NOT NULL DEFAULT 0,
+            bytes INTEGER,
+            completed_at INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS idx_transfer_stats_host ON transfer_stats(host);",
+    )?;
+    Ok(())
+}
+
+/// Indexes for filtering by category or tag
+fn create_category_indexes(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_downloads_category ON downloads(category);
+         CREATE INDEX IF NOT EXISTS idx_download_tags_tag ON download_tags(tag);",
+    )?;
+    Ok(())
+}
+
+fn create_segments_table(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS download_segments (
+            download_id TEXT NOT NULL,
+            segment_index INTEGER NOT NULL,
+            start_byte INTEGER NOT NULL,
+            end_byte INTEGER NOT NULL,
+            downloaded_bytes INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (download_id, segment_index),
+            FOREIGN KEY (download_id) REFERENCES downloads(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Redirect tracking, per-download options and credentials, custom
+/// headers, priorities and schedules
+fn migrate_v1(tx: &Transaction) -> Result<()> {
+    add_column(tx, "downloads", "resolved_url", "TEXT")?;
+    add_column(tx, "downloads", "options", "TEXT")?;
+    add_column(tx, "downloads", "auth", "TEXT")?;
+    add_column(tx, "downloads", "headers", "TEXT")?;
+    add_column(tx, "downloads", "priority", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column(tx, "downloads", "start_at", "INTEGER")?;
+    create_segments_table(tx)
+}
+
+/// Keep the cause of failed downloads
+fn migrate_v2(tx: &Transaction) -> Result<()> {
+    add_column(tx, "downloads", "error_message", "TEXT")
+}
+
+/// Digest of the finished file
+fn migrate_v3(tx: &Transaction) -> Result<()> {
+    add_column(tx, "downloads", "checksum", "TEXT")
+}
+
+/// Indexes for filtering and sorting large histories
+fn migrate_v4(tx: &Transaction) -> Result<()> {
+    create_indexes(tx)
+}
+
+/// Structured failure causes, alongside the message
+fn migrate_v5(tx: &Transaction) -> Result<()> {
+    add_column(tx, "downloads", "error_details", "TEXT")
+}
+
+/// Categories and tags
+fn migrate_v6(tx: &Transaction) -> Result<()> {
+    add_column(tx, "downloads", "category", "TEXT")?;
+    create_tags_table(tx)?;
+    create_category_indexes(tx)
+}
+
+/// Per-host throughput for picking segment counts
+fn migrate_v7(tx: &Transaction) -> Result<()> {
+    create_host_stats_table(tx)
+}
+
+/// Validators for resuming with If-Range
+fn migrate_v8(tx: &Transaction) -> Result<()> {
+    add_column(tx, "downloads", "etag", "TEXT")?;
+    add_column(tx, "downloads", "last_modified", "TEXT")
+}
+
+/// Cookies with their domain and path
+fn migrate_v9(tx: &Transaction) -> Result<()> {
+    add_column(tx, "downloads", "cookies_structured", "TEXT")
+}
+
+/// Settings that outlive the session
+fn migrate_v10(tx: &Transaction) -> Result<()> {
+    create_settings_table(tx)
+}
+
+/// The redirects a download went through
+fn migrate_v11(tx: &Transaction) -> Result<()> {
+    add_column(tx, "downloads", "redirect_chain", "TEXT")
+}
+
+/// Transfer time and volume for statistics
+fn migrate_v12(tx: &Transaction) -> Result<()> {
+    create_transfer_stats_table(tx)
+}
+
+fn table_exists(conn: &Connection, table: &str) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        params![table],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Bytes per second, 0 without any time to divide by
+fn rate(bytes: u64, secs: f64) -> u64 {
+    if secs > 0.0 {
+        (bytes as f64 / secs) as u64
+    } else {
+        0
+    }
+}
+
+/// `ALTER TABLE ... ADD COLUMN` that is a no-op if the column exists
+fn add_column(tx: &Transaction, table: &str, column: &str, definition: &str) -> Result<()> {
+    let exists = tx
+        .prepare(&format!("PRAGMA table_info({})", table))?
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .iter()
+        .any(|name| name == column);
+
+    if !exists {
+        tx.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+    Ok(())
+}
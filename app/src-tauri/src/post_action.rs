@@ -0,0 +1,188 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::downloader::free_path;
+
+/// Placeholder for the downloaded file in a `Run` command
+const FILE_PLACEHOLDER: &str = "%f";
+
+/// Most lines of a failed command's output kept in the error
+const ERROR_OUTPUT_LINES: usize = 5;
+
+/// What to do with a file once its download has completed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum PostAction {
+    /// Move the file into `dir`
+    Move { dir: PathBuf },
+    /// Copy the file into `dir`, leaving the download where it is
+    Copy { dir: PathBuf },
+    /// Run a program, with `%f` in any argument replaced by the file's path.
+    /// The command line is split into arguments here rather than handed to
+    /// a shell, so a file name can't inject anything into it.
+    Run { command: String },
+}
+
+impl PostAction {
+    /// Reject actions that can't work, before the download starts
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            PostAction::Move { dir } | PostAction::Copy { dir } => {
+                if !dir.is_absolute() {
+                    anyhow::bail!("{} is not an absolute path", dir.display());
+                }
+            }
+            PostAction::Run { command } => {
+                split_command(command)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Carry out the action on the file at `path` and return where the
+    /// download's file is afterwards. Blocking; run it off the async
+    /// runtime.
+    pub fn execute(&self, path: &Path) -> Result<PathBuf> {
+        match self {
+            PostAction::Move { dir } => {
+                let target = target_in(dir, path)?;
+                move_file(path, &target)?;
+                Ok(target)
+            }
+            PostAction::Copy { dir } => {
+                copy(path, &target_in(dir, path)?)?;
+                Ok(path.to_path_buf())
+            }
+            PostAction::Run { command } => {
+                run(command, path)?;
+                Ok(path.to_path_buf())
+            }
+        }
+    }
+}
+
+/// A free path for `file` inside `dir`, creating `dir` if needed
+fn target_in(dir: &Path, file: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    let name = file.file_name().context("Download has no file name")?;
+    Ok(free_path(&dir.join(name), |p| p.exists()))
+}
+
+/// Move a file, also to another filesystem. Blocking.
+pub(crate) fn move_file(from: &Path, to: &Path) -> Result<()> {
+    // A rename can't cross filesystems; copy and remove instead
+    if std::fs::rename(from, to).is_err() {
+        copy(from, to)?;
+        std::fs::remove_file(from)
+            .with_context(|| format!("Failed to remove {}", from.display()))?;
+    }
+    Ok(())
+}
+
+fn copy(from: &Path, to: &Path) -> Result<()> {
+    if from.is_dir() {
+        anyhow::bail!("{} is a folder; only files can be copied", from.display());
+    }
+    std::fs::copy(from, to)
+        .with_context(|| format!("Failed to copy to {}", to.display()))?;
+    Ok(())
+}
+
+fn run(command: &str, path: &Path) -> Result<()> {
+    let path = path.to_string_lossy();
+    let mut args = split_command(command)?
+        .into_iter()
+        .map(|arg| arg.replace(FILE_PLACEHOLDER, &path));
+    let program = args.next().context("Empty command")?;
+
+    let output = Command::new(&program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to run {}", program))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    tracing::info!(
+        "{} exited with {} for {}\nstdout: {}\nstderr: {}",
+        program,
+        output.status,
+        path,
+        stdout.trim_end(),
+        stderr.trim_end()
+    );
+
+    if !output.status.success() {
+        let lines: Vec<&str> = stderr.lines().filter(|l| !l.trim().is_empty()).collect();
+        let tail = lines[lines.len().saturating_sub(ERROR_OUTPUT_LINES)..].join("\n");
+        if tail.is_empty() {
+            anyhow::bail!("{} exited with {}", program, output.status);
+        }
+        anyhow::bail!("{} exited with {}: {}", program, output.status, tail);
+    }
+    Ok(())
+}
+
+/// Split a command line on whitespace, keeping double-quoted runs together
+fn split_command(command: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quoted = false;
+
+    for c in command.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if quoted {
+        anyhow::bail!("Unterminated quote in command");
+    }
+    if in_arg {
+        args.push(current);
+    }
+    if args.is_empty() {
+        anyhow::bail!("Empty command");
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_command_keeps_quoted_runs_together() {
+        assert_eq!(
+            split_command("  /usr/bin/tool  -x \"a file.txt\" end ").unwrap(),
+            ["/usr/bin/tool", "-x", "a file.txt", "end"]
+        );
+        assert_eq!(
+            split_command("\"C:\\Program Files\\app.exe\" --in=\"x y\"").unwrap(),
+            ["C:\\Program Files\\app.exe", "--in=x y"]
+        );
+        assert_eq!(split_command("tool \"\"").unwrap(), ["tool", ""]);
+    }
+
+    #[test]
+    fn split_command_rejects_unterminated_quotes_and_empty_commands() {
+        assert!(split_command("tool \"open").is_err());
+        assert!(split_command("").is_err());
+        assert!(split_command("   ").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+pub const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 3;
+
+/// Limits how many downloads transfer at once. Downloads beyond the limit
+/// wait ordered by priority (highest first, FIFO among equals); only the
+/// head of the queue competes for a free slot, so the order here is the
+/// order in which they start.
+pub struct DownloadQueue {
+    slots: Arc<Semaphore>,
+    limit: Mutex<usize>,
+    waiting: Mutex<Vec<Waiting>>,
+    changed: Notify,
+}
+
+struct Waiting {
+    id: String,
+    priority: i32,
+}
+
+impl DownloadQueue {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(limit)),
+            limit: Mutex::new(limit),
+            waiting: Mutex::new(Vec::new()),
+            changed: Notify::new(),
+        }
+    }
+
+    /// Take a slot right away if one is free and nobody is queued ahead
+    pub fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        let waiting = self.waiting.lock();
+        if !waiting.is_empty() {
+            return None;
+        }
+        Arc::clone(&self.slots).try_acquire_owned().ok()
+    }
+
+    /// Join the queue behind everything of equal or higher priority and wait
+    /// for a slot. If the future is dropped before it resolves, call
+    /// [`DownloadQueue::remove`].
+    pub async fn acquire(&self, id: &str, priority: i32) -> OwnedSemaphorePermit {
+        self.join(id, priority);
+
+        loop {
+            let notified = self.changed.notified();
+            if !self.is_head(id) {
+                notified.await;
+                continue;
+            }
+
+            // Give the slot up again if something of higher priority jumped
+            // ahead while this one was waiting for it
+            tokio::select! {
+                permit = Arc::clone(&self.slots).acquire_owned() => {
+                    if self.is_head(id) {
+                        self.remove(id);
+                        return permit.expect("download slots are never closed");
+                    }
+                }
+                _ = notified => {}
+            }
+        }
+    }
+
+    /// Take a place in the queue ahead of [`DownloadQueue::acquire`], so
+    /// the download has a position as soon as it's marked queued. A no-op
+    /// for downloads already waiting.
+    pub fn join(&self, id: &str, priority: i32) {
+        let mut waiting = self.waiting.lock();
+        if !waiting.iter().any(|w| w.id == id) {
+            insert_by_priority(
+                &mut waiting,
+                Waiting {
+                    id: id.to_string(),
+                    priority,
+                },
+            );
+        }
+    }
+
+    /// Drop a download from the queue, e.g. because it was paused or
+    /// cancelled while waiting
+    pub fn remove(&self, id: &str) {
+        self.waiting.lock().retain(|w| w.id != id);
+        self.changed.notify_waiters();
+    }
+
+    /// Move a queued download to its place for the new priority. A no-op
+    /// for downloads that aren't waiting.
+    pub fn set_priority(&self, id: &str, priority: i32) {
+        let mut waiting = self.waiting.lock();
+        if let Some(index) = waiting.iter().position(|w| w.id == id) {
+            let mut entry = waiting.remove(index);
+            entry.priority = priority;
+            insert_by_priority(&mut waiting, entry);
+            self.changed.notify_waiters();
+        }
+    }
+
+    fn is_head(&self, id: &str) -> bool {
+        self.waiting.lock().first().map(|w| w.id.as_str()) == Some(id)
+    }
+
+    /// Zero-based position in the queue, `None` if not waiting
+    pub fn position(&self, id: &str) -> Option<usize> {
+        self.waiting.lock().iter().position(|w| w.id == id)
+    }
+
+    /// Ids of the waiting downloads, next to start first
+    pub fn waiting(&self) -> Vec<String> {
+        self.waiting.lock().iter().map(|w| w.id.clone()).collect()
+    }
+
+    pub fn waiting_count(&self) -> usize {
+        self.waiting.lock().len()
+    }
+
+    pub fn limit(&self) -> usize {
+        *self.limit.lock()
+    }
+
+    /// Change the limit at runtime. Shrinking does not interrupt running
+    /// downloads; the surplus slots are retired as they free up.
+    pub fn set_limit(&self, new_limit: usize) -> Result<()> {
+        if new_limit == 0 {
+            bail!("At least one concurrent download is required");
+        }
+
+        let mut limit = self.limit.lock();
+        if new_limit > *limit {
+            self.slots.add_permits(new_limit - *limit);
+        } else if new_limit < *limit {
+            let surplus = *limit - new_limit;
+            let retired = self.slots.forget_permits(surplus);
+            if retired < surplus {
+                let slots = Arc::clone(&self.slots);
+                tokio::spawn(async move {
+                    if let Ok(permits) = slots.acquire_many_owned((surplus - retired) as u32).await {
+                        permits.forget();
+                    }
+                });
+            }
+        }
+        *limit = new_limit;
+        Ok(())
+    }
+}
+
+fn insert_by_priority(waiting: &mut Vec<Waiting>, entry: Waiting) {
+    let index = waiting.partition_point(|w| w.priority >= entry.priority);
+    waiting.insert(index, entry);
+}
@@ -0,0 +1,122 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use publicsuffix::Psl;
+use reqwest::redirect::{Attempt, Policy};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, OnceLock};
+
+/// Upper bound for a configured number of redirects
+const MAX_REDIRECTS_LIMIT: usize = 50;
+
+/// Which redirects a download follows
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedirectPolicy {
+    /// Redirects followed from one URL before giving up
+    pub max_redirects: usize,
+    /// Fail the download if a redirect leads to another site than the
+    /// URL's own
+    pub same_site_only: bool,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self {
+            max_redirects: 10,
+            same_site_only: false,
+        }
+    }
+}
+
+impl RedirectPolicy {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_redirects > MAX_REDIRECTS_LIMIT {
+            anyhow::bail!("max_redirects must be at most {}", MAX_REDIRECTS_LIMIT);
+        }
+        Ok(())
+    }
+
+    /// The policy for a client, writing each chain it follows into `chain`
+    pub fn to_reqwest(self, same_site_only: bool, chain: RedirectChain) -> Policy {
+        Policy::custom(move |attempt| {
+            let mut urls: Vec<String> = attempt.previous().iter().map(Url::to_string).collect();
+            urls.push(attempt.url().to_string());
+            *chain.0.lock() = urls;
+
+            if attempt.previous().len() > self.max_redirects {
+                let message = format!("Stopped after {} redirects", self.max_redirects);
+                return attempt.error(message);
+            }
+            if same_site_only {
+                if let Some(message) = leaves_site(&attempt) {
+                    return attempt.error(message);
+                }
+            }
+            attempt.follow()
+        })
+    }
+}
+
+/// The redirects a client's latest request went through, starting URL
+/// first. Empty if it wasn't redirected.
+#[derive(Clone, Default)]
+pub struct RedirectChain(Arc<Mutex<Vec<String>>>);
+
+impl RedirectChain {
+    pub fn urls(&self) -> Vec<String> {
+        self.0.lock().clone()
+    }
+}
+
+fn leaves_site(attempt: &Attempt) -> Option<String> {
+    let from = attempt.previous().first()?;
+    let to = attempt.url();
+    // IP addresses have no domain and must match exactly
+    let same = match (from.domain(), to.domain()) {
+        (Some(a), Some(b)) => site(a) == site(b),
+        _ => from.host_str() == to.host_str(),
+    };
+    (!same).then(|| {
+        format!(
+            "Refused a redirect from {} to {}, another site",
+            from.host_str().unwrap_or_default(),
+            to.host_str().unwrap_or_default()
+        )
+    })
+}
+
+/// Mozilla's Public Suffix List, from https://publicsuffix.org/list/.
+/// Parsed on first use.
+static PUBLIC_SUFFIXES: OnceLock<publicsuffix::List> = OnceLock::new();
+
+/// A host's registrable domain by the Public Suffix List, so tenants of a
+/// shared host like `github.io` count as separate sites. A host that has
+/// none, like `localhost`, is its own site.
+fn site(host: &str) -> String {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let list = PUBLIC_SUFFIXES.get_or_init(|| {
+        publicsuffix::List::from_bytes(include_bytes!("public_suffix_list.dat"))
+            .expect("the bundled public suffix list is valid")
+    });
+    match list.domain(host.as_bytes()) {
+        Some(domain) => String::from_utf8_lossy(domain.as_bytes()).into_owned(),
+        None => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn site_follows_the_public_suffix_list() {
+        assert_eq!(site("cdn.x.io"), site("www.x.io"));
+        assert_eq!(site("downloads.example.co.uk"), "example.co.uk");
+        assert_eq!(site("Example.COM."), "example.com");
+        assert_ne!(site("a.github.io"), site("b.github.io"));
+        assert_ne!(site("x.herokuapp.com"), site("y.herokuapp.com"));
+        assert_ne!(site("bucket.s3.amazonaws.com"), site("other.s3.amazonaws.com"));
+        assert_eq!(site("localhost"), "localhost");
+    }
+}
@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use crate::state::AppState;
+
+/// How often scheduled downloads are checked for being due, and the speed
+/// schedule for a new limit
+const SCHEDULER_TICK: Duration = Duration::from_secs(15);
+
+/// Periodically start `Scheduled` downloads whose time has come and move
+/// the overall speed limit along the speed schedule. Going through the
+/// shared state means every tick sees the current manager configuration.
+pub fn spawn(state: AppState) {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(SCHEDULER_TICK);
+        loop {
+            interval.tick().await;
+            let manager = state.download_manager.read().await;
+            manager.apply_speed_schedule();
+            if let Err(e) = manager.start_due_downloads().await {
+                tracing::warn!("Failed to start scheduled downloads: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,89 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// Keychain entry that holds the database key
+const KEYCHAIN_SERVICE: &str = "com.gripdl.app";
+const KEYCHAIN_USER: &str = "downloads-db-key";
+
+/// Marks an encrypted column value; anything else is legacy plaintext
+const PREFIX: &str = "enc1:";
+
+const NONCE_LEN: usize = 12;
+
+/// Encrypts credential columns in downloads.db with AES-256-GCM, under a
+/// key kept in the OS keychain rather than next to the database
+#[derive(Clone)]
+pub struct Secrets {
+    cipher: Aes256Gcm,
+}
+
+impl Secrets {
+    /// Load the key from the keychain, creating it on first run
+    pub fn from_keychain() -> Result<Self> {
+        let entry = keyring::Entry::new(KEYCHAIN_SERVICE, KEYCHAIN_USER)?;
+        let key = match entry.get_password() {
+            Ok(encoded) => {
+                let bytes = STANDARD
+                    .decode(encoded.trim())
+                    .context("Malformed database key in the keychain")?;
+                if bytes.len() != 32 {
+                    anyhow::bail!("Database key in the keychain has the wrong length");
+                }
+                *Key::<Aes256Gcm>::from_slice(&bytes)
+            }
+            Err(keyring::Error::NoEntry) => {
+                let key = Aes256Gcm::generate_key(OsRng);
+                entry
+                    .set_password(&STANDARD.encode(key))
+                    .context("Failed to store the database key in the keychain")?;
+                key
+            }
+            Err(e) => return Err(e).context("Failed to read the database key from the keychain"),
+        };
+
+        Ok(Self {
+            cipher: Aes256Gcm::new(&key),
+        })
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt credentials"))?;
+
+        let mut blob = nonce.to_vec();
+        blob.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}", PREFIX, STANDARD.encode(blob)))
+    }
+
+    /// Decrypt a column value. Values written before encryption was added
+    /// come back unchanged.
+    pub fn decrypt(&self, value: &str) -> Result<String> {
+        let Some(encoded) = value.strip_prefix(PREFIX) else {
+            return Ok(value.to_string());
+        };
+        let blob = STANDARD
+            .decode(encoded)
+            .context("Malformed encrypted value")?;
+        if blob.len() < NONCE_LEN {
+            anyhow::bail!("Malformed encrypted value");
+        }
+
+        let (nonce, ciphertext) = blob.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt credentials (was the key replaced?)"))?;
+        String::from_utf8(plaintext).context("Decrypted credentials are not UTF-8")
+    }
+}
+
+/// Whether a column value is already encrypted
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
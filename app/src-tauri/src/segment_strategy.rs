@@ -0,0 +1,65 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+const MB: u64 = 1024 * 1024;
+
+/// How many segments a file is split into, by its size. Small files aren't
+/// worth the extra requests, mid-size ones get a few connections, and
+/// large ones get one per `target_segment_size` up to the maximum.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SegmentStrategy {
+    /// Files smaller than this download on one connection
+    pub single_below: u64,
+    /// Files from this size on count as large
+    pub large_from: u64,
+    /// Segments for files between `single_below` and `large_from`
+    pub medium_segments: usize,
+    /// Size large files aim for per segment
+    pub target_segment_size: u64,
+}
+
+impl Default for SegmentStrategy {
+    fn default() -> Self {
+        Self {
+            single_below: 5 * MB,
+            large_from: 256 * MB,
+            medium_segments: 4,
+            target_segment_size: 64 * MB,
+        }
+    }
+}
+
+impl SegmentStrategy {
+    pub fn validate(&self) -> Result<()> {
+        if self.single_below > self.large_from {
+            anyhow::bail!("single_below must not be larger than large_from");
+        }
+        if self.medium_segments == 0 {
+            anyhow::bail!("medium_segments must be positive");
+        }
+        if self.target_segment_size == 0 {
+            anyhow::bail!("target_segment_size must be positive");
+        }
+        Ok(())
+    }
+
+    /// Segments for a file of `total_size` bytes, never more than
+    /// `max_segments` nor so many that one is smaller than `min_segment_size`
+    pub fn segment_count(
+        &self,
+        total_size: u64,
+        max_segments: usize,
+        min_segment_size: u64,
+    ) -> usize {
+        let wanted = if total_size < self.single_below {
+            1
+        } else if total_size < self.large_from {
+            self.medium_segments
+        } else {
+            total_size.div_ceil(self.target_segment_size) as usize
+        };
+        let fits = (total_size / min_segment_size.max(1)) as usize;
+        wanted.min(max_segments).min(fits).max(1)
+    }
+}
@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use crate::category::CategoryRule;
+use crate::connections::{DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_CONNECTIONS_PER_HOST};
+use crate::downloader::{
+    DEFAULT_MAX_SEGMENTS, DEFAULT_MIN_SEGMENT_SIZE, DEFAULT_SEGMENTS_PER_CORE,
+    DEFAULT_SEGMENT_MEMORY_BUDGET, DEFAULT_SEGMENT_SAVE_INTERVAL, DEFAULT_WRITE_BUFFER_SIZE,
+};
+use crate::network::IpPreference;
+use crate::notifications::NotificationSettings;
+use crate::queue::DEFAULT_MAX_CONCURRENT_DOWNLOADS;
+use crate::redirects::RedirectPolicy;
+use crate::segment_strategy::SegmentStrategy;
+use crate::user_agent::UserAgentSettings;
+
+/// Key the settings are saved under in the settings table
+pub const SETTINGS_KEY: &str = "settings";
+
+/// Application settings, saved with `update_settings` and applied at
+/// startup. Fields missing from a saved copy take their defaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Folder for downloads that don't pick their own; `None` for the
+    /// system's Downloads folder
+    pub download_dir: Option<PathBuf>,
+    pub max_concurrent: usize,
+    pub max_connections: usize,
+    pub max_connections_per_host: usize,
+    pub max_segments: usize,
+    pub min_segment_size: u64,
+    /// 0 for no cap by CPU cores
+    pub segments_per_core: usize,
+    /// Bytes; `None` for no cap by memory
+    pub segment_memory_budget: Option<u64>,
+    pub segment_strategy: SegmentStrategy,
+    pub default_proxy: Option<String>,
+    /// Bytes; `None` for no limit
+    pub max_size: Option<u64>,
+    pub preallocate: bool,
+    pub write_buffer_size: usize,
+    pub segment_save_interval_ms: u64,
+    pub redirect_policy: RedirectPolicy,
+    /// Address connections are bound to; `None` to let the system choose
+    pub local_address: Option<IpAddr>,
+    pub ip_preference: IpPreference,
+    pub notifications: NotificationSettings,
+    pub user_agents: UserAgentSettings,
+    pub category_rules: Vec<CategoryRule>,
+    pub sidecar_files: bool,
+    pub allow_post_commands: bool,
+    /// Keep cookies and credentials on disk (encrypted) rather than only
+    /// for the session
+    pub persist_credentials: bool,
+    pub purge_finished_credentials: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            download_dir: None,
+            max_concurrent: DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            max_connections_per_host: DEFAULT_MAX_CONNECTIONS_PER_HOST,
+            max_segments: DEFAULT_MAX_SEGMENTS,
+            min_segment_size: DEFAULT_MIN_SEGMENT_SIZE,
+            segments_per_core: DEFAULT_SEGMENTS_PER_CORE,
+            segment_memory_budget: Some(DEFAULT_SEGMENT_MEMORY_BUDGET),
+            segment_strategy: SegmentStrategy::default(),
+            default_proxy: None,
+            max_size: None,
+            preallocate: false,
+            write_buffer_size: DEFAULT_WRITE_BUFFER_SIZE,
+            segment_save_interval_ms: DEFAULT_SEGMENT_SAVE_INTERVAL.as_millis() as u64,
+            redirect_policy: RedirectPolicy::default(),
+            local_address: None,
+            ip_preference: IpPreference::default(),
+            notifications: NotificationSettings::default(),
+            user_agents: UserAgentSettings::default(),
+            category_rules: Vec::new(),
+            sidecar_files: false,
+            allow_post_commands: false,
+            persist_credentials: true,
+            purge_finished_credentials: false,
+        }
+    }
+}
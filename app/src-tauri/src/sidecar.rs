@@ -0,0 +1,98 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::downloader::{DownloadInfo, DownloadStatus, Segment};
+
+/// Appended to a download's file name for its sidecar
+const SIDECAR_SUFFIX: &str = ".gripdl";
+
+/// What the sidecar holds: enough for another tool to see how far the
+/// download got and continue it, even without GripDL's database
+#[derive(Serialize)]
+struct Sidecar<'a> {
+    id: &'a str,
+    url: &'a str,
+    resolved_url: Option<&'a str>,
+    file_path: &'a Path,
+    /// Where the bytes are written until the download completes
+    temp_path: PathBuf,
+    total_size: Option<u64>,
+    downloaded_size: u64,
+    status: &'a DownloadStatus,
+    /// Empty for a single-connection download, whose bytes are a
+    /// contiguous prefix of the temp file
+    segments: &'a [Segment],
+    etag: Option<&'a str>,
+    last_modified: Option<&'a str>,
+    updated_at: i64,
+}
+
+/// A sidecar read back, describing a partial download whose entry may no
+/// longer exist
+#[derive(Deserialize)]
+pub struct Partial {
+    pub url: String,
+    pub total_size: Option<u64>,
+    pub downloaded_size: u64,
+    #[serde(default)]
+    pub segments: Vec<Segment>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// `<file>.gripdl`, next to the file being downloaded
+pub fn sidecar_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.file_name().unwrap_or_default().to_os_string();
+    name.push(SIDECAR_SUFFIX);
+    file_path.with_file_name(name)
+}
+
+/// Write the sidecar of an unfinished download. It's replaced in one
+/// rename, so readers never see half of it.
+pub async fn write(info: &DownloadInfo, segments: &[Segment]) -> Result<()> {
+    let sidecar = Sidecar {
+        id: &info.id,
+        url: &info.url,
+        resolved_url: info.resolved_url.as_deref(),
+        file_path: &info.file_path,
+        temp_path: crate::downloader::temp_path(&info.file_path),
+        total_size: info.total_size,
+        downloaded_size: info.downloaded_size,
+        status: &info.status,
+        segments,
+        etag: info.etag.as_deref(),
+        last_modified: info.last_modified.as_deref(),
+        updated_at: info.updated_at,
+    };
+    let json = serde_json::to_vec_pretty(&sidecar)?;
+
+    let path = sidecar_path(&info.file_path);
+    let mut staging = path.clone().into_os_string();
+    staging.push(".new");
+    tokio::fs::write(&staging, json).await?;
+    tokio::fs::rename(&staging, &path).await?;
+    Ok(())
+}
+
+/// The sidecar of `file_path`, if there is a readable one
+pub async fn read(file_path: &Path) -> Option<Partial> {
+    let path = sidecar_path(file_path);
+    let json = tokio::fs::read(&path).await.ok()?;
+    serde_json::from_slice(&json)
+        .map_err(|e| tracing::warn!("Ignoring unreadable sidecar {}: {}", path.display(), e))
+        .ok()
+}
+
+/// Remove the sidecar of `file_path`, if there is one
+pub fn remove(file_path: &Path) {
+    match std::fs::remove_file(sidecar_path(file_path)) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => tracing::warn!(
+            "Failed to remove the sidecar of {}: {}",
+            file_path.display(),
+            e
+        ),
+    }
+}
@@ -0,0 +1,56 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Samples older than this no longer count towards the speed
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+
+/// Transfer rate of one download, averaged over a sliding window so the
+/// number shown doesn't jump with every chunk
+#[derive(Default)]
+pub struct SpeedTracker {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl SpeedTracker {
+    /// Record the download's byte count as of now
+    pub fn record(&mut self, downloaded: u64) {
+        let now = Instant::now();
+
+        // A restart from byte 0 invalidates the history
+        if self.samples.back().is_some_and(|&(_, bytes)| downloaded < bytes) {
+            self.samples.clear();
+        }
+        self.samples.push_back((now, downloaded));
+
+        // Keep one sample at or beyond the window edge as the baseline
+        while self.samples.len() > 2
+            && now.duration_since(self.samples[1].0) >= SPEED_WINDOW
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn last_sample_at(&self) -> Option<Instant> {
+        self.samples.back().map(|&(at, _)| at)
+    }
+
+    /// Average bytes per second across the window, `None` until there
+    /// are two samples far enough apart to measure
+    pub fn bytes_per_sec(&self) -> Option<u64> {
+        let (first_at, first_bytes) = *self.samples.front()?;
+        let (last_at, last_bytes) = *self.samples.back()?;
+        let elapsed = last_at.duration_since(first_at).as_secs_f64();
+        if elapsed < 0.5 {
+            return None;
+        }
+        Some(((last_bytes - first_bytes) as f64 / elapsed) as u64)
+    }
+
+    /// Seconds until `remaining` more bytes arrive at the current speed
+    pub fn eta_secs(&self, remaining: u64) -> Option<u64> {
+        match self.bytes_per_sec()? {
+            0 => None,
+            speed => Some(remaining.div_ceil(speed)),
+        }
+    }
+}
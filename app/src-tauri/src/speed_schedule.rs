@@ -0,0 +1,135 @@
+use anyhow::{Context, Result};
+use chrono::Timelike;
+use serde::{Deserialize, Serialize};
+
+/// Key the schedule is saved under in the settings table
+pub const SETTINGS_KEY: &str = "speed_schedule";
+
+/// A limit on the combined speed of all downloads for part of the day
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpeedProfile {
+    /// Local time as `HH:MM`
+    pub start: String,
+    /// Local time as `HH:MM`, exclusive. Before `start` the profile runs
+    /// past midnight; equal to it, all day.
+    pub end: String,
+    /// Bytes per second; `None` for full speed
+    pub rate_limit: Option<u64>,
+}
+
+/// Rate limits by time of day. The first profile covering the current
+/// time applies; outside all of them downloads run at full speed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpeedSchedule {
+    pub profiles: Vec<SpeedProfile>,
+}
+
+impl SpeedSchedule {
+    pub fn validate(&self) -> Result<()> {
+        for profile in &self.profiles {
+            minutes(&profile.start)?;
+            minutes(&profile.end)?;
+        }
+        Ok(())
+    }
+
+    /// The limit in force now
+    pub fn current_limit(&self) -> Option<u64> {
+        let now = chrono::Local::now();
+        self.limit_at(now.hour() * 60 + now.minute())
+    }
+
+    fn limit_at(&self, minute: u32) -> Option<u64> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.covers(minute))
+            .and_then(|profile| profile.rate_limit)
+            .filter(|&rate| rate > 0)
+    }
+}
+
+impl SpeedProfile {
+    fn covers(&self, minute: u32) -> bool {
+        let (Ok(start), Ok(end)) = (minutes(&self.start), minutes(&self.end)) else {
+            return false;
+        };
+        match start.cmp(&end) {
+            std::cmp::Ordering::Less => (start..end).contains(&minute),
+            std::cmp::Ordering::Greater => minute >= start || minute < end,
+            std::cmp::Ordering::Equal => true,
+        }
+    }
+}
+
+/// Minutes after midnight of an `HH:MM` time
+fn minutes(time: &str) -> Result<u32> {
+    let invalid = || format!("Invalid time {:?}; expected HH:MM", time);
+    let (hours, mins) = time.trim().split_once(':').with_context(invalid)?;
+    let hours: u32 = hours.parse().with_context(invalid)?;
+    let mins: u32 = mins.parse().with_context(invalid)?;
+    if hours > 23 || mins > 59 {
+        anyhow::bail!(invalid());
+    }
+    Ok(hours * 60 + mins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(start: &str, end: &str, rate_limit: Option<u64>) -> SpeedProfile {
+        SpeedProfile {
+            start: start.to_string(),
+            end: end.to_string(),
+            rate_limit,
+        }
+    }
+
+    #[test]
+    fn covers_daytime_ranges() {
+        let day = profile("09:00", "17:30", None);
+        assert!(!day.covers(8 * 60 + 59));
+        assert!(day.covers(9 * 60));
+        assert!(day.covers(17 * 60 + 29));
+        assert!(!day.covers(17 * 60 + 30));
+    }
+
+    #[test]
+    fn covers_ranges_past_midnight() {
+        let night = profile("22:00", "06:00", None);
+        assert!(night.covers(22 * 60));
+        assert!(night.covers(23 * 60 + 59));
+        assert!(night.covers(0));
+        assert!(night.covers(5 * 60 + 59));
+        assert!(!night.covers(6 * 60));
+        assert!(!night.covers(21 * 60 + 59));
+    }
+
+    #[test]
+    fn covers_all_day_when_start_equals_end() {
+        let all_day = profile("00:00", "00:00", None);
+        assert!(all_day.covers(0));
+        assert!(all_day.covers(23 * 60 + 59));
+        assert!(!profile("bad", "00:00", None).covers(0));
+    }
+
+    #[test]
+    fn first_covering_profile_applies() {
+        let schedule = SpeedSchedule {
+            profiles: vec![
+                profile("22:00", "06:00", Some(0)),
+                profile("00:00", "00:00", Some(1000)),
+            ],
+        };
+        // A zero limit means full speed, and still shadows later profiles
+        assert_eq!(schedule.limit_at(23 * 60), None);
+        assert_eq!(schedule.limit_at(12 * 60), Some(1000));
+        assert!(schedule.validate().is_ok());
+        assert!(SpeedSchedule {
+            profiles: vec![profile("24:00", "01:00", None)],
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,29 @@
+use serde::Serialize;
+
+/// Hosts listed in `Statistics::top_hosts`
+pub const TOP_HOSTS: usize = 10;
+
+/// Lifetime transfer totals, from `get_statistics`. Downloads removed from
+/// the list still count.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Statistics {
+    /// Downloads completed
+    pub completed: u64,
+    /// Bytes of the completed downloads
+    pub bytes: u64,
+    /// Time spent transferring, including downloads that didn't complete
+    pub active_secs: f64,
+    /// `bytes` over the time the completed downloads took
+    pub avg_bps: u64,
+    /// Hosts by bytes downloaded, most first
+    pub top_hosts: Vec<HostVolume>,
+}
+
+/// What was downloaded from one host
+#[derive(Debug, Clone, Serialize)]
+pub struct HostVolume {
+    pub host: String,
+    pub completed: u64,
+    pub bytes: u64,
+    pub avg_bps: u64,
+}
@@ -0,0 +1,156 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use librqbit::{AddTorrent, AddTorrentOptions, AddTorrentResponse, ManagedTorrent, Session};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::OnceCell;
+
+type ManagedTorrentHandle = Arc<ManagedTorrent>;
+
+use crate::backend::{ControlHandle, DownloadBackend, DownloadCtx};
+use crate::downloader::resolve_target_path;
+
+/// How often swarm progress is copied into the download
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// One BitTorrent session shared by every torrent download
+static SESSION: OnceCell<Arc<Session>> = OnceCell::const_new();
+
+/// Torrents added to the session, by download id, so a resume picks up
+/// the paused torrent instead of adding it again
+static HANDLES: LazyLock<Mutex<HashMap<String, ManagedTorrentHandle>>> =
+    LazyLock::new(Default::default);
+
+/// `magnet:` links and `.torrent` files served over HTTP
+pub fn handles(url: &str) -> bool {
+    if url
+        .get(..7)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("magnet:"))
+    {
+        return true;
+    }
+    reqwest::Url::parse(url).is_ok_and(|u| {
+        matches!(u.scheme(), "http" | "https") && u.path().to_ascii_lowercase().ends_with(".torrent")
+    })
+}
+
+async fn session() -> Result<&'static Arc<Session>> {
+    SESSION
+        .get_or_try_init(|| async {
+            // Every torrent sets its own output folder; this is only a fallback
+            let dir = dirs::download_dir().unwrap_or_else(std::env::temp_dir);
+            Session::new(dir).await
+        })
+        .await
+}
+
+/// Downloads torrents into a folder named after the torrent. Pieces are
+/// hash-checked by the protocol, and a torrent is dropped from the session
+/// once complete rather than seeded.
+pub struct TorrentBackend;
+
+#[async_trait]
+impl DownloadBackend for TorrentBackend {
+    async fn download(&self, ctx: &DownloadCtx<'_>, control: ControlHandle) -> Result<()> {
+        let session = session().await?;
+        let existing = HANDLES.lock().get(ctx.id).cloned();
+        let handle = match existing {
+            Some(handle) => {
+                if handle.is_paused() {
+                    session.unpause(&handle).await?;
+                }
+                handle
+            }
+            None => {
+                let handle = add(session, ctx).await?;
+                HANDLES.lock().insert(ctx.id.to_string(), handle.clone());
+                handle
+            }
+        };
+
+        let mut info = ctx.info().await?;
+        loop {
+            let stats = handle.stats();
+            if let Some(error) = stats.error {
+                HANDLES.lock().remove(ctx.id);
+                let _ = session.delete(handle.id().into(), false).await;
+                anyhow::bail!("Torrent failed: {}", error);
+            }
+
+            info.downloaded_size = stats.progress_bytes;
+            info.total_size = Some(stats.total_bytes);
+            info.updated_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64;
+            ctx.report(&info).await?;
+
+            if stats.finished {
+                HANDLES.lock().remove(ctx.id);
+                session.delete(handle.id().into(), false).await?;
+                return Ok(());
+            }
+            if control.is_stopped() {
+                session.pause(&handle).await?;
+                return Ok(());
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Resolve the torrent's metadata, name the target folder after it and
+/// start it. Adding a torrent whose files are already there resumes it.
+async fn add(session: &Arc<Session>, ctx: &DownloadCtx<'_>) -> Result<ManagedTorrentHandle> {
+    let mut info = ctx.info().await?;
+
+    let listed = session
+        .add_torrent(
+            AddTorrent::from_url(info.url.as_str()),
+            Some(AddTorrentOptions {
+                list_only: true,
+                ..Default::default()
+            }),
+        )
+        .await
+        .context("Failed to fetch torrent metadata")?;
+    let AddTorrentResponse::ListOnly(listed) = listed else {
+        anyhow::bail!("Torrent is already being downloaded");
+    };
+
+    // A fresh download gets a folder named after the torrent; a resumed one
+    // keeps the folder it started in
+    if info.downloaded_size == 0 && info.options.file_name.is_none() {
+        if let Some(name) = listed.info.name.as_ref() {
+            let name = crate::downloader::sanitize_filename(&String::from_utf8_lossy(name));
+            if !name.is_empty() {
+                let folder = info.file_path.with_file_name(&name);
+                info.file_path = resolve_target_path(&folder, info.options.on_conflict)?;
+                info.file_name = info
+                    .file_path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or(name);
+            }
+        }
+    }
+    info.total_size = Some(listed.info.iter_file_lengths()?.sum());
+    ctx.report(&info).await?;
+
+    let added = session
+        .add_torrent(
+            AddTorrent::from_bytes(listed.torrent_bytes),
+            Some(AddTorrentOptions {
+                output_folder: Some(info.file_path.to_string_lossy().into_owned()),
+                overwrite: true,
+                ..Default::default()
+            }),
+        )
+        .await
+        .context("Failed to start torrent")?;
+    added
+        .into_handle()
+        .context("Torrent was not added to the session")
+}
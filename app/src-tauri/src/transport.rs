@@ -0,0 +1,194 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::header::{IF_RANGE, RANGE};
+use reqwest::{Client, RequestBuilder, Response};
+use std::time::Duration;
+
+/// The requests a transfer makes, separated from `reqwest` so the download
+/// logic can be driven by canned responses instead of a live server.
+/// `client` carries the per-download configuration (headers, cookies,
+/// proxy); `timeout` bounds the wait for the response head, not the body.
+#[async_trait]
+pub trait HttpTransport: Send + Sync {
+    async fn head(&self, client: &Client, url: &str, timeout: Duration) -> Result<Response>;
+
+    async fn get(&self, client: &Client, url: &str, timeout: Duration) -> Result<Response>;
+
+    /// GET bytes `start..=end`, or `start..` through the end of the file.
+    /// With `if_range` (an ETag or HTTP date) the server sends the whole
+    /// file instead if it no longer matches.
+    async fn get_range(
+        &self,
+        client: &Client,
+        url: &str,
+        start: u64,
+        end: Option<u64>,
+        if_range: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Response>;
+}
+
+/// Sends requests over the network
+pub struct ReqwestTransport;
+
+#[async_trait]
+impl HttpTransport for ReqwestTransport {
+    async fn head(&self, client: &Client, url: &str, timeout: Duration) -> Result<Response> {
+        send_with_timeout(client.head(url), timeout).await
+    }
+
+    async fn get(&self, client: &Client, url: &str, timeout: Duration) -> Result<Response> {
+        send_with_timeout(client.get(url), timeout).await
+    }
+
+    async fn get_range(
+        &self,
+        client: &Client,
+        url: &str,
+        start: u64,
+        end: Option<u64>,
+        if_range: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Response> {
+        let range = match end {
+            Some(end) => format!("bytes={}-{}", start, end),
+            None => format!("bytes={}-", start),
+        };
+        let mut request = client.get(url).header(RANGE, range);
+        if let Some(validator) = if_range {
+            request = request.header(IF_RANGE, validator);
+        }
+        send_with_timeout(request, timeout).await
+    }
+}
+
+/// Send a request, giving up if the server doesn't start responding in time
+async fn send_with_timeout(request: RequestBuilder, timeout: Duration) -> Result<Response> {
+    match tokio::time::timeout(timeout, request.send()).await {
+        Ok(response) => Ok(response?),
+        Err(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("server did not respond within {}s", timeout.as_secs()),
+        )
+        .into()),
+    }
+}
+
+/// Serves one file from memory, standing in for a server in tests
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use bytes::Bytes;
+    use parking_lot::Mutex;
+    use reqwest::header::{HeaderName, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE};
+    use reqwest::{ResponseBuilderExt, StatusCode, Url};
+
+    pub struct MockTransport {
+        file: Bytes,
+        /// Send `Accept-Ranges: bytes` with the HEAD response
+        pub advertise_ranges: bool,
+        /// Answer Range requests with 206; otherwise send the whole file
+        pub honour_ranges: bool,
+        /// End GET bodies after this many bytes, as if the connection closed
+        pub truncate_at: Option<usize>,
+        /// `HEAD`, `GET` or `GET start-end` for every request, in order
+        pub requests: Mutex<Vec<String>>,
+    }
+
+    impl MockTransport {
+        pub fn new(file: impl Into<Bytes>) -> Self {
+            Self {
+                file: file.into(),
+                advertise_ranges: true,
+                honour_ranges: true,
+                truncate_at: None,
+                requests: Mutex::new(Vec::new()),
+            }
+        }
+
+        pub fn ranged_requests(&self) -> usize {
+            self.requests
+                .lock()
+                .iter()
+                .filter(|r| r.starts_with("GET "))
+                .count()
+        }
+
+        fn respond(
+            &self,
+            url: &str,
+            status: StatusCode,
+            headers: &[(HeaderName, String)],
+            body: Bytes,
+        ) -> Result<Response> {
+            let mut builder = hyper::Response::builder()
+                .status(status)
+                .url(Url::parse(url)?);
+            for (name, value) in headers {
+                builder = builder.header(name, value);
+            }
+            // Streamed, so the body's length is only known from the headers,
+            // like on the wire
+            let (mut sender, stream) = hyper::Body::channel();
+            let body = match self.truncate_at {
+                Some(limit) if limit < body.len() => body.slice(..limit),
+                _ => body,
+            };
+            tokio::spawn(async move {
+                if !body.is_empty() {
+                    let _ = sender.send_data(body).await;
+                }
+            });
+            Ok(builder.body(stream)?.into())
+        }
+
+        fn whole_file(&self, url: &str) -> Result<Response> {
+            let length = (CONTENT_LENGTH, self.file.len().to_string());
+            self.respond(url, StatusCode::OK, &[length], self.file.clone())
+        }
+    }
+
+    #[async_trait]
+    impl HttpTransport for MockTransport {
+        async fn head(&self, _client: &Client, url: &str, _timeout: Duration) -> Result<Response> {
+            self.requests.lock().push("HEAD".to_string());
+            let mut headers = vec![(CONTENT_LENGTH, self.file.len().to_string())];
+            if self.advertise_ranges {
+                headers.push((ACCEPT_RANGES, "bytes".to_string()));
+            }
+            self.respond(url, StatusCode::OK, &headers, Bytes::new())
+        }
+
+        async fn get(&self, _client: &Client, url: &str, _timeout: Duration) -> Result<Response> {
+            self.requests.lock().push("GET".to_string());
+            self.whole_file(url)
+        }
+
+        async fn get_range(
+            &self,
+            _client: &Client,
+            url: &str,
+            start: u64,
+            end: Option<u64>,
+            _if_range: Option<&str>,
+            _timeout: Duration,
+        ) -> Result<Response> {
+            let last = self.file.len() as u64 - 1;
+            let end = end.map_or(last, |end| end.min(last));
+            self.requests.lock().push(format!("GET {}-{}", start, end));
+            if !self.honour_ranges {
+                return self.whole_file(url);
+            }
+
+            let headers = [
+                (CONTENT_LENGTH, (end - start + 1).to_string()),
+                (
+                    CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end, self.file.len()),
+                ),
+            ];
+            let body = self.file.slice(start as usize..=end as usize);
+            self.respond(url, StatusCode::PARTIAL_CONTENT, &headers, body)
+        }
+    }
+}
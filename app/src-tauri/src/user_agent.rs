@@ -0,0 +1,103 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Sent when neither the download nor the settings name a user agent
+pub const BUILTIN_USER_AGENT: &str = "GripDL/1.0";
+
+/// Variables a user agent may contain, and what they expand to
+const VARIABLES: [(&str, &str); 3] = [
+    ("{version}", env!("CARGO_PKG_VERSION")),
+    ("{os}", std::env::consts::OS),
+    ("{arch}", std::env::consts::ARCH),
+];
+
+/// User agents from the settings. Each may use `{version}` (GripDL's
+/// version), `{os}` and `{arch}`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserAgentSettings {
+    /// Replaces the built-in user agent
+    pub default: Option<String>,
+    /// Earlier overrides win
+    pub hosts: Vec<HostUserAgent>,
+}
+
+/// User agent for one host and its subdomains
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostUserAgent {
+    pub host: String,
+    pub user_agent: String,
+}
+
+impl UserAgentSettings {
+    /// Drop empty entries and reject unknown variables
+    pub fn normalize(mut self) -> Result<Self> {
+        self.default = match self.default.as_deref().map(str::trim) {
+            Some("") | None => None,
+            Some(ua) => Some(check_template(ua)?.to_string()),
+        };
+        let mut hosts = Vec::new();
+        for mut rule in self.hosts {
+            rule.host = rule.host.trim().trim_end_matches('.').to_ascii_lowercase();
+            rule.user_agent = rule.user_agent.trim().to_string();
+            if rule.host.is_empty() {
+                anyhow::bail!("User agent override without a host");
+            }
+            if rule.user_agent.is_empty() {
+                continue;
+            }
+            check_template(&rule.user_agent)?;
+            hosts.push(rule);
+        }
+        self.hosts = hosts;
+        Ok(self)
+    }
+
+    /// The user agent to send to `url`: the download's own, else the
+    /// first override for its host, else the default, else the built-in one
+    pub fn resolve(&self, per_download: Option<&str>, url: &str) -> String {
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_ascii_lowercase));
+        let for_host = host.and_then(|host| {
+            self.hosts
+                .iter()
+                .find(|rule| matches_host(&rule.host, &host))
+                .map(|rule| rule.user_agent.as_str())
+        });
+        let template = per_download
+            .filter(|ua| !ua.trim().is_empty())
+            .or(for_host)
+            .or(self.default.as_deref())
+            .unwrap_or(BUILTIN_USER_AGENT);
+        expand(template)
+    }
+}
+
+fn matches_host(rule: &str, host: &str) -> bool {
+    host == rule
+        || host
+            .strip_suffix(rule)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+fn expand(template: &str) -> String {
+    VARIABLES
+        .iter()
+        .fold(template.to_string(), |ua, (name, value)| ua.replace(name, value))
+}
+
+fn check_template(template: &str) -> Result<&str> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let variable = &rest[start..start + len + 1];
+        if !VARIABLES.iter().any(|(name, _)| *name == variable) {
+            anyhow::bail!("Unknown variable {} in user agent {:?}", variable, template);
+        }
+        rest = &rest[start + len + 1..];
+    }
+    Ok(template)
+}
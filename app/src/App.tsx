@@ -4,6 +4,14 @@ import { invoke } from "@tauri-apps/api/core";
 import DownloadList from "./components/DownloadList";
 import DownloadItem from "./components/DownloadItem";
 
+interface DownloadFailure {
+  kind: string;
+  message?: string;
+  status?: number;
+  expected?: string;
+  actual?: string;
+}
+
 interface DownloadInfo {
   id: string;
   url: string;
@@ -11,10 +19,11 @@ interface DownloadInfo {
   file_name: string;
   total_size: number | null;
   downloaded_size: number;
-  status: "Pending" | "Downloading" | "Paused" | "Completed" | { Failed: string } | "Cancelled";
+  status: "Pending" | "Scheduled" | "Queued" | "Downloading" | "Verifying" | "Paused" | "Completed" | { Failed: DownloadFailure } | "Cancelled";
   cookies: string | null;
   referrer: string | null;
   user_agent: string | null;
+  queue_position?: number | null;
   created_at: number;
   updated_at: number;
 }
@@ -39,46 +48,34 @@ function App() {
       });
     });
 
-    // Listen for native download requests from extension
-    const unlistenNative = listen<any>("native-download-request", async (event) => {
-      const { url, cookies, referrer, user_agent } = event.payload;
-      await startDownload(url, cookies, referrer, user_agent);
-    });
+    // Queued downloads move up as the ones ahead of them start
+    const unlistenQueue = listen<{ id: string; queue_position: number }[]>(
+      "queue-positions",
+      (event) => {
+        const positions = new Map(event.payload.map((p) => [p.id, p.queue_position]));
+        setDownloads((prev) =>
+          prev.map((d) => (positions.has(d.id) ? { ...d, queue_position: positions.get(d.id) } : d))
+        );
+      }
+    );
 
     return () => {
       unlisten.then((fn) => fn());
-      unlistenNative.then((fn) => fn());
+      unlistenQueue.then((fn) => fn());
     };
   }, []);
 
   const loadDownloads = async () => {
     try {
-      const result = await invoke<DownloadInfo[]>("get_downloads");
-      setDownloads(result);
+      const result = await invoke<{ downloads: DownloadInfo[]; total: number }>(
+        "get_downloads"
+      );
+      setDownloads(result.downloads);
     } catch (error) {
       console.error("Failed to load downloads:", error);
     }
   };
 
-  const startDownload = async (
-    url: string,
-    cookies?: string,
-    referrer?: string,
-    userAgent?: string
-  ) => {
-    try {
-      await invoke("start_download", {
-        url,
-        cookies: cookies || null,
-        referrer: referrer || null,
-        userAgent: userAgent || null,
-      });
-      await loadDownloads();
-    } catch (error) {
-      console.error("Failed to start download:", error);
-    }
-  };
-
   const pauseDownload = async (id: string) => {
     try {
       await invoke("pause_download", { id });
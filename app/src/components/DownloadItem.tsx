@@ -1,4 +1,12 @@
-import { Pause, Play, X, CheckCircle2, AlertCircle } from "lucide-react";
+import { Pause, Play, X, CheckCircle2, AlertCircle, Loader2 } from "lucide-react";
+
+interface DownloadFailure {
+  kind: string;
+  message?: string;
+  status?: number;
+  expected?: string;
+  actual?: string;
+}
 
 interface DownloadInfo {
   id: string;
@@ -7,10 +15,12 @@ interface DownloadInfo {
   file_name: string;
   total_size: number | null;
   downloaded_size: number;
-  status: "Pending" | "Downloading" | "Paused" | "Completed" | { Failed: string } | "Cancelled";
+  status: "Pending" | "Scheduled" | "Queued" | "Downloading" | "Verifying" | "Paused" | "Completed" | { Failed: DownloadFailure } | "Cancelled";
   cookies: string | null;
   referrer: string | null;
   user_agent: string | null;
+  progress?: number | null;
+  queue_position?: number | null;
   created_at: number;
   updated_at: number;
 }
@@ -34,15 +44,26 @@ function getStatusIcon(status: DownloadInfo["status"]) {
   if (status === "Completed") {
     return <CheckCircle2 className="w-5 h-5 text-green-500" />;
   }
+  if (status === "Verifying") {
+    return <Loader2 className="w-5 h-5 text-muted-foreground animate-spin" />;
+  }
   if (typeof status === "object" && "Failed" in status) {
     return <AlertCircle className="w-5 h-5 text-red-500" />;
   }
   return null;
 }
 
-function getStatusText(status: DownloadInfo["status"]): string {
+function getStatusText(download: DownloadInfo): string {
+  const status = download.status;
   if (typeof status === "object" && "Failed" in status) {
-    return `Failed: ${status.Failed}`;
+    const failure = status.Failed;
+    return `Failed: ${failure.message ?? `checksum mismatch: expected ${failure.expected} got ${failure.actual}`}`;
+  }
+  if (status === "Verifying") {
+    return "Verifying…";
+  }
+  if (status === "Queued" && download.queue_position != null) {
+    return `Queued (#${download.queue_position})`;
   }
   return status;
 }
@@ -53,12 +74,10 @@ export default function DownloadItem({
   onResume,
   onCancel,
 }: DownloadItemProps) {
-  const progress =
-    download.total_size && download.total_size > 0
-      ? (download.downloaded_size / download.total_size) * 100
-      : 0;
+  const progress = download.progress != null ? download.progress * 100 : null;
 
-  const isActive = download.status === "Downloading" || download.status === "Pending";
+  const isActive =
+    download.status === "Downloading" || download.status === "Pending" || download.status === "Queued";
   const isPaused = download.status === "Paused";
   const isCompleted = download.status === "Completed";
   const isFailed = typeof download.status === "object" && "Failed" in download.status;
@@ -106,18 +125,27 @@ export default function DownloadItem({
 
       <div className="mt-3">
         <div className="flex justify-between text-sm text-muted-foreground mb-1">
-          <span>{getStatusText(download.status)}</span>
+          <span
+            className="truncate"
+            title={isFailed ? getStatusText(download) : undefined}
+          >
+            {getStatusText(download)}
+          </span>
           <span>
             {formatBytes(download.downloaded_size)}
             {download.total_size && ` / ${formatBytes(download.total_size)}`}
           </span>
         </div>
-        {isActive && download.total_size && (
+        {isActive && (
           <div className="w-full bg-muted rounded-full h-2 overflow-hidden">
-            <div
-              className="bg-primary h-2 rounded-full transition-all duration-300"
-              style={{ width: `${progress}%` }}
-            />
+            {progress != null ? (
+              <div
+                className="bg-primary h-2 rounded-full transition-all duration-300"
+                style={{ width: `${progress}%` }}
+              />
+            ) : (
+              <div className="bg-primary h-2 rounded-full w-full animate-pulse" />
+            )}
           </div>
         )}
       </div>
@@ -1,5 +1,13 @@
 import DownloadItem from "./DownloadItem";
 
+interface DownloadFailure {
+  kind: string;
+  message?: string;
+  status?: number;
+  expected?: string;
+  actual?: string;
+}
+
 interface DownloadInfo {
   id: string;
   url: string;
@@ -7,7 +15,7 @@ interface DownloadInfo {
   file_name: string;
   total_size: number | null;
   downloaded_size: number;
-  status: "Pending" | "Downloading" | "Paused" | "Completed" | { Failed: string } | "Cancelled";
+  status: "Pending" | "Scheduled" | "Queued" | "Downloading" | "Verifying" | "Paused" | "Completed" | { Failed: DownloadFailure } | "Cancelled";
   cookies: string | null;
   referrer: string | null;
   user_agent: string | null;
@@ -1,13 +1,27 @@
 // Native messaging host name - must match the manifest registration
 const NATIVE_HOST = "com.gripdl.app";
 
+// Either a single `url` or a batch of `urls` sharing the same cookies etc.
 interface DownloadMessage {
-  url: string;
+  url?: string;
+  urls?: string[];
   cookies?: string;
+  cookies_structured?: CookieSpec[];
   referrer?: string;
   user_agent?: string;
 }
 
+// A cookie with its scope, so it still applies after a redirect to
+// another subdomain
+interface CookieSpec {
+  name: string;
+  value: string;
+  domain?: string;
+  path?: string;
+  secure: boolean;
+  http_only: boolean;
+}
+
 // Intercept downloads
 browser.downloads.onCreated.addListener(async (downloadItem) => {
   try {
@@ -17,6 +31,14 @@ browser.downloads.onCreated.addListener(async (downloadItem) => {
     // Get cookies for the download URL
     const cookies = await browser.cookies.getAll({ url: downloadItem.url });
     const cookieString = cookies.map((c) => `${c.name}=${c.value}`).join("; ");
+    const cookieSpecs: CookieSpec[] = cookies.map((c) => ({
+      name: c.name,
+      value: c.value,
+      domain: c.hostOnly ? undefined : c.domain,
+      path: c.path,
+      secure: c.secure,
+      http_only: c.httpOnly,
+    }));
 
     // Get referrer from download item
     const referrer = downloadItem.referrer || undefined;
@@ -28,6 +50,7 @@ browser.downloads.onCreated.addListener(async (downloadItem) => {
     const message: DownloadMessage = {
       url: downloadItem.url,
       cookies: cookieString || undefined,
+      cookies_structured: cookieSpecs.length > 0 ? cookieSpecs : undefined,
       referrer: referrer,
       user_agent: userAgent,
     };